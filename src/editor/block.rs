@@ -0,0 +1,191 @@
+use std::ops::Range;
+
+use crate::editor::document::Document;
+use crate::editor::util::{self, GapBuffer};
+
+// Rectangular selections. A block covers the screen columns left..right of rows
+// top..=bottom; with left == right it is just a column to type into.
+// Characters are in the block if any part of them is, so tabs and wide characters
+// are never cut in half.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Rect {
+    pub top: usize,
+    pub bottom: usize,
+    pub left: usize,
+    pub right: usize,
+}
+
+impl Rect {
+    // The block between two (row, screen column) corners
+    pub fn between(a: (usize, usize), b: (usize, usize)) -> Rect {
+        Rect {
+            top: a.0.min(b.0),
+            bottom: a.0.max(b.0),
+            left: a.1.min(b.1),
+            right: a.1.max(b.1),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.left == self.right
+    }
+
+    pub fn rows(&self) -> Range<usize> {
+        self.top..self.bottom + 1
+    }
+}
+
+// Characters of a row in the screen columns left..right, or where a character typed at
+// column `left` goes when the range is empty.
+pub fn char_range(row: &GapBuffer, left: usize, right: usize, tab_width: usize) -> Range<usize> {
+    let mut x = 0;
+    let mut start = None;
+    let mut end = 0;
+    for (idx, c) in row.to_string().chars().enumerate() {
+        let next = x + util::char_width(c, x, tab_width);
+        if start.is_none() && (next > left && x < right || x >= left) {
+            start = Some(idx);
+        }
+        // A column has nothing in it, even inside a tab
+        if x < right && next > left && left < right {
+            end = idx + 1;
+        }
+        x = next;
+    }
+    let start = start.unwrap_or_else(|| row.str_len());
+    start..end.max(start)
+}
+
+// Pads a row with spaces so it reaches screen column `column`, and returns where that column is.
+fn reach(document: &mut Document, row: usize, column: usize, tab_width: usize) -> usize {
+    let buffer = &document.rows[row];
+    let len = buffer.str_len();
+    let width = buffer.visual_width(0, len, tab_width);
+    if width < column {
+        document.insert_str(row, len, &" ".repeat(column - width));
+    }
+    char_range(&document.rows[row], column, column, tab_width).start
+}
+
+pub fn copy(document: &Document, rect: &Rect, tab_width: usize) -> Vec<String> {
+    rect.rows()
+        .filter_map(|row| document.row(row))
+        .map(|buffer| {
+            let range = char_range(buffer, rect.left, rect.right, tab_width);
+            buffer.to_string().chars().skip(range.start).take(range.len()).collect()
+        })
+        .collect()
+}
+
+pub fn delete(document: &mut Document, rect: &Rect, tab_width: usize) {
+    for row in rect.top..rect.rows().end.min(document.rows()) {
+        let range = char_range(&document.rows[row], rect.left, rect.right, tab_width);
+        document.remove_chars(row, range);
+    }
+}
+
+// Types `text` at column `column` of every row of the block, padding short rows.
+pub fn insert(document: &mut Document, rows: Range<usize>, column: usize, text: &str, tab_width: usize) {
+    for row in rows.start..rows.end.min(document.rows()) {
+        let col = reach(document, row, column, tab_width);
+        document.insert_str(row, col, text);
+    }
+}
+
+// Puts a copied block with its top left corner at `row`, `column`, adding rows at the end
+// of the document when it runs past it. Lines are padded to keep later columns aligned,
+// except at the end of a row.
+pub fn paste(document: &mut Document, row: usize, column: usize, lines: &[String], tab_width: usize) {
+    let width = lines.iter().map(|line| line.chars().map(|c| util::char_width(c, 0, tab_width)).sum::<usize>()).max().unwrap_or(0);
+    for (offset, line) in lines.iter().enumerate() {
+        let row = row + offset;
+        if row >= document.rows() {
+            let last = document.rows() - 1;
+            let len = document.rows[last].str_len();
+            document.new_line(last, len);
+        }
+        // Nothing would go past the end of the row but padding
+        let buffer = &document.rows[row];
+        if line.is_empty() && buffer.visual_width(0, buffer.str_len(), tab_width) <= column {
+            continue;
+        }
+        let col = reach(document, row, column, tab_width);
+        let at_end = col >= document.rows[row].str_len();
+        let line_width: usize = line.chars().map(|c| util::char_width(c, 0, tab_width)).sum();
+        let padding = if at_end { 0 } else { width - line_width };
+        document.insert_str(row, col, &format!("{}{}", line, " ".repeat(padding)));
+    }
+}
+
+// Puts `open` before and `close` after the part of every row inside the block.
+pub fn surround(document: &mut Document, rect: &Rect, open: char, close: char, tab_width: usize) {
+    for row in rect.top..rect.rows().end.min(document.rows()) {
+        let range = char_range(&document.rows[row], rect.left, rect.right, tab_width);
+        if range.is_empty() {
+            continue;
+        }
+        document.insert_char(row, range.end, close);
+        document.insert_char(row, range.start, open);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(line: &str, left: usize, right: usize) -> Range<usize> {
+        char_range(&GapBuffer::from_str(line), left, right, 4)
+    }
+
+    fn lines(document: &Document) -> Vec<String> {
+        (0..document.rows()).map(|row| document.rows[row].to_string()).collect()
+    }
+
+    #[test]
+    fn takes_whole_tabs_and_wide_characters() {
+        // a, then a tab to column 4, then b
+        assert_eq!(range("a\tb", 0, 2), 0..2);
+        assert_eq!(range("a\tb", 2, 3), 1..2);
+        assert_eq!(range("a\tb", 4, 5), 2..3);
+        // 漢 takes columns 1 and 2
+        assert_eq!(range("a漢b", 2, 3), 1..2);
+        assert_eq!(range("a漢b", 1, 4), 1..3);
+        assert_eq!(range("a漢b", 3, 9), 2..3);
+    }
+
+    #[test]
+    fn a_column_is_where_typing_goes() {
+        assert_eq!(range("a\tb", 1, 1), 1..1);
+        assert_eq!(range("a\tb", 4, 4), 2..2);
+        // Inside a tab or wide character, typing goes before it
+        assert_eq!(range("a\tb", 2, 2), 1..1);
+        assert_eq!(range("a漢b", 2, 2), 1..1);
+        // Past the end of a short row, it goes at the end
+        assert_eq!(range("ab", 5, 5), 2..2);
+        assert_eq!(range("ab", 5, 7), 2..2);
+    }
+
+    #[test]
+    fn pastes_aligned_into_ragged_rows() {
+        let mut document = Document::from_text("test.txt", "abcd\na\nabcd\n");
+        paste(&mut document, 0, 2, &[String::from("XY"), String::from("Z"), String::new(), String::from("W")], 4);
+        // Short rows are padded to the column and not past the block; rows run past the end are added
+        assert_eq!(lines(&document), vec!["abXYcd", "a Z", "ab  cd", "  W"]);
+    }
+
+    #[test]
+    fn pastes_wide_characters_and_tabs() {
+        let mut document = Document::from_text("test.txt", "ab\tc\nabcdef\n");
+        paste(&mut document, 0, 1, &[String::from("漢"), String::from("x")], 4);
+        assert_eq!(lines(&document), vec!["a漢b\tc", "ax bcdef"]);
+    }
+
+    #[test]
+    fn surrounds_what_is_inside_the_block() {
+        let mut document = Document::from_text("test.txt", "abcd\na\n\tx\na漢b\n");
+        surround(&mut document, &Rect { top: 0, bottom: 3, left: 1, right: 3 }, '(', ')', 4);
+        // The short row has nothing inside, and the tab and wide character are taken whole
+        assert_eq!(lines(&document), vec!["a(bc)d", "a", "(\t)x", "a(漢)b"]);
+    }
+}
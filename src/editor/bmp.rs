@@ -0,0 +1,201 @@
+// Windows bitmaps, decoded to be shown in the terminal. Only uncompressed 24 and 32 bit
+// images are read, with the info header of Windows 3 or the longer V4 and V5 ones.
+
+const FILE_HEADER: usize = 14;
+const INFO_HEADER: usize = 40;
+// Compression values: none, and 32 bit pixels with channel masks
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+// Pixel limit, so a bad header cannot ask for gigabytes
+const MAX_PIXELS: usize = 1 << 26;
+
+pub type Rgb = (u8, u8, u8);
+
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    // Rows from the top
+    pixels: Vec<Rgb>,
+}
+
+fn u16_at(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn u32_at(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+// Reads a channel out of a 32 bit pixel with its mask, scaled to 0..=255
+fn channel(pixel: u32, mask: u32) -> u8 {
+    if mask == 0 {
+        return 0;
+    }
+    // Wide masks would overflow 32 bits when scaled
+    let value = ((pixel & mask) >> mask.trailing_zeros()) as u64;
+    let max = (mask >> mask.trailing_zeros()) as u64;
+    (value * 255 / max) as u8
+}
+
+pub fn is_bmp(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"BM")
+}
+
+pub fn decode(bytes: &[u8]) -> Result<Image, String> {
+    let short = || String::from("the header is cut short");
+    if !is_bmp(bytes) {
+        return Err(String::from("not a BMP file"));
+    }
+    let data = u32_at(bytes, 10).ok_or_else(short)? as usize;
+    let header = u32_at(bytes, FILE_HEADER).ok_or_else(short)? as usize;
+    if header < INFO_HEADER {
+        return Err(format!("{} byte headers are not supported", header));
+    }
+    let width = u32_at(bytes, 18).ok_or_else(short)? as i32;
+    let height = u32_at(bytes, 22).ok_or_else(short)? as i32;
+    let bits = u16_at(bytes, 28).ok_or_else(short)?;
+    let compression = u32_at(bytes, 30).ok_or_else(short)?;
+    // Rows are stored from the bottom unless the height is negative
+    let top_down = height < 0;
+    let (width, height) = (width.unsigned_abs() as usize, height.unsigned_abs() as usize);
+    if width == 0 || height == 0 || width.saturating_mul(height) > MAX_PIXELS {
+        return Err(format!("{}x{} images are not supported", width, height));
+    }
+    // The masks come right after the Windows 3 header, and are part of the longer ones
+    let masks = match (bits, compression) {
+        (24, BI_RGB) => None,
+        (32, BI_RGB) => Some((0xFF_0000, 0xFF00, 0xFF)),
+        (32, BI_BITFIELDS) => {
+            let mask = |at| u32_at(bytes, FILE_HEADER + INFO_HEADER + at).ok_or_else(short);
+            Some((mask(0)?, mask(4)?, mask(8)?))
+        },
+        (24 | 32, _) => return Err(String::from("compressed images are not supported")),
+        _ => return Err(format!("{} bit images are not supported", bits)),
+    };
+
+    let stride = (width * bits as usize).div_ceil(32) * 4;
+    let size = bits as usize / 8;
+    let end = data.saturating_add(stride * height);
+    let rows = bytes.get(data..end).ok_or("the pixel data is cut short")?;
+    let mut pixels = Vec::with_capacity(width * height);
+    for y in 0..height {
+        let stored = if top_down { y } else { height - 1 - y };
+        let row = &rows[stored * stride..stored * stride + width * size];
+        pixels.extend(row.chunks_exact(size).map(|pixel| match masks {
+            None => (pixel[2], pixel[1], pixel[0]),
+            Some((red, green, blue)) => {
+                let value = u32::from_le_bytes([pixel[0], pixel[1], pixel[2], pixel[3]]);
+                (channel(value, red), channel(value, green), channel(value, blue))
+            },
+        }));
+    }
+    Ok(Image { width, height, pixels })
+}
+
+impl Image {
+    // The average color of the pixels in columns x0..x1 of rows y0..y1
+    fn average(&self, x0: usize, x1: usize, y0: usize, y1: usize) -> Rgb {
+        let mut sum = (0, 0, 0);
+        for y in y0..y1 {
+            for &(r, g, b) in &self.pixels[y * self.width + x0..y * self.width + x1] {
+                sum = (sum.0 + r as usize, sum.1 + g as usize, sum.2 + b as usize);
+            }
+        }
+        let count = (x1 - x0) * (y1 - y0);
+        ((sum.0 / count) as u8, (sum.1 / count) as u8, (sum.2 / count) as u8)
+    }
+
+    // The image scaled to fit `columns` by `rows` cells, as the colors of the top and
+    // bottom half of every cell. Cells are taken to be twice as tall as they are wide.
+    pub fn cells(&self, columns: usize, rows: usize) -> Vec<Vec<(Rgb, Option<Rgb>)>> {
+        let scale = (columns as f64 / self.width as f64).min(2.0 * rows as f64 / self.height as f64);
+        let width = ((self.width as f64 * scale) as usize).clamp(1, columns.max(1));
+        let height = ((self.height as f64 * scale) as usize).clamp(1, 2 * rows.max(1));
+        // Each scaled pixel averages the pixels it covers, at least one of them
+        let span = |idx: usize, scaled: usize, full: usize| {
+            let start = idx * full / scaled;
+            start..((idx + 1) * full / scaled).max(start + 1)
+        };
+        let pixel = |x: usize, y: usize| {
+            let (xs, ys) = (span(x, width, self.width), span(y, height, self.height));
+            self.average(xs.start, xs.end, ys.start, ys.end)
+        };
+        (0..height.div_ceil(2))
+            .map(|row| (0..width).map(|x| {
+                // An odd last row has only a top half
+                (pixel(x, 2 * row), (2 * row + 1 < height).then(|| pixel(x, 2 * row + 1)))
+            }).collect())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A bitmap with a Windows 3 header, the masks after it and then the pixel rows
+    fn bitmap(width: i32, height: i32, bits: u16, compression: u32, masks: &[u32], rows: &[u8]) -> Vec<u8> {
+        let data = FILE_HEADER + INFO_HEADER + 4 * masks.len();
+        let mut bytes = b"BM".to_vec();
+        for field in [(data + rows.len()) as u32, 0, data as u32, INFO_HEADER as u32] {
+            bytes.extend(field.to_le_bytes());
+        }
+        bytes.extend(width.to_le_bytes());
+        bytes.extend(height.to_le_bytes());
+        bytes.extend(1u16.to_le_bytes());
+        bytes.extend(bits.to_le_bytes());
+        bytes.extend(compression.to_le_bytes());
+        bytes.extend([0; 20]);
+        for mask in masks {
+            bytes.extend(mask.to_le_bytes());
+        }
+        bytes.extend(rows);
+        bytes
+    }
+
+    // Two rows of two blue, green, red pixels, padded to four bytes
+    const ROWS: [u8; 16] = [0, 0, 255, 0, 255, 0, 0, 0, 255, 0, 0, 255, 255, 255, 0, 0];
+
+    #[test]
+    fn reads_rows_from_the_bottom_unless_the_height_is_negative() {
+        let image = decode(&bitmap(2, 2, 24, BI_RGB, &[], &ROWS)).unwrap();
+        assert_eq!((image.width, image.height), (2, 2));
+        assert_eq!(image.pixels, [(0, 0, 255), (255, 255, 255), (255, 0, 0), (0, 255, 0)]);
+        let image = decode(&bitmap(2, -2, 24, BI_RGB, &[], &ROWS)).unwrap();
+        assert_eq!(image.pixels, [(255, 0, 0), (0, 255, 0), (0, 0, 255), (255, 255, 255)]);
+    }
+
+    #[test]
+    fn scales_channels_by_their_masks() {
+        // Ten bits a channel, and the alpha in the top two
+        let pixel: u32 = (1023 << 20) | (512 << 10) | 3 << 30;
+        let image = decode(&bitmap(1, 1, 32, BI_BITFIELDS, &[0x3FF0_0000, 0xF_FC00, 0x3FF], &pixel.to_le_bytes())).unwrap();
+        assert_eq!(image.pixels, [(255, 127, 0)]);
+        let image = decode(&bitmap(1, 1, 32, BI_RGB, &[], &[1, 2, 3, 4])).unwrap();
+        assert_eq!(image.pixels, [(3, 2, 1)]);
+        assert_eq!(channel(u32::MAX, u32::MAX), 255);
+        assert_eq!(channel(u32::MAX, 0), 0);
+    }
+
+    #[test]
+    fn refuses_what_it_cannot_show() {
+        let error = |bytes: &[u8]| decode(bytes).err().unwrap();
+        assert_eq!(error(b"GIF89a"), "not a BMP file");
+        assert_eq!(error(b"BM\0\0"), "the header is cut short");
+        assert_eq!(error(&bitmap(2, 2, 8, BI_RGB, &[], &ROWS)), "8 bit images are not supported");
+        assert_eq!(error(&bitmap(2, 2, 24, 1, &[], &ROWS)), "compressed images are not supported");
+        assert_eq!(error(&bitmap(0, 2, 24, BI_RGB, &[], &ROWS)), "0x2 images are not supported");
+        assert_eq!(error(&bitmap(2, 2, 24, BI_RGB, &[], &ROWS[..12])), "the pixel data is cut short");
+    }
+
+    #[test]
+    fn fits_images_into_cells() {
+        let image = decode(&bitmap(2, 2, 24, BI_RGB, &[], &ROWS)).unwrap();
+        assert_eq!(image.cells(2, 1), [[((0, 0, 255), Some((255, 0, 0))), ((255, 255, 255), Some((0, 255, 0)))]]);
+        assert_eq!(image.cells(4, 4)[1][3], ((0, 255, 0), Some((0, 255, 0))));
+        // Shrunk to a pixel, which averages them all and is an odd row without a bottom half
+        assert_eq!(image.cells(1, 1), [[((127, 127, 127), None)]]);
+        let tall = decode(&bitmap(1, 3, 24, BI_RGB, &[], &[1, 1, 1, 0, 2, 2, 2, 0, 3, 3, 3, 0])).unwrap();
+        assert_eq!(tall.cells(1, 2), [[((3, 3, 3), Some((2, 2, 2)))], [((1, 1, 1), None)]]);
+    }
+}
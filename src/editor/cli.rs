@@ -0,0 +1,128 @@
+use std::path::Path;
+
+// Command line arguments:
+//   deft-txt [-R] [+LINE] [FILE[:LINE[:COL]]]
+//   deft-txt [-f] [+LINE] [-]
+// -R opens the file read-only. A FILE of - reads standard input, as does no FILE when
+// standard input is not a terminal, and -f keeps adding what arrives there like tail -f.
+
+pub const USAGE: &str = "usage: deft-txt [-R] [+LINE] [FILE[:LINE[:COL]]]\n       deft-txt [-f] [+LINE] [-]";
+
+#[derive(Default)]
+pub struct Args {
+    pub path: Option<String>,
+    // Where to put the cursor, 1-based like everything the user types
+    pub position: Option<Position>,
+    pub read_only: bool,
+    // The text comes from standard input, as - or a pipe with no FILE asks
+    pub stdin: bool,
+    pub follow: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Position {
+    Line(usize, Option<usize>),
+    // A percentage of the way through the document
+    Percent(usize),
+    LastLine,
+}
+
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Args, String> {
+    let mut parsed = Args::default();
+    for arg in args {
+        if let Some(line) = arg.strip_prefix('+') {
+            parsed.position = Some(match line {
+                "" => Position::LastLine,
+                _ => parse_position(line).ok_or_else(|| format!("invalid line `{}`", arg))?,
+            });
+        } else if arg == "-R" {
+            parsed.read_only = true;
+        } else if arg == "-f" {
+            parsed.follow = true;
+        } else if arg == "-" && parsed.path.is_none() && !parsed.stdin {
+            parsed.stdin = true;
+        } else if arg.starts_with('-') {
+            return Err(format!("unknown option `{}`", arg));
+        } else if parsed.path.is_some() || parsed.stdin {
+            return Err(String::from("only one file can be opened"));
+        } else {
+            parsed.path = Some(split_position(&arg, &mut parsed.position));
+        }
+    }
+    if parsed.follow && parsed.path.is_some() {
+        return Err(String::from("-f only follows standard input"));
+    }
+    // Text piped in is read like a pager does when no file is given
+    parsed.stdin |= parsed.path.is_none() && unsafe { libc::isatty(libc::STDIN_FILENO) } == 0;
+    if parsed.follow && !parsed.stdin {
+        return Err(String::from("-f requires piped input or -"));
+    }
+    Ok(parsed)
+}
+
+// Takes a trailing :LINE or :LINE:COL off a file name, unless a file by the full name exists.
+fn split_position(arg: &str, position: &mut Option<Position>) -> String {
+    if Path::new(arg).exists() {
+        return String::from(arg);
+    }
+    let mut parts = arg.splitn(3, ':');
+    let path = parts.next().unwrap_or(arg);
+    let rest: Vec<&str> = parts.collect();
+    match parse_position(&rest.join(":")) {
+        Some(found) if !path.is_empty() => {
+            *position = Some(found);
+            String::from(path)
+        },
+        _ => String::from(arg),
+    }
+}
+
+// "LINE", "LINE:COL" or "PERCENT%"
+pub fn parse_position(text: &str) -> Option<Position> {
+    if let Some(percent) = text.strip_suffix('%') {
+        return percent.parse().ok().filter(|p| *p <= 100).map(Position::Percent);
+    }
+    let (line, col) = match text.split_once(':') {
+        Some((line, col)) => (line, Some(col.parse().ok()?)),
+        None => (text, None),
+    };
+    Some(Position::Line(line.parse().ok()?, col))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(list: &[&str]) -> Result<Args, String> {
+        parse(list.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn parses_positions() {
+        assert_eq!(parse_position("12"), Some(Position::Line(12, None)));
+        assert_eq!(parse_position("12:3"), Some(Position::Line(12, Some(3))));
+        assert_eq!(parse_position("50%"), Some(Position::Percent(50)));
+        assert_eq!(parse_position("101%"), None);
+        assert_eq!(parse_position("12:"), None);
+        assert_eq!(parse_position("x"), None);
+    }
+
+    #[test]
+    fn takes_positions_off_file_names() {
+        let parsed = args(&["-R", "no/such/file.txt:7:2"]).unwrap();
+        assert_eq!(parsed.path.as_deref(), Some("no/such/file.txt"));
+        assert_eq!(parsed.position, Some(Position::Line(7, Some(2))));
+        assert!(parsed.read_only);
+        let parsed = args(&["+", "no/such/file.txt:x"]).unwrap();
+        assert_eq!(parsed.path.as_deref(), Some("no/such/file.txt:x"));
+        assert_eq!(parsed.position, Some(Position::LastLine));
+    }
+
+    #[test]
+    fn rejects_what_it_cannot_do() {
+        assert_eq!(args(&["-x"]).err(), Some(String::from("unknown option `-x`")));
+        assert_eq!(args(&["a", "b"]).err(), Some(String::from("only one file can be opened")));
+        assert_eq!(args(&["+1x", "a"]).err(), Some(String::from("invalid line `+1x`")));
+        assert_eq!(args(&["-f", "a"]).err(), Some(String::from("-f only follows standard input")));
+    }
+}
@@ -0,0 +1,515 @@
+use std::{env, fmt, fs};
+use std::path::PathBuf;
+
+use crate::editor::theme;
+
+// A small TOML subset: [section] headers, key = value pairs and # comments.
+// Values may be strings, integers, floats or booleans.
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Str(String),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+}
+
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Value::Str(s) => write!(f, "\"{}\"", s),
+            Value::Int(i) => write!(f, "{}", i),
+            Value::Float(x) => write!(f, "{}", x),
+            Value::Bool(b) => write!(f, "{}", b),
+        }
+    }
+}
+
+pub struct Entry {
+    pub section: String,
+    pub key: String,
+    pub value: Value,
+    pub line: usize,
+}
+
+pub struct ConfigFile {
+    pub path: String,
+    pub entries: Vec<Entry>,
+    pub errors: Vec<String>,
+}
+
+impl ConfigFile {
+    pub fn empty() -> ConfigFile {
+        ConfigFile {
+            path: String::new(),
+            entries: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+
+    // Loads the user config file, or an empty config if there is none.
+    pub fn load() -> ConfigFile {
+        let path = match config_path() {
+            Some(path) => path,
+            None => return ConfigFile::empty(),
+        };
+        match fs::read_to_string(&path) {
+            Ok(text) => ConfigFile::parse(&path.to_string_lossy(), &text),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => ConfigFile::empty(),
+            Err(err) => {
+                let mut config = ConfigFile::empty();
+                config.errors.push(format!("{}: {}", path.display(), err));
+                config
+            }
+        }
+    }
+
+    pub fn parse(path: &str, text: &str) -> ConfigFile {
+        let mut config = ConfigFile {
+            path: String::from(path),
+            entries: Vec::new(),
+            errors: Vec::new(),
+        };
+        let mut section = String::new();
+
+        for (idx, raw) in text.lines().enumerate() {
+            let line = idx + 1;
+            let trimmed = strip_comment(raw).trim();
+            if trimmed.is_empty() {
+                continue;
+            }
+
+            if let Some(header) = trimmed.strip_prefix('[') {
+                match header.strip_suffix(']') {
+                    Some(name) => section = String::from(name.trim()),
+                    None => config.error(line, "unterminated section header"),
+                }
+                continue;
+            }
+
+            let (key, value) = match split_key_value(trimmed) {
+                Some(pair) => pair,
+                None => {
+                    config.error(line, "expected `key = value`");
+                    continue;
+                }
+            };
+            let key = match parse_key(key) {
+                Ok(key) => key,
+                Err(msg) => {
+                    config.error(line, &msg);
+                    continue;
+                }
+            };
+            match parse_value(value) {
+                Ok(value) => config.entries.push(Entry {
+                    section: section.clone(),
+                    key,
+                    value,
+                    line,
+                }),
+                Err(msg) => config.error(line, &msg),
+            }
+        }
+        config
+    }
+
+    pub fn section<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Entry> + 'a {
+        self.entries.iter().filter(move |entry| entry.section == name)
+    }
+
+    // Formats an error message pointing at the line an entry came from.
+    pub fn location(&self, line: usize) -> String {
+        let name = self.path.rsplit('/').next().unwrap_or(&self.path);
+        format!("{}:{}", name, line)
+    }
+
+    fn error(&mut self, line: usize, msg: &str) {
+        let text = format!("{}: {}", self.location(line), msg);
+        self.errors.push(text);
+    }
+}
+
+// $XDG_CONFIG_HOME/deft-txt, falling back to ~/.config/deft-txt.
+pub fn config_dir() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_CONFIG_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".config"),
+    };
+    Some(base.join("deft-txt"))
+}
+
+pub fn config_path() -> Option<PathBuf> {
+    Some(config_dir()?.join("config.toml"))
+}
+
+fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    let mut escaped = false;
+    for (idx, c) in line.char_indices() {
+        match quote {
+            Some(q) => {
+                if escaped {
+                    escaped = false;
+                } else if c == '\\' && q == '"' {
+                    escaped = true;
+                } else if c == q {
+                    quote = None;
+                }
+            }
+            None => match c {
+                '"' | '\'' => quote = Some(c),
+                '#' => return &line[..idx],
+                _ => (),
+            },
+        }
+    }
+    line
+}
+
+fn split_key_value(line: &str) -> Option<(&str, &str)> {
+    // The key may be quoted and contain '=' itself
+    let split = if line.starts_with('"') || line.starts_with('\'') {
+        let quote = line.chars().next()?;
+        let close = line[1..].find(quote)? + 1;
+        close + 1 + line[close + 1..].find('=')?
+    } else {
+        line.find('=')?
+    };
+    let (key, value) = line.split_at(split);
+    Some((key.trim(), value[1..].trim()))
+}
+
+fn parse_key(key: &str) -> Result<String, String> {
+    if key.is_empty() {
+        return Err(String::from("missing key"));
+    }
+    if key.starts_with('"') || key.starts_with('\'') {
+        return match parse_value(key)? {
+            Value::Str(s) => Ok(s),
+            _ => Err(format!("invalid key {}", key)),
+        };
+    }
+    if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.') {
+        Ok(String::from(key))
+    } else {
+        Err(format!("invalid key `{}`, quote keys containing spaces or symbols", key))
+    }
+}
+
+pub fn parse_value(value: &str) -> Result<Value, String> {
+    if value.is_empty() {
+        return Err(String::from("missing value"));
+    }
+    if let Some(rest) = value.strip_prefix('\'') {
+        return match rest.strip_suffix('\'') {
+            Some(s) => Ok(Value::Str(String::from(s))),
+            None => Err(String::from("unterminated string")),
+        };
+    }
+    if let Some(rest) = value.strip_prefix('"') {
+        let body = match rest.strip_suffix('"') {
+            Some(body) if !body.ends_with('\\') || body.ends_with("\\\\") => body,
+            _ => return Err(String::from("unterminated string")),
+        };
+        return unescape(body).map(Value::Str);
+    }
+    match value {
+        "true" => return Ok(Value::Bool(true)),
+        "false" => return Ok(Value::Bool(false)),
+        _ => (),
+    }
+    let digits = value.replace('_', "");
+    if let Ok(i) = digits.parse::<i64>() {
+        return Ok(Value::Int(i));
+    }
+    if let Ok(x) = digits.parse::<f64>() {
+        return Ok(Value::Float(x));
+    }
+    Err(format!("invalid value `{}`", value))
+}
+
+fn unescape(body: &str) -> Result<String, String> {
+    let mut out = String::new();
+    let mut chars = body.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('n') => out.push('\n'),
+            Some('t') => out.push('\t'),
+            Some('"') => out.push('"'),
+            Some('\\') => out.push('\\'),
+            Some('e') => out.push('\x1B'),
+            Some(other) => return Err(format!("unknown escape `\\{}`", other)),
+            None => return Err(String::from("unterminated escape")),
+        }
+    }
+    Ok(out)
+}
+
+// Editor options, set from the top level of the config file or with :set.
+pub struct Options {
+    // Width of the main pane as a percentage of the terminal
+    pub main_width: usize,
+    pub sidebar: bool,
+    // "left" or "right" of the main pane
+    pub sidebar_side: String,
+    pub scrollbar: bool,
+    // A line above the main pane naming the open documents
+    pub tab_line: bool,
+    pub tab_width: usize,
+    pub line_numbers: bool,
+    pub theme: String,
+    // "auto", "16", "256" or "truecolor"
+    pub colors: String,
+    // Rows kept visible above and below the cursor when scrolling
+    pub scrolloff: usize,
+    pub center_cursor: bool,
+    // Soft wrap long lines instead of scrolling horizontally
+    pub wrap: bool,
+    pub wrap_words: bool,
+    pub wrap_indent: bool,
+    pub wrap_marker: String,
+    // Scroll only the cursor line horizontally, leaving the others at column 0
+    pub cursor_line_scroll: bool,
+    // Indent new lines like the one before, deeper after a block opener
+    pub auto_indent: bool,
+    // Indent with spaces rather than tabs
+    pub expand_tab: bool,
+    // Columns per indentation level when indenting with spaces
+    pub indent_width: usize,
+    // Follow the indentation found in each file rather than the two options above
+    pub detect_indent: bool,
+    // Type closing brackets and quotes along with the opening ones
+    pub auto_pairs: bool,
+    // Keep marks between sessions
+    pub persist_marks: bool,
+    // Keep unsaved changes in a swap file next to the file, to recover them after a crash
+    pub swap_file: bool,
+    // Seconds between writes of the swap file while there are unsaved changes
+    pub swap_interval: usize,
+    // Save after this many seconds without a key press, or never when 0
+    pub autosave: usize,
+    // Reload the file when another program changes it, unless there are unsaved changes
+    pub auto_reload: bool,
+    // Files of at least this many megabytes are paged in as they are shown, and cannot be edited
+    pub large_file_size: usize,
+    // Typing in hex mode inserts bytes instead of writing over them
+    pub hex_insert: bool,
+}
+
+pub const OPTION_NAMES: &[&str] = &["main_width", "sidebar", "sidebar_side", "scrollbar", "tab_line", "tab_width", "line_numbers", "theme", "colors", "scrolloff", "center_cursor",
+    "wrap", "wrap_words", "wrap_indent", "wrap_marker", "cursor_line_scroll",
+    "auto_indent", "expand_tab", "indent_width", "detect_indent", "auto_pairs", "persist_marks",
+    "swap_file", "swap_interval", "autosave", "auto_reload", "large_file_size",
+    "hex_insert"];
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            main_width: 75,
+            sidebar: true,
+            sidebar_side: String::from("right"),
+            scrollbar: true,
+            tab_line: false,
+            tab_width: 4,
+            line_numbers: false,
+            theme: String::from("dark"),
+            colors: String::from("auto"),
+            scrolloff: 3,
+            center_cursor: false,
+            wrap: false,
+            wrap_words: true,
+            wrap_indent: true,
+            wrap_marker: String::from("↪ "),
+            cursor_line_scroll: false,
+            auto_indent: true,
+            expand_tab: true,
+            indent_width: 4,
+            detect_indent: true,
+            auto_pairs: true,
+            persist_marks: false,
+            swap_file: true,
+            swap_interval: 4,
+            autosave: 0,
+            auto_reload: false,
+            large_file_size: 64,
+            hex_insert: false,
+        }
+    }
+}
+
+impl Options {
+    // Starts from the defaults and applies every top level entry of the config file.
+    pub fn load(config: &ConfigFile) -> (Options, Vec<String>) {
+        let mut options = Options::default();
+        let mut errors = Vec::new();
+        for entry in config.section("") {
+            if let Err(msg) = options.set(&entry.key, &entry.value) {
+                errors.push(format!("{}: {}", config.location(entry.line), msg));
+            }
+        }
+        (options, errors)
+    }
+
+    pub fn set(&mut self, name: &str, value: &Value) -> Result<(), String> {
+        match name {
+            "main_width" => self.main_width = int_in_range(name, value, 20, 95)?,
+            "sidebar" => self.sidebar = boolean(name, value)?,
+            "sidebar_side" => match value {
+                Value::Str(side) if side == "left" || side == "right" => self.sidebar_side = side.clone(),
+                _ => return Err(format!("sidebar_side must be left or right, found {}", value)),
+            },
+            "scrollbar" => self.scrollbar = boolean(name, value)?,
+            "tab_line" => self.tab_line = boolean(name, value)?,
+            "tab_width" => self.tab_width = int_in_range(name, value, 1, 16)?,
+            "line_numbers" => self.line_numbers = boolean(name, value)?,
+            "theme" => match value {
+                Value::Str(name) if theme::Theme::exists(name) => self.theme = name.clone(),
+                _ => return Err(format!("no theme named {}", value)),
+            },
+            "scrolloff" => self.scrolloff = int_in_range(name, value, 0, 999)?,
+            "center_cursor" => self.center_cursor = boolean(name, value)?,
+            "wrap" => self.wrap = boolean(name, value)?,
+            "wrap_words" => self.wrap_words = boolean(name, value)?,
+            "wrap_indent" => self.wrap_indent = boolean(name, value)?,
+            "wrap_marker" => match value {
+                Value::Str(marker) if marker.chars().count() <= 8 => self.wrap_marker = marker.clone(),
+                _ => return Err(format!("wrap_marker must be a string of up to 8 characters, found {}", value)),
+            },
+            "cursor_line_scroll" => self.cursor_line_scroll = boolean(name, value)?,
+            "auto_indent" => self.auto_indent = boolean(name, value)?,
+            "expand_tab" => self.expand_tab = boolean(name, value)?,
+            "indent_width" => self.indent_width = int_in_range(name, value, 1, 16)?,
+            "detect_indent" => self.detect_indent = boolean(name, value)?,
+            "auto_pairs" => self.auto_pairs = boolean(name, value)?,
+            "persist_marks" => self.persist_marks = boolean(name, value)?,
+            "swap_file" => self.swap_file = boolean(name, value)?,
+            "swap_interval" => self.swap_interval = int_in_range(name, value, 1, 3600)?,
+            "autosave" => self.autosave = int_in_range(name, value, 0, 86400)?,
+            "auto_reload" => self.auto_reload = boolean(name, value)?,
+            "large_file_size" => self.large_file_size = int_in_range(name, value, 1, 1 << 20)?,
+            "hex_insert" => self.hex_insert = boolean(name, value)?,
+            "colors" => match value {
+                Value::Str(name) if theme::ColorSupport::from_name(name).is_some() => self.colors = name.clone(),
+                Value::Int(n) if *n == 16 || *n == 256 => self.colors = n.to_string(),
+                _ => return Err(format!("colors must be auto, 16, 256 or truecolor, found {}", value)),
+            },
+            _ => return Err(format!("unknown option `{}`", name)),
+        }
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Option<Value> {
+        let value = match name {
+            "main_width" => Value::Int(self.main_width as i64),
+            "sidebar" => Value::Bool(self.sidebar),
+            "sidebar_side" => Value::Str(self.sidebar_side.clone()),
+            "scrollbar" => Value::Bool(self.scrollbar),
+            "tab_line" => Value::Bool(self.tab_line),
+            "tab_width" => Value::Int(self.tab_width as i64),
+            "line_numbers" => Value::Bool(self.line_numbers),
+            "theme" => Value::Str(self.theme.clone()),
+            "colors" => Value::Str(self.colors.clone()),
+            "scrolloff" => Value::Int(self.scrolloff as i64),
+            "center_cursor" => Value::Bool(self.center_cursor),
+            "wrap" => Value::Bool(self.wrap),
+            "wrap_words" => Value::Bool(self.wrap_words),
+            "wrap_indent" => Value::Bool(self.wrap_indent),
+            "wrap_marker" => Value::Str(self.wrap_marker.clone()),
+            "cursor_line_scroll" => Value::Bool(self.cursor_line_scroll),
+            "auto_indent" => Value::Bool(self.auto_indent),
+            "expand_tab" => Value::Bool(self.expand_tab),
+            "indent_width" => Value::Int(self.indent_width as i64),
+            "detect_indent" => Value::Bool(self.detect_indent),
+            "auto_pairs" => Value::Bool(self.auto_pairs),
+            "persist_marks" => Value::Bool(self.persist_marks),
+            "swap_file" => Value::Bool(self.swap_file),
+            "swap_interval" => Value::Int(self.swap_interval as i64),
+            "autosave" => Value::Int(self.autosave as i64),
+            "auto_reload" => Value::Bool(self.auto_reload),
+            "large_file_size" => Value::Int(self.large_file_size as i64),
+            "hex_insert" => Value::Bool(self.hex_insert),
+            _ => return None,
+        };
+        Some(value)
+    }
+}
+
+fn boolean(name: &str, value: &Value) -> Result<bool, String> {
+    match value {
+        Value::Bool(b) => Ok(*b),
+        _ => Err(format!("{} must be true or false, found {}", name, value)),
+    }
+}
+
+fn int_in_range(name: &str, value: &Value, min: i64, max: i64) -> Result<usize, String> {
+    match value {
+        Value::Int(i) if (min..=max).contains(i) => Ok(*i as usize),
+        _ => Err(format!("{} must be a number from {} to {}, found {}", name, min, max, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections_keys_and_values() {
+        let text = "tab_width = 4 # spaces\n\n[keys]\n\"C-s\" = 'save'\nname = \"a \\\"b\\\"\\t#c\"\n";
+        let config = ConfigFile::parse("/home/me/config.toml", text);
+        assert!(config.errors.is_empty(), "{:?}", config.errors);
+        let entries: Vec<(&str, &str, &Value, usize)> = config.entries.iter()
+            .map(|entry| (entry.section.as_str(), entry.key.as_str(), &entry.value, entry.line))
+            .collect();
+        assert_eq!(entries, [
+            ("", "tab_width", &Value::Int(4), 1),
+            ("keys", "C-s", &Value::Str(String::from("save")), 4),
+            ("keys", "name", &Value::Str(String::from("a \"b\"\t#c")), 5),
+        ]);
+        assert_eq!(config.section("keys").count(), 2);
+    }
+
+    #[test]
+    fn reports_bad_lines_and_keeps_going() {
+        let config = ConfigFile::parse("/home/me/config.toml", "[keys\nno value\nwrap = yes\nwrap = true\n");
+        assert_eq!(config.errors, [
+            "config.toml:1: unterminated section header",
+            "config.toml:2: expected `key = value`",
+            "config.toml:3: invalid value `yes`",
+        ]);
+        assert_eq!(config.entries.len(), 1);
+    }
+
+    #[test]
+    fn parses_values() {
+        assert_eq!(parse_value("1_000"), Ok(Value::Int(1000)));
+        assert_eq!(parse_value("-2"), Ok(Value::Int(-2)));
+        assert_eq!(parse_value("0.5"), Ok(Value::Float(0.5)));
+        assert_eq!(parse_value("false"), Ok(Value::Bool(false)));
+        assert_eq!(parse_value("'C:\\dir'"), Ok(Value::Str(String::from("C:\\dir"))));
+        assert_eq!(parse_value("\"\\e[1m\""), Ok(Value::Str(String::from("\x1B[1m"))));
+        assert_eq!(parse_value("\"open\\\""), Err(String::from("unterminated string")));
+        assert_eq!(parse_value("\"\\q\""), Err(String::from("unknown escape `\\q`")));
+        assert_eq!(parse_value(""), Err(String::from("missing value")));
+    }
+
+    #[test]
+    fn options_check_their_values() {
+        let config = ConfigFile::parse("config.toml", "tab_width = 8\nwrap = true\nmain_width = 5\nsidebar_side = \"up\"\nnope = 1\n");
+        let (options, errors) = Options::load(&config);
+        assert_eq!(options.tab_width, 8);
+        assert!(options.wrap);
+        assert_eq!(options.main_width, Options::default().main_width);
+        assert_eq!(errors, [
+            "config.toml:3: main_width must be a number from 20 to 95, found 5",
+            "config.toml:4: sidebar_side must be left or right, found \"up\"",
+            "config.toml:5: unknown option `nope`",
+        ]);
+        assert_eq!(options.get("tab_width"), Some(Value::Int(8)));
+        assert_eq!(options.get("nope"), None);
+    }
+}
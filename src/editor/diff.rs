@@ -0,0 +1,160 @@
+use std::ops::Range;
+
+// Line differences between two texts, to show how a file on disk differs from the editor's copy.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+// Past this many pairs of lines to compare, the changed middle is shown as replaced whole
+const MAX_WORK: usize = 4_000_000;
+
+// Longest common subsequence of lines, after the lines the texts start and end with in common
+pub fn lines<'a>(old: &[&'a str], new: &[&'a str]) -> Vec<Line<'a>> {
+    let prefix = old.iter().zip(new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut result: Vec<Line> = old[..prefix].iter().map(|line| Line::Same(line)).collect();
+    if a.len() * b.len() > MAX_WORK {
+        result.extend(a.iter().map(|line| Line::Removed(line)));
+        result.extend(b.iter().map(|line| Line::Added(line)));
+    } else {
+        // common[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+        let mut common = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                common[i][j] = if a[i] == b[j] { common[i + 1][j + 1] + 1 } else { common[i + 1][j].max(common[i][j + 1]) };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                result.push(Line::Same(a[i]));
+                (i, j) = (i + 1, j + 1);
+            } else if j == b.len() || (i < a.len() && common[i + 1][j] >= common[i][j + 1]) {
+                result.push(Line::Removed(a[i]));
+                i += 1;
+            } else {
+                result.push(Line::Added(b[j]));
+                j += 1;
+            }
+        }
+    }
+    result.extend(old[old.len() - suffix..].iter().map(|line| Line::Same(line)));
+    result
+}
+
+// A run of changed lines: the `old` lines of the first text became the `new` lines of the second
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Hunk {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+// The runs of changed lines, from the top
+pub fn hunks(old: &[&str], new: &[&str]) -> Vec<Hunk> {
+    let mut hunks: Vec<Hunk> = Vec::new();
+    let (mut i, mut j) = (0, 0);
+    let mut in_run = false;
+    for line in lines(old, new) {
+        let changed = !matches!(line, Line::Same(_));
+        if changed && !in_run {
+            hunks.push(Hunk { old: i..i, new: j..j });
+        }
+        in_run = changed;
+        match line {
+            Line::Same(_) => (i, j) = (i + 1, j + 1),
+            Line::Removed(_) => i += 1,
+            Line::Added(_) => j += 1,
+        }
+        if let Some(hunk) = hunks.last_mut().filter(|_| changed) {
+            (hunk.old.end, hunk.new.end) = (i, j);
+        }
+    }
+    hunks
+}
+
+// The changed lines, each run under a header with the line number it starts at in `old`
+pub fn render(old: &str, new: &str) -> Vec<String> {
+    let (old, new): (Vec<&str>, Vec<&str>) = (old.lines().collect(), new.lines().collect());
+    let mut rendered = Vec::new();
+    let mut row = 0;
+    let mut in_run = false;
+    for line in lines(&old, &new) {
+        match line {
+            Line::Same(_) => {
+                row += 1;
+                in_run = false;
+                continue;
+            },
+            _ if !in_run => rendered.push(format!("@@ line {}", row + 1)),
+            _ => (),
+        }
+        in_run = true;
+        match line {
+            Line::Removed(text) => {
+                rendered.push(format!("-{}", text));
+                row += 1;
+            },
+            Line::Added(text) => rendered.push(format!("+{}", text)),
+            Line::Same(_) => (),
+        }
+    }
+    rendered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finds_the_lines_in_common() {
+        let old = ["a", "b", "c", "d"];
+        let new = ["a", "x", "c", "d", "e"];
+        assert_eq!(lines(&old, &new), [
+            Line::Same("a"),
+            Line::Removed("b"),
+            Line::Added("x"),
+            Line::Same("c"),
+            Line::Same("d"),
+            Line::Added("e"),
+        ]);
+        assert_eq!(lines(&old, &old), old.map(Line::Same));
+        assert_eq!(lines(&[], &["a"]), [Line::Added("a")]);
+    }
+
+    #[test]
+    fn replaces_large_changes_whole() {
+        let text = |name: &str| -> Vec<String> {
+            std::iter::once(String::from("top")).chain((0..2001).map(|n| format!("{} {}", name, n))).collect()
+        };
+        let (old, new) = (text("old"), text("new"));
+        let (old, new): (Vec<&str>, Vec<&str>) = (old.iter().map(String::as_str).collect(), new.iter().map(String::as_str).collect());
+        let diff = lines(&old, &new);
+        assert_eq!(diff[0], Line::Same("top"));
+        assert!(diff[1..2002].iter().all(|line| matches!(line, Line::Removed(_))));
+        assert!(diff[2002..].iter().all(|line| matches!(line, Line::Added(_))));
+    }
+
+    #[test]
+    fn groups_changes_into_hunks() {
+        let old = ["a", "b", "c", "d", "e"];
+        let new = ["a", "x", "y", "c", "e", "f"];
+        assert_eq!(hunks(&old, &new), [
+            Hunk { old: 1..2, new: 1..3 },
+            Hunk { old: 3..4, new: 4..4 },
+            Hunk { old: 5..5, new: 5..6 },
+        ]);
+        assert_eq!(hunks(&old, &old), []);
+    }
+
+    #[test]
+    fn renders_runs_under_their_line() {
+        assert_eq!(render("a\nb\nc\nd\n", "a\nx\nc\n"), ["@@ line 2", "-b", "+x", "@@ line 4", "-d"]);
+        assert_eq!(render("a\n", "a\n"), Vec::<String>::new());
+    }
+}
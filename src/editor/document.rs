@@ -1,54 +1,328 @@
-use std::{fs, cmp};
-use crate::editor::util;
+use std::cell::{Ref, RefCell};
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
+use std::ops::{Index, IndexMut};
+use crate::editor::{diff, encoding, highlighting, history, indent, large, marks, util, watch, wrap};
+
+// The rows of a document: all in memory, or paged in from a large file as they are looked at
+pub enum Rows {
+    Lines(Vec<util::GapBuffer>),
+    Large(large::LargeFile),
+}
+
+impl Rows {
+    pub fn len(&self) -> usize {
+        match self {
+            Rows::Lines(lines) => lines.len(),
+            Rows::Large(file) => file.len(),
+        }
+    }
+
+    pub fn get(&self, index: usize) -> Option<&util::GapBuffer> {
+        match self {
+            Rows::Lines(lines) => lines.get(index),
+            Rows::Large(file) => file.get(index),
+        }
+    }
+
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut util::GapBuffer> {
+        match self {
+            Rows::Lines(lines) => lines.get_mut(index),
+            Rows::Large(file) => file.get_mut(index),
+        }
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &util::GapBuffer> {
+        (0..self.len()).filter_map(|index| self.get(index))
+    }
+
+    // Rows can only be added and removed in memory. Large files are read-only,
+    // which the editor sees to before it gets here.
+    fn lines_mut(&mut self) -> &mut Vec<util::GapBuffer> {
+        match self {
+            Rows::Lines(lines) => lines,
+            Rows::Large(_) => panic!("large files are read-only"),
+        }
+    }
+}
+
+impl Index<usize> for Rows {
+    type Output = util::GapBuffer;
+
+    fn index(&self, index: usize) -> &util::GapBuffer {
+        self.get(index).expect("row out of range")
+    }
+}
+
+impl IndexMut<usize> for Rows {
+    fn index_mut(&mut self, index: usize) -> &mut util::GapBuffer {
+        self.get_mut(index).expect("row out of range")
+    }
+}
+
+// A window without focus onto a document: its cursor, and the row at the top of it
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Place {
+    pub cursor: (usize, usize),
+    pub top: usize,
+}
 
 pub struct Document{
-    pub rows: Vec<util::GapBuffer>,
+    pub rows: Rows,
     pub file_type: String,
     pub file_path: String,
+    // Indentation found in the file, if it has any
+    pub indent: Option<indent::Style>,
+    pub history: history::History,
+    pub marks: marks::Marks,
+    // Positions other than marks that edits move along with the text, such as the
+    // cursors of an edit made at several places at once
+    pub tracked: Vec<(usize, usize)>,
+    // Where each window without focus onto the document is, by window, which moves along
+    // with the text while it is edited in another window
+    pub windows: HashMap<usize, Place>,
+    // Counts changes to the text, so others can tell whether it changed since they last looked
+    pub version: u64,
+    // Version of the text last written to the file
+    saved_version: u64,
+    // The file as it was last read or written, to tell when another program changes it
+    pub disk: Option<watch::Stamp>,
+    // Encoding the text is written in
+    pub encoding: encoding::Encoding,
+    // What ends lines in the file, "\n" or "\r\n"
+    pub line_ending: &'static str,
+    // The file ends with a line ending, which does not start another row
+    pub final_newline: bool,
+    // Changes are refused, as asked for or because the file cannot be written
+    pub read_only: bool,
+    // The lexer state at the start of rows, as far down as it has been needed. The state
+    // of a row depends on those above it, so an edit drops the ones below where it starts.
+    states: RefCell<Vec<highlighting::State>>,
+    // Screen rows each row takes with soft wrap, measured for the scrollbar
+    heights: RefCell<wrap::Heights>,
 }
 
 impl Document{
-    pub fn open(path: &str) -> Document{
+    pub fn open(path: &str) -> Result<Document, std::io::Error> {
+        // A file that does not exist yet starts out empty and is created on save
+        let bytes = match fs::read(path) {
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Document { final_newline: true, ..Document::from_text(path, "") });
+            },
+            result => result?,
+        };
+        let (encoding, content) = decode(path, &bytes)?;
+        Ok(Document {
+            disk: Some(watch::Stamp::new(path, &bytes)),
+            encoding,
+            read_only: !writable(path),
+            ..Document::from_text(path, &content)
+        })
+    }
+
+    // Pages a large file in as it is shown instead of reading it all. It cannot be edited.
+    // Only UTF-8 and Latin-1 files can be paged in, as lines are found by their newline bytes.
+    pub fn open_large(path: &str) -> Result<Document, std::io::Error> {
+        let mut head = Vec::new();
+        fs::File::open(path)?.take(4096).read_to_end(&mut head)?;
+        if encoding::is_binary(&head) || head.starts_with(b"\xFF\xFE") || head.starts_with(b"\xFE\xFF") {
+            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "not a UTF-8 or Latin-1 text file"));
+        }
+        let encoding = encoding::detect_start(&head);
+        let mut document = Document {
+            rows: Rows::Large(large::LargeFile::open(path, encoding)?),
+            encoding,
+            read_only: true,
+            ..Document::from_text(path, "")
+        };
+        document.detect_indent();
+        Ok(document)
+    }
+
+    pub fn is_large(&self) -> bool {
+        matches!(self.rows, Rows::Large(_))
+    }
+
+    // Percentage of a large file found so far, while its lines are still being indexed
+    pub fn indexing(&self) -> Option<usize> {
+        match &self.rows {
+            Rows::Large(file) => file.indexing(),
+            Rows::Lines(_) => None,
+        }
+    }
+
+    // Takes in the lines of a large file found since the last call, and lets go of the
+    // ones outside `keep`. Returns whether there are more rows.
+    pub fn page(&mut self, keep: std::ops::Range<usize>) -> bool {
+        match &mut self.rows {
+            Rows::Large(file) => {
+                file.trim(keep);
+                file.update()
+            },
+            Rows::Lines(_) => false,
+        }
+    }
+
+    // A document holding `content` that is saved to `path`
+    pub fn from_text(path: &str, content: &str) -> Document {
         let mut rows = Vec::new();
-        let content = fs::read_to_string(path).expect("Could not read file");
-        let file_type = String::from(path.split('.').last().unwrap_or("txt"));
+        let file_type = String::from(path.split('.').next_back().unwrap_or("txt"));
         for line in content.lines() {
-            let row = util::GapBuffer::from_str(&line);
+            let row = util::GapBuffer::from_str(line);
             rows.push(row);
         }
-        
+        if rows.is_empty() {
+            rows.push(util::GapBuffer::from_str(""));
+        }
+        let indent = indent::detect(content.lines());
+
         Document{
-            rows,
+            rows: Rows::Lines(rows),
             file_type,
             file_path: String::from(path),
+            indent,
+            history: history::History::default(),
+            marks: marks::Marks::default(),
+            tracked: Vec::new(),
+            windows: HashMap::new(),
+            version: 0,
+            saved_version: 0,
+            disk: None,
+            encoding: encoding::Encoding::default(),
+            line_ending: line_ending(content),
+            final_newline: content.ends_with('\n'),
+            read_only: false,
+            states: RefCell::default(),
+            heights: RefCell::default(),
         }
     }
 
-    pub fn save(&self) -> Result<(), std::io::Error>{
-        let content: Vec<String> = self.rows.iter().map(|row| row.to_string()).collect();
-        fs::write(&self.file_path, content.join("\n"))
+    // Fails with InvalidData, before touching the file, when the text has characters
+    // its encoding cannot hold.
+    pub fn save(&mut self) -> Result<(), std::io::Error>{
+        let bytes = self.encoding.encode(&self.text())
+            .map_err(|msg| std::io::Error::new(std::io::ErrorKind::InvalidData, msg))?;
+        fs::write(&self.file_path, &bytes)?;
+        self.synced(&bytes);
+        Ok(())
     }
 
-    pub fn print(&self){
-        for row in &self.rows{
-            println!("{}", row.to_string());
+    // Writes bytes edited in place of the text, as in hex mode
+    pub fn save_bytes(&mut self, bytes: &[u8]) -> Result<(), std::io::Error> {
+        fs::write(&self.file_path, bytes)?;
+        self.synced(bytes);
+        Ok(())
+    }
+
+    // Takes the file to hold `bytes` now, as they were just read or written
+    pub fn synced(&mut self, bytes: &[u8]) {
+        self.disk = Some(watch::Stamp::new(&self.file_path, bytes));
+        self.mark_saved();
+    }
+
+    // Takes the text as it is now to be what the file holds
+    pub fn mark_saved(&mut self) {
+        self.saved_version = self.version;
+    }
+
+    // Counts a change made to what is saved other than by editing the text
+    pub fn changed(&mut self) {
+        self.version += 1;
+    }
+
+    // Replaces the text with what the file holds now, as a change that can be undone.
+    pub fn reload(&mut self) -> Result<(), std::io::Error> {
+        let bytes = fs::read(&self.file_path)?;
+        let (encoding, content) = decode(&self.file_path, &bytes)?;
+        self.replace_text(&content);
+        self.encoding = encoding;
+        self.synced(&bytes);
+        Ok(())
+    }
+
+    // Writes the text in `encoding` from now on. The file has to be saved again, so this
+    // counts as a change, but not one that can be undone.
+    pub fn set_encoding(&mut self, encoding: encoding::Encoding) -> Result<(), String> {
+        if encoding == self.encoding {
+            return Ok(());
+        }
+        encoding.encode(&self.text())?;
+        self.encoding = encoding;
+        self.changed();
+        Ok(())
+    }
+
+    // How the file compares with what was last read from or written to it
+    pub fn disk_state(&self) -> watch::Disk {
+        watch::compare(self.disk.as_ref(), &self.file_path)
+    }
+
+    // The whole text as it is written to the file
+    pub fn text(&self) -> String {
+        let mut text = self.lines(0, self.rows.len()).join(self.line_ending);
+        if self.final_newline {
+            text.push_str(self.line_ending);
+        }
+        text
+    }
+
+    // Whether there are changes that have not been saved
+    pub fn is_modified(&self) -> bool {
+        self.version != self.saved_version
+    }
+
+    // Adds rows at the end that are not an edit, like input read as it arrives. The
+    // first ones take the place of the empty row a document starts out with.
+    pub fn append_lines(&mut self, lines: &[String], first: bool) {
+        let rows = self.rows.lines_mut();
+        if first {
+            rows.clear();
+            self.states.get_mut().clear();
+            self.heights.get_mut().clear();
+        }
+        rows.extend(lines.iter().map(|line| util::GapBuffer::from_str(line)));
+        if rows.is_empty() {
+            rows.push(util::GapBuffer::from_str(""));
+        }
+        // Nothing that needs saving
+        self.changed();
+        self.mark_saved();
+    }
+
+    // Replaces the whole text, as one change that can be undone. Only the lines that
+    // differ are replaced and recorded. How lines end is taken from the new text.
+    pub fn replace_text(&mut self, text: &str) {
+        self.line_ending = line_ending(text);
+        self.final_newline = text.ends_with('\n');
+        let old = self.lines(0, self.rows.len());
+        let old_lines: Vec<&str> = old.iter().map(String::as_str).collect();
+        let mut new: Vec<&str> = text.lines().collect();
+        if new.is_empty() {
+            new.push("");
+        }
+        // Runs before this one are already replaced, so each starts where it does in the new text
+        for hunk in diff::hunks(&old_lines, &new) {
+            let removed = old[hunk.old].to_vec();
+            let inserted: Vec<String> = new[hunk.new.clone()].iter().map(|line| line.to_string()).collect();
+            self.replace_rows(hunk.new.start, removed.len(), &inserted);
+            self.history.record(history::Change { row: hunk.new.start, removed, inserted });
         }
     }
 
     pub fn new_line(&mut self, row_idx: usize, col: usize) {
+        let removed = self.lines(row_idx, 1);
         if let Some(current_row) = self.rows.get_mut(row_idx) {
             // Get the full content as a UTF-8 string
             let content = current_row.to_string();
             
             // Find the byte offset for the character position
             let mut byte_offset = content.len(); // Default to end if col is out of bounds
-            let mut current_char = 0;
-            for (idx, _) in content.char_indices() {
+            for (current_char, (idx, _)) in content.char_indices().enumerate() {
                 if current_char == col {
                     byte_offset = idx;
                     break;
                 }
-                current_char += 1;
             }
     
             // Split the content into two parts
@@ -59,23 +333,209 @@ impl Document{
     
             // Create new row with post-split content
             let new_row = util::GapBuffer::from_str(post_split);
-            self.rows.insert(row_idx + 1, new_row);
+            self.rows.lines_mut().insert(row_idx + 1, new_row);
+            self.record(row_idx, removed, 2);
+            marks::split(self.moved(), row_idx, col);
+            marks::rows_replaced(self.tops(), row_idx, 1, 2);
         }
     }
     
-    pub fn row(&self, index: usize) -> Option<&util::GapBuffer>{
-        self.rows.get(index)
+    pub fn insert_char(&mut self, row_idx: usize, col: usize, c: char) {
+        let removed = self.lines(row_idx, 1);
+        if let Some(row) = self.rows.get_mut(row_idx) {
+            let offset = row.byte_offset(col);
+            row.insert_char(offset, c);
+            self.record(row_idx, removed, 1);
+            marks::inserted(self.moved(), row_idx, col, 1);
+        }
     }
 
-    pub fn row_mut(&mut self, index: usize) -> Option<&mut util::GapBuffer>{
-        self.rows.get_mut(index)
+    pub fn insert_str(&mut self, row_idx: usize, col: usize, text: &str) {
+        let removed = self.lines(row_idx, 1);
+        if let Some(row) = self.rows.get_mut(row_idx) {
+            let offset = row.byte_offset(col);
+            row.insert(offset, text.as_bytes());
+            self.record(row_idx, removed, 1);
+            marks::inserted(self.moved(), row_idx, col, text.chars().count());
+        }
+    }
+
+    pub fn remove_chars(&mut self, row_idx: usize, cols: std::ops::Range<usize>) {
+        let removed = self.lines(row_idx, 1);
+        if let Some(row) = self.rows.get_mut(row_idx) {
+            row.remove_chars(cols.clone());
+            self.record(row_idx, removed, 1);
+            marks::removed(self.moved(), row_idx, cols.start, cols.end);
+        }
+    }
+
+    // Appends the next row to `row_idx` and removes it.
+    pub fn join_lines(&mut self, row_idx: usize) {
+        if row_idx + 1 >= self.rows.len() {
+            return;
+        }
+        let removed = self.lines(row_idx, 2);
+        let next = self.rows.lines_mut().remove(row_idx + 1).to_string();
+        let row = &mut self.rows[row_idx];
+        let end = row.buffer_length();
+        let len = row.str_len();
+        row.insert(end, next.as_bytes());
+        self.record(row_idx, removed, 1);
+        marks::joined(self.moved(), row_idx, len);
+        marks::rows_replaced(self.tops(), row_idx, 2, 1);
+    }
+
+    // Looks at the rows again to work out how the document is indented.
+    // Only the start of a large file is looked at.
+    pub fn detect_indent(&mut self) {
+        let count = if self.is_large() { large::CHUNK } else { self.rows.len() };
+        let lines = self.lines(0, count);
+        self.indent = indent::detect(lines.iter().map(|line| line.as_str()));
+    }
+
+    // Every position that has to follow the text through an edit
+    fn moved(&mut self) -> impl Iterator<Item = &mut (usize, usize)> {
+        self.marks.positions_mut()
+            .chain(self.tracked.iter_mut())
+            .chain(self.windows.values_mut().map(|place| &mut place.cursor))
+    }
+
+    // The top rows of the windows without focus, which follow rows rather than text
+    fn tops(&mut self) -> impl Iterator<Item = &mut usize> {
+        self.windows.values_mut().map(|place| &mut place.top)
+    }
+
+    // Text of up to `count` rows from `row_idx`
+    fn lines(&self, row_idx: usize, count: usize) -> Vec<String> {
+        self.rows.iter().skip(row_idx).take(count).map(|row| row.to_string()).collect()
+    }
+
+    // Records that `removed` rows at `row_idx` are now the `count` rows there.
+    fn record(&mut self, row_idx: usize, removed: Vec<String>, count: usize) {
+        let inserted = self.lines(row_idx, count);
+        self.version += 1;
+        self.states.get_mut().truncate(row_idx + 1);
+        self.heights.get_mut().replaced(row_idx, removed.len(), count);
+        self.history.record(history::Change {
+            row: row_idx,
+            removed,
+            inserted,
+        });
+    }
+
+    // Puts `lines` in place of `count` rows at `row_idx`, without recording it.
+    fn replace_rows(&mut self, row_idx: usize, count: usize, lines: &[String]) {
+        let end = (row_idx + count).min(self.rows.len());
+        self.rows.lines_mut().splice(row_idx..end, lines.iter().map(|line| util::GapBuffer::from_str(line)));
+        self.version += 1;
+        self.states.get_mut().truncate(row_idx + 1);
+        self.heights.get_mut().replaced(row_idx, end - row_idx, lines.len());
+        marks::replaced(self.moved(), row_idx, end - row_idx, lines.len());
+        marks::rows_replaced(self.tops(), row_idx, end - row_idx, lines.len());
+    }
+
+    // Reverts the last group of edits and returns the cursor position from before it.
+    pub fn undo(&mut self) -> Option<(usize, usize)> {
+        let group = self.history.take_undo()?;
+        for change in group.changes.iter().rev() {
+            self.replace_rows(change.row, change.inserted.len(), &change.removed);
+        }
+        let cursor = group.before;
+        self.history.push_redo(group);
+        Some(cursor)
+    }
+
+    // Repeats the last undone group of edits and returns the cursor position after it.
+    pub fn redo(&mut self) -> Option<(usize, usize)> {
+        let group = self.history.take_redo()?;
+        for change in &group.changes {
+            self.replace_rows(change.row, change.removed.len(), &change.inserted);
+        }
+        let cursor = group.after;
+        self.history.push_undo(group);
+        Some(cursor)
+    }
+
+    // The lexer state at the start of each of the first `count` rows
+    pub fn line_states(&self, count: usize) -> Ref<'_, [highlighting::State]> {
+        let count = count.min(self.rows.len());
+        if self.states.borrow().len() < count {
+            highlighting::extend_states(&mut self.states.borrow_mut(), self, count);
+        }
+        Ref::map(self.states.borrow(), |states| &states[..count])
+    }
+
+    // Screen rows the document takes with soft wrap, and those taken by the rows above `row`
+    pub fn wrapped_height(&self, options: &wrap::WrapOptions, row: usize) -> (usize, usize) {
+        self.heights.borrow_mut().measure(self, options, row)
+    }
+
+    pub fn row(&self, index: usize) -> Option<&util::GapBuffer>{
+        self.rows.get(index)
     }
 
     pub fn rows(&self) -> usize{
         self.rows.len()
     }
+}
+
+// The encoding and text of a file's bytes, or an error for binary files
+pub fn decode(path: &str, bytes: &[u8]) -> Result<(encoding::Encoding, String), std::io::Error> {
+    encoding::detect(bytes).ok_or_else(|| {
+        std::io::Error::new(std::io::ErrorKind::InvalidData, format!("{} looks like a binary file", path))
+    })
+}
+
+// What ends the first line of `text`, and the others with it
+fn line_ending(text: &str) -> &'static str {
+    match text.find('\n') {
+        Some(end) if text[..end].ends_with('\r') => "\r\n",
+        _ => "\n",
+    }
+}
+
+// Whether this process may write to the file at `path`
+pub fn writable(path: &str) -> bool {
+    let Ok(path) = std::ffi::CString::new(path) else {
+        return false;
+    };
+    unsafe { libc::access(path.as_ptr(), libc::W_OK) == 0 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    pub fn cols(&self) -> usize{
-        self.rows.iter().map(|row| row.buffer_length()).max().unwrap_or(0) as usize
+    #[test]
+    fn wrapped_heights_follow_edits() {
+        let options = wrap::WrapOptions { width: 4, tab_width: 4, words: false, indent: false, marker: String::new() };
+        let mut document = Document::from_text("test.txt", "abcdefghij\nab\n");
+        assert_eq!(document.wrapped_height(&options, 1), (4, 3));
+        document.insert_str(1, 2, "cdefg");
+        assert_eq!(document.wrapped_height(&options, 1), (5, 3));
+        document.new_line(0, 2);
+        assert_eq!(document.wrapped_height(&options, 2), (5, 3));
+        document.join_lines(0);
+        assert_eq!(document.wrapped_height(&options, 1), (5, 3));
+        document.history.end((0, 0), false);
+        document.undo();
+        assert_eq!(document.wrapped_height(&options, 1), (4, 3));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn replacing_text_records_only_the_changed_lines() {
+        let mut document = Document::from_text("test.txt", "a\nb\nc\nd\ne\n");
+        document.replace_text("a\nB\nc\nd\nx\ny\n");
+        document.history.end((0, 0), false);
+        let group = document.history.take_undo().unwrap();
+        let changes: Vec<(usize, Vec<&str>, Vec<&str>)> = group.changes.iter()
+            .map(|change| (change.row, change.removed.iter().map(String::as_str).collect(), change.inserted.iter().map(String::as_str).collect()))
+            .collect();
+        assert_eq!(changes, vec![(1, vec!["b"], vec!["B"]), (4, vec!["e"], vec!["x", "y"])]);
+        document.history.push_undo(group);
+        document.undo();
+        assert_eq!(document.text(), "a\nb\nc\nd\ne\n");
+        document.redo();
+        assert_eq!(document.text(), "a\nB\nc\nd\nx\ny\n");
+    }
+}
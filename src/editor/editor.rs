@@ -1,76 +1,406 @@
-use std::collections::VecDeque;
-
-use crate::editor::{document, terminal, util, highlighting};
-
-pub static BLACK: &str = "\x1B[0;30m";
-pub static RED: &str = "\x1B[0;31m";
-pub static GREEN: &str = "\x1B[0;32m";
-pub static YELLOW: &str = "\x1B[0;33m";
-pub static BLUE: &str = "\x1B[0;34m";
-pub static MAGENTA: &str = "\x1B[0;35m";
-pub static CYAN: &str = "\x1B[0;36m";
-pub static WHITE: &str = "\x1B[0;37m";
-pub static STATUS_BAR: &str = "\x1B[48;5;237m";
-pub static SIDEBAR: &str = "\x1B[48;5;234m";
-static ANSI_END: &str = "\x1B[0m";
-
-const NAME: &str = env!("CARGO_PKG_NAME");
-const VERSION: &str = env!("CARGO_PKG_VERSION");
-const AUTHOR: &str = env!("CARGO_PKG_AUTHORS");
-const DESCRIPTION: &str = env!("CARGO_PKG_DESCRIPTION");
+use std::time::{Duration, Instant};
 
+use termion::event::Key;
+
+use crate::editor::{block, bmp, cli, config, diff, document, encoding, hex, indent, layout, marks, motion, pairs, pipe, swap, terminal, util, view, watch, window, wrap};
+use crate::editor::theme::{self, Role};
+use crate::editor::keymap::{self, Action, Lookup};
+
+// Rows past the top and bottom of the screen searched for the bracket to highlight
+const BRACKET_MARGIN: usize = 100;
+
+#[allow(clippy::upper_case_acronyms)]
 #[derive(PartialEq, Eq)]
 enum EditorState {
     EDIT,
     COMMAND,
 }
 
+// A question in the status bar, answered by the next key
+enum Prompt {
+    // Unsaved text from a crashed session, to recover or discard
+    Recover(swap::Swap),
+    // Another program changed the file: reload it, or keep the text here
+    Changed,
+}
+
+// A document and what the editor keeps for it. The document of the window with focus is
+// kept in the editor itself, and the others here until a window showing them gets focus.
+struct Buffer {
+    id: usize,
+    document: document::Document,
+    // The file's bytes while they are edited in hex mode, which takes the place of the text
+    hex: Option<hex::Hex>,
+    // A picture shown in place of the bytes of an image file
+    image: Option<bmp::Image>,
+    // The text came from standard input, so there is no file to save it to
+    piped: bool,
+    // Standard input while more of it is still to come, with -f
+    pipe: Option<pipe::Pipe>,
+    // Tells when another program changes the file, where inotify is available
+    watcher: Option<watch::Watcher>,
+    // Whether this editor keeps the document's swap file. Not while another one is using it,
+    // or while one left by a crash waits to be recovered.
+    write_swap: bool,
+    // Document version last written to the swap file, and when. The first change after
+    // the swap file is removed writes a new one straight away.
+    swap_version: u64,
+    swapped_at: Option<Instant>,
+    // Document version autosave last tried to save, so a failure is not retried until the next edit
+    autosave_version: u64,
+}
+
+impl Buffer {
+    fn new(id: usize, document: document::Document) -> Buffer {
+        Buffer {
+            id,
+            document,
+            hex: None,
+            image: None,
+            piped: false,
+            pipe: None,
+            watcher: None,
+            write_swap: true,
+            swap_version: 0,
+            swapped_at: None,
+            autosave_version: 0,
+        }
+    }
+
+    // Opens the file at `path`: paged in when it is large, and as bytes when it is not
+    // text, which are also shown as a picture when they are one. What goes wrong without
+    // stopping the file from being shown is added to `errors`.
+    fn open(id: usize, path: &str, options: &config::Options, errors: &mut Vec<String>) -> Result<Buffer, std::io::Error> {
+        let size = std::fs::metadata(path).map(|meta| meta.len()).unwrap_or(0);
+        if size >= options.large_file_size as u64 * 1024 * 1024 {
+            match document::Document::open_large(path) {
+                Ok(document) => return Ok(Buffer::new(id, document)),
+                Err(err) => errors.push(format!("Could not map {}, reading it whole: {}", path, err)),
+            }
+        }
+        match document::Document::open(path) {
+            // Binary files are edited as bytes
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                let bytes = std::fs::read(path)?;
+                let mut document = document::Document::from_text(path, "");
+                document.synced(&bytes);
+                document.read_only = !document::writable(path);
+                let image = match bmp::is_bmp(&bytes) {
+                    true => bmp::decode(&bytes).map_err(|msg| errors.push(format!("Could not show {}: {}", path, msg))).ok(),
+                    false => None,
+                };
+                Ok(Buffer { hex: Some(hex::Hex::new(&bytes)), image, ..Buffer::new(id, document) })
+            },
+            result => Ok(Buffer::new(id, result?)),
+        }
+    }
+
+    fn store_marks(&self, options: &config::Options) -> Result<(), std::io::Error> {
+        if options.persist_marks && !self.piped {
+            marks::store(&self.document.file_path, &self.document.marks)?;
+        }
+        Ok(())
+    }
+
+    // What is left to do for the document before it is closed. Nothing can be done about
+    // a failure on the way out.
+    fn put_away(&self, options: &config::Options) {
+        let _ = self.store_marks(options);
+        if self.write_swap {
+            swap::remove(&self.document.file_path);
+        }
+    }
+}
+
+// One screen row of the main view
+#[derive(Clone, Default)]
+struct ScreenLine {
+    // The document row, if this screen row starts one
+    number: Option<usize>,
+    text: Option<String>,
+    // Text continues past the left or right edge
+    clipped_left: bool,
+    clipped_right: bool,
+    // Column within `text` of the bracket matching the one at the cursor
+    bracket: Option<usize>,
+    // Columns within `text` of the cursors other than the main one
+    cursors: Vec<usize>,
+    // Columns within `text` inside the block selection
+    selection: Option<std::ops::Range<usize>>,
+    // Colors of the top and bottom halves of cells drawn before the text, for images
+    cells: Vec<(bmp::Rgb, Option<bmp::Rgb>)>,
+}
+
+// A window being drawn: the buffer it shows, its view of it and the size of its text
+struct Pane<'a> {
+    buffer: &'a Buffer,
+    view: &'a view::View,
+    width: usize,
+    height: usize,
+}
+
 pub struct Editor {
     terminal: terminal::Terminal,
-    document: document::Document,
-    cursor_x: usize,
-    cursor_y: usize,
+    view: view::View,
     exit: bool,
+    // Size of the text in the window with focus
     display_height: usize,
     display_width: usize,
-    display_x: usize,
-    display_y: usize,
-    file_cols: usize,
-    file_rows: usize,
-    previous_positions: VecDeque<(usize, usize)>,
+    // Where the panels of the screen go
+    layout: layout::Layout,
+    windows: window::Tree,
+    // The window with focus, and the windows without it
+    window: usize,
+    parked: Vec<window::Window>,
+    // The buffer shown in the window with focus, the others, and the number of the next one opened
+    buffer: Buffer,
+    buffers: Vec<Buffer>,
+    next_buffer: usize,
     state: EditorState,
-    key_pressed: Option<termion::event::Key>,
+    keymap: keymap::Keymap,
+    options: config::Options,
+    theme: theme::Theme,
+    pending_keys: Vec<Key>,
+    // A mark action waiting for the key that names the mark
+    mark_action: Option<Action>,
+    last_action: Option<Action>,
+    // Lines of the last copied or cut block
+    clipboard: Vec<String>,
+    prompt: Option<Prompt>,
+    last_key_at: Instant,
+    // The last key typed into the text, so it is undone along with the typing before it
+    typed: bool,
     status_text: util::GapBuffer,
+    // Cursor column within the command line
+    command_x: usize,
     sidebar: document::Document,
 }
 //TODO: Implement the sidebar
 //TODO: Implement row numbers
 impl Editor {
-    pub fn new() -> Result<Self, std::io::Error> {
+    pub fn new(args: cli::Args) -> Result<Self, std::io::Error> {
         let terminal = terminal::Terminal::new()?;
-        let document = document::Document::open("src/editor/editor.rs");
-        let doc_rows = document.rows();
-        let doc_cols = document.cols();
-        let display_height = terminal.height - 4; // I have no idea why its -4
-        let display_width = (terminal.width as f64 * 0.75) as usize -10;
-        Ok(Editor {
+        let config = config::ConfigFile::load();
+        let mut keymap = keymap::Keymap::new();
+        let (options, option_errors) = config::Options::load(&config);
+        let mut errors = config.errors.clone();
+        errors.extend(option_errors);
+        errors.extend(keymap.load(&config));
+        let piped = args.stdin;
+        let buffer = if piped && args.follow {
+            // What is followed arrives later, between keys
+            Buffer { piped, pipe: Some(pipe::Pipe::new()), ..Buffer::new(0, document::Document::from_text("-", "")) }
+        } else if piped {
+            Buffer { piped, ..Buffer::new(0, document::Document::from_text("-", &pipe::read_all()?)) }
+        } else {
+            Buffer::open(0, args.path.as_deref().unwrap_or("src/editor/editor.rs"), &options, &mut errors)?
+        };
+        let support = theme::ColorSupport::from_name(&options.colors).unwrap_or(theme::ColorSupport::Basic);
+        let theme = match theme::Theme::load(&options.theme, support) {
+            Ok(theme) => theme,
+            Err(msg) => {
+                errors.push(msg);
+                theme::Theme::load("dark", support).unwrap()
+            },
+        };
+        let status_text = errors.first().cloned().unwrap_or_default();
+        let mut editor = Editor {
             terminal,
-            document,
-            cursor_x: 0,
-            cursor_y: display_height/2,
+            view: view::View::default(),
             exit: false,
-            display_height,
-            display_width,
-            display_x: 0,
-            display_y: 0,
-            file_cols: doc_cols,
-            file_rows: doc_rows,
-            previous_positions: VecDeque::new(),
+            display_height: 0,
+            display_width: 0,
+            layout: layout::Layout::new(layout::Node::Panel(layout::Panel::Main, layout::Border::NONE)),
+            windows: window::Tree::new(),
+            window: 0,
+            parked: Vec::new(),
+            buffer,
+            buffers: Vec::new(),
+            next_buffer: 1,
             state: EditorState::EDIT,
-            key_pressed: Some(termion::event::Key::Null),
-            status_text: util::GapBuffer::from_str(""),
-            sidebar: document::Document::open("test.txt"),
-        })
+            keymap,
+            options,
+            theme,
+            pending_keys: Vec::new(),
+            mark_action: None,
+            last_action: None,
+            clipboard: Vec::new(),
+            prompt: None,
+            last_key_at: Instant::now(),
+            typed: false,
+            status_text: util::GapBuffer::from_str(&status_text),
+            command_x: 0,
+            sidebar: document::Document::open("test.txt").unwrap_or_else(|_| document::Document::from_text("test.txt", "")),
+        };
+        editor.layout();
+        editor.buffer.document.read_only |= args.read_only || piped;
+        editor.start_document();
+        if let Some(position) = args.position {
+            editor.go_to(position);
+        }
+        Ok(editor)
+    }
+
+    // Divides the screen into panels as the options have them, and the main panel among
+    // the windows.
+    fn layout(&mut self) {
+        use layout::{Border, Constraint, Node, Panel};
+        let main = if self.options.sidebar { Constraint::Percent(self.options.main_width) } else { Constraint::Fill };
+        let mut columns = vec![(main, Node::Panel(Panel::Main, Border::NONE))];
+        if self.options.sidebar {
+            // The dotted edge faces the text
+            let left = self.options.sidebar_side == "left";
+            let border = match left {
+                true => Border { left: Some('┃'), right: Some('┊') },
+                false => Border { left: Some('┊'), right: Some('┃') },
+            };
+            columns.insert(if left { 0 } else { 1 }, (Constraint::Fill, Node::Panel(Panel::Sidebar, border)));
+        }
+        let mut rows = Vec::new();
+        if self.options.tab_line {
+            rows.push((Constraint::Length(1), Node::Panel(Panel::TabLine, Border::NONE)));
+        }
+        rows.push((Constraint::Fill, Node::Split(window::Split::Columns, columns)));
+        for panel in [Panel::Status, Panel::Message, Panel::Mode] {
+            rows.push((Constraint::Length(1), Node::Panel(panel, Border::NONE)));
+        }
+        self.layout = layout::Layout::new(Node::Split(window::Split::Rows, rows));
+        self.layout.place(window::Rect { x: 0, y: 0, width: self.terminal.width, height: self.terminal.height });
+        self.windows.place(self.layout.inner(Panel::Main));
+        let rect = self.text_area(self.windows.rect(self.window));
+        (self.display_width, self.display_height) = (rect.width, rect.height);
+    }
+
+    // The part of a window that shows text, beside its scrollbar
+    fn text_area(&self, rect: window::Rect) -> window::Rect {
+        let scrollbar = usize::from(self.options.scrollbar);
+        layout::divide(rect, window::Split::Columns, &[layout::Constraint::Fill, layout::Constraint::Length(scrollbar)])[0]
+    }
+
+    // Starts looking after the document of the window with focus once it is opened: its
+    // swap file, changes made to the file elsewhere and its marks.
+    fn start_document(&mut self) {
+        // Text that cannot be changed has nothing to recover, until it is made writable
+        if self.read_only() {
+            self.buffer.write_swap = false;
+        } else {
+            self.check_swap();
+        }
+        if !self.buffer.document.is_large() && !self.buffer.piped {
+            self.buffer.watcher = watch::Watcher::new(&self.buffer.document.file_path);
+        }
+        if self.options.persist_marks && !self.buffer.piped {
+            self.buffer.document.marks = marks::load(&self.buffer.document.file_path);
+        }
+    }
+
+    // Gives the focus to buffer `id`, putting the one that had it with the others.
+    fn switch_buffer(&mut self, id: usize) {
+        if let Some(buffer) = self.buffers.iter_mut().find(|buffer| buffer.id == id) {
+            std::mem::swap(&mut self.buffer, buffer);
+        }
+    }
+
+    // Makes window `id` the one the editor works on. The document keeps the place of the
+    // window that was, so edits made elsewhere move it along with the text.
+    fn switch_window(&mut self, id: usize) {
+        let Some(idx) = self.parked.iter().position(|window| window.id == id) else {
+            return;
+        };
+        self.follow_edits();
+        let target = self.parked.swap_remove(idx);
+        let view = std::mem::replace(&mut self.view, target.view);
+        let place = document::Place { cursor: (view.cursor.row, view.cursor.col), top: view.top };
+        self.buffer.document.windows.insert(self.window, place);
+        self.parked.push(window::Window { id: self.window, buffer: self.buffer.id, view });
+        self.switch_buffer(target.buffer);
+        self.window = target.id;
+        self.buffer.document.windows.remove(&id);
+        let rect = self.text_area(self.windows.rect(id));
+        (self.display_width, self.display_height) = (rect.width, rect.height);
+    }
+
+    // Moves the windows without focus to where edits made in other windows left their places.
+    fn follow_edits(&mut self) {
+        for window in &mut self.parked {
+            let Some(buffer) = std::iter::once(&self.buffer).chain(&self.buffers).find(|buffer| buffer.id == window.buffer) else {
+                continue;
+            };
+            let Some(place) = buffer.document.windows.get(&window.id) else {
+                continue;
+            };
+            let view = &mut window.view;
+            if place.cursor != (view.cursor.row, view.cursor.col) {
+                view.set_cursor(&buffer.document, place.cursor.0, place.cursor.1);
+            }
+            if place.top != view.top {
+                (view.top, view.top_sub) = (place.top, 0);
+            }
+        }
+    }
+
+    // Moves the focus to window `id`. A window without focus keeps only its cursor and
+    // scroll position, not extra cursors or a block selection.
+    fn focus_window(&mut self, id: usize) {
+        if id == self.window || self.parked.iter().all(|window| window.id != id) {
+            return;
+        }
+        // The swap file is kept up to date for the document with focus, so it is brought
+        // up to date on the way out
+        self.update_swap(true);
+        self.view.extra_cursors.clear();
+        self.view.block_anchor = None;
+        self.switch_window(id);
+        self.check_disk();
+    }
+
+    // Buffer `id`, which has the focus or is one of the others
+    fn find_buffer(&self, id: usize) -> &Buffer {
+        self.buffers.iter().find(|buffer| buffer.id == id).unwrap_or(&self.buffer)
+    }
+
+    // The window with focus, as it is drawn
+    fn pane(&self) -> Pane<'_> {
+        Pane { buffer: &self.buffer, view: &self.view, width: self.display_width, height: self.display_height }
+    }
+
+    // Window `id`, as it is drawn
+    fn window_pane(&self, id: usize) -> Pane<'_> {
+        let Some(window) = self.parked.iter().find(|window| window.id == id) else {
+            return self.pane();
+        };
+        let rect = self.text_area(self.windows.rect(id));
+        Pane { buffer: self.find_buffer(window.buffer), view: &window.view, width: rect.width, height: rect.height }
+    }
+
+    fn gutter_width(&self, pane: &Pane) -> usize {
+        // Hex rows start with their offset instead
+        if pane.buffer.hex.is_some() {
+            return 0;
+        }
+        let numbers = if self.options.line_numbers {
+            pane.buffer.document.rows().max(1).to_string().len() + 1
+        } else if self.mark_column(pane) > 0 {
+            1
+        } else {
+            0
+        };
+        self.mark_column(pane) + numbers
+    }
+
+    // Marks are shown in a column of the gutter when the document has any
+    fn mark_column(&self, pane: &Pane) -> usize {
+        if pane.buffer.document.marks.is_empty() { 0 } else { 1 }
+    }
+
+    // Screen columns available for document text
+    fn text_width(&self, pane: &Pane) -> usize {
+        pane.width.saturating_sub(self.gutter_width(pane))
+    }
+
+    // Bytes on a row in hex mode
+    fn hex_per_row(&self, pane: &Pane) -> usize {
+        hex::per_row(self.text_width(pane))
     }
 
     pub fn run(&mut self) {
@@ -90,161 +420,1322 @@ impl Editor {
             }
         }
     }
-    // TODO: FIXME: BACKSPACE ISNT WORKING WEEEEEEE
-    pub fn render_row(&self, line: usize) -> String {
-        let main_row_num = self.display_y + line;
-        let sidebar_row_num = line;
+    // Soft wrap settings, or None when long lines scroll horizontally
+    fn wrap_options(&self, pane: &Pane) -> Option<wrap::WrapOptions> {
+        // Large files scroll by lines, so only the rows shown have to be paged in
+        if !self.options.wrap || pane.buffer.document.is_large() {
+            return None;
+        }
+        Some(wrap::WrapOptions {
+            width: self.text_width(pane).max(1),
+            tab_width: self.options.tab_width,
+            words: self.options.wrap_words,
+            indent: self.options.wrap_indent,
+            marker: self.options.wrap_marker.clone(),
+        })
+    }
+
+    // Every row of the main view
+    fn visible_lines(&self, pane: &Pane) -> Vec<ScreenLine> {
+        if let Some(image) = &pane.buffer.image {
+            return self.image_lines(pane, image);
+        }
+        if let Some(hex) = &pane.buffer.hex {
+            return self.hex_lines(pane, hex);
+        }
+        let mut lines = Vec::with_capacity(pane.height);
+        let matched = self.bracket_match(pane, pane.view.top.saturating_sub(BRACKET_MARGIN)..pane.view.top + pane.height + BRACKET_MARGIN);
+        let block = pane.view.block(&pane.buffer.document, self.options.tab_width);
+        match self.wrap_options(pane) {
+            Some(options) => {
+                let (mut row, mut sub) = (pane.view.top, pane.view.top_sub);
+                while lines.len() < pane.height && row < pane.buffer.document.rows() {
+                    let line = pane.buffer.document.rows[row].to_string();
+                    let segments = wrap::segments(&line, &options);
+                    // Segment and screen column of a character of this row
+                    let locate = |col: usize| {
+                        let sub = wrap::segment_of(&segments, col);
+                        (sub, wrap::column_in_segment(&line, &segments[sub], col, options.tab_width))
+                    };
+                    let bracket = matched.filter(|(r, _)| *r == row).map(|(_, col)| locate(col));
+                    let mut cursors: Vec<(usize, usize)> = pane.view.extra_cursors.iter()
+                        .filter(|cursor| cursor.row == row)
+                        .map(|cursor| locate(cursor.col))
+                        .collect();
+                    let selected = block.filter(|rect| rect.rows().contains(&row)).map(|rect| {
+                        (rect, block::char_range(&pane.buffer.document.rows[row], rect.left, rect.right, options.tab_width))
+                    });
+                    // A block without width shows where typing goes on its other rows
+                    if let Some((_, range)) = selected.as_ref().filter(|(rect, _)| rect.is_empty() && row != pane.view.cursor.row) {
+                        cursors.push(locate(range.start));
+                    }
+                    for (idx, segment) in segments.iter().enumerate().skip(sub).take(pane.height - lines.len()) {
+                        let selection = selected.as_ref().filter(|(rect, _)| !rect.is_empty()).and_then(|(_, range)| {
+                            let (start, end) = (range.start.max(segment.start), range.end.min(segment.end));
+                            (start < end).then(|| {
+                                let column = |col| wrap::column_in_segment(&line, segment, col, options.tab_width);
+                                column(start)..column(end)
+                            })
+                        });
+                        lines.push(ScreenLine {
+                            number: if segment.start == 0 { Some(row) } else { None },
+                            text: Some(wrap::render_segment(&line, segment, &options)),
+                            bracket: bracket.filter(|(sub, _)| *sub == idx).map(|(_, x)| x),
+                            cursors: cursors.iter().filter(|(sub, _)| *sub == idx).map(|(_, x)| *x).collect(),
+                            selection,
+                            ..ScreenLine::default()
+                        });
+                    }
+                    row += 1;
+                    sub = 0;
+                }
+            },
+            None => {
+                let end = (pane.view.top + pane.height).min(pane.buffer.document.rows());
+                let (width, tab_width) = (self.text_width(pane), self.options.tab_width);
+                for row in pane.view.top..end {
+                    let left = if self.options.cursor_line_scroll && row != pane.view.cursor.row { 0 } else { pane.view.left };
+                    let buffer = &pane.buffer.document.rows[row];
+                    let line_width = buffer.visual_width(0, buffer.str_len(), tab_width);
+                    // Screen column of a character of this row, if it is on screen
+                    let locate = |col: usize| {
+                        let x = buffer.visual_width(0, col, tab_width);
+                        (left..left + width).contains(&x).then(|| x - left)
+                    };
+                    let mut cursors: Vec<usize> = pane.view.extra_cursors.iter()
+                        .filter(|cursor| cursor.row == row)
+                        .filter_map(|cursor| locate(cursor.col))
+                        .collect();
+                    let mut selection = None;
+                    match block.filter(|rect| rect.rows().contains(&row)) {
+                        Some(rect) if !rect.is_empty() => {
+                            let range = block::char_range(buffer, rect.left, rect.right, tab_width);
+                            let start = buffer.visual_width(0, range.start, tab_width).max(left);
+                            let end = buffer.visual_width(0, range.end, tab_width).min(left + width);
+                            selection = (start < end).then(|| start - left..end - left);
+                        },
+                        // A block without width shows where typing goes on its other rows
+                        Some(rect) if row != pane.view.cursor.row && (left..left + width).contains(&rect.left) => {
+                            cursors.push(rect.left - left);
+                        },
+                        _ => (),
+                    }
+                    lines.push(ScreenLine {
+                        number: Some(row),
+                        text: Some(buffer.render_cols(left, width, tab_width)),
+                        clipped_left: self.options.cursor_line_scroll && left > 0,
+                        clipped_right: self.options.cursor_line_scroll && line_width > left + width,
+                        bracket: matched.filter(|(r, _)| *r == row).and_then(|(_, col)| locate(col)),
+                        cursors,
+                        selection,
+                        ..ScreenLine::default()
+                    });
+                }
+            },
+        }
+        lines.resize(pane.height, ScreenLine::default());
+        lines
+    }
+
+    // Rows of the main view showing an image, scaled to fit
+    fn image_lines(&self, pane: &Pane, image: &bmp::Image) -> Vec<ScreenLine> {
+        let blank = ScreenLine { text: Some(String::new()), ..ScreenLine::default() };
+        let mut lines: Vec<ScreenLine> = image.cells(self.text_width(pane), pane.height).into_iter()
+            .map(|cells| ScreenLine { cells, ..blank.clone() })
+            .collect();
+        lines.resize(pane.height, blank);
+        lines
+    }
+
+    // Rows of the main view in hex mode. The cursor's byte is also marked in the column
+    // the cursor is not in.
+    fn hex_lines(&self, pane: &Pane, hex: &hex::Hex) -> Vec<ScreenLine> {
+        let per_row = self.hex_per_row(pane);
+        let (cursor_row, idx) = (hex.cursor / per_row, hex.cursor % per_row);
+        let other = if hex.ascii { hex::hex_column(idx) } else { hex::ascii_column(idx, per_row) };
+        let end = hex.rows(per_row).min(hex.top + pane.height);
+        let mut lines: Vec<ScreenLine> = (hex.top..end)
+            .map(|row| ScreenLine {
+                text: Some(hex.render_row(row, per_row)),
+                cursors: if row == cursor_row { vec![other] } else { Vec::new() },
+                ..ScreenLine::default()
+            })
+            .collect();
+        lines.resize(pane.height, ScreenLine::default());
+        lines
+    }
+
+    fn render_row(&self, pane: &Pane, line: usize, main: &ScreenLine) -> String {
+        let main_content_width = pane.width;
+        let gutter_width = self.gutter_width(pane);
+        let tab_width = self.options.tab_width;
     
-        // Reserve 1 column for the scrollbar
-        let main_content_width = self.display_width - 1;
+        let main_content = main.text.clone().unwrap_or_else(|| "~".to_string());
     
-        let main_content = if main_row_num < self.document.rows() {
-            let row = self.document.row(main_row_num).unwrap();
-            row.render(self.display_x, self.display_x + main_content_width)
-        } else {
-            "~".to_string()
-        };
+        let (thumb_height, thumb_pos) = self.calculate_scrollbar(pane);
     
+        let mut rendered = self.theme.switch(Role::Text);
+        if gutter_width > 0 {
+            let numbers_width = gutter_width - self.mark_column(pane);
+            if self.mark_column(pane) > 0 {
+                let mark = main.number.and_then(|row| pane.buffer.document.marks.on_row(row));
+                rendered.push_str(self.theme.style(Role::Mark));
+                rendered.push(mark.unwrap_or(' '));
+            }
+            rendered.push_str(&self.theme.switch(Role::LineNumber));
+            match main.number {
+                Some(row) if self.options.line_numbers => {
+                    rendered.push_str(&format!("{:>width$} ", row + 1, width = numbers_width - 1));
+                },
+                _ => rendered.push_str(&" ".repeat(numbers_width)),
+            }
+            rendered.push_str(&self.theme.switch(Role::Text));
+        }
+        // Pixels are half blocks, the top one in the foreground color
+        for (top, bottom) in &main.cells {
+            let color = |(r, g, b): bmp::Rgb, background| theme::Color::Rgb(r, g, b).downsample(self.theme.support).escape(background);
+            match bottom {
+                Some(bottom) => rendered.push_str(&color(*bottom, true)),
+                None => rendered.push_str(&self.theme.switch(Role::Text)),
+            }
+            rendered.push_str(&color(*top, false));
+            rendered.push('▀');
+        }
+        if !main.cells.is_empty() {
+            rendered.push_str(&self.theme.switch(Role::Text));
+        }
+        let mut text: Vec<char> = main_content.chars().collect();
+        let text_width = main_content_width.saturating_sub(gutter_width);
+        let mut used: usize = main.cells.len() + text.iter().map(|c| util::char_width(*c, 0, tab_width)).sum::<usize>();
+        if main.clipped_right && used < text_width {
+            text.extend(std::iter::repeat_n(' ', text_width - used));
+            used = text_width;
+        }
+        // Cursors past the end of the line still need a cell to be drawn in
+        if let Some(last) = main.cursors.iter().max().filter(|x| **x >= used) {
+            let end = (last + 1).min(text_width);
+            text.extend(std::iter::repeat_n(' ', end.saturating_sub(used)));
+            used = used.max(end);
+        }
+        // Screen column of the character being drawn, which differs from its index after wide characters
+        let mut x = 0;
+        for (idx, c) in text.iter().enumerate() {
+            let cell = x;
+            x += util::char_width(*c, 0, tab_width);
+            // Overflow indicators take the place of the text at a clipped edge
+            let indicator = if idx == 0 && main.clipped_left {
+                Some('«')
+            } else if idx + 1 == text.len() && main.clipped_right {
+                Some('»')
+            } else {
+                None
+            };
+            match indicator {
+                Some(indicator) => {
+                    rendered.push_str(self.theme.style(Role::LineNumber));
+                    rendered.push(indicator);
+                    rendered.push_str(&self.theme.switch(Role::Text));
+                },
+                None if main.cursors.contains(&cell) => {
+                    rendered.push_str(self.theme.style(Role::SecondaryCursor));
+                    rendered.push(*c);
+                    rendered.push_str(&self.theme.switch(Role::Text));
+                },
+                None if main.bracket == Some(cell) => {
+                    rendered.push_str(self.theme.style(Role::MatchingBracket));
+                    rendered.push(*c);
+                    rendered.push_str(&self.theme.switch(Role::Text));
+                },
+                None if main.selection.as_ref().is_some_and(|range| range.contains(&cell)) => {
+                    rendered.push_str(self.theme.style(Role::Selection));
+                    rendered.push(*c);
+                    rendered.push_str(&self.theme.switch(Role::Text));
+                },
+                None => rendered.push(*c),
+            }
+        }
+        // Pad main content to its width
+        for _ in (gutter_width + used)..main_content_width {
+            rendered.push(' ');
+        }
+        // Add scrollbar character if within the thumb
+        if !self.options.scrollbar {
+            return rendered;
+        }
+        if line >= thumb_pos && line < thumb_pos + thumb_height {
+            rendered.push_str(&self.theme.switch(Role::ScrollbarThumb));
+            rendered.push('▓');
+        } else {
+            rendered.push_str(&self.theme.switch(Role::Scrollbar));
+            rendered.push(' ');
+        }
+        rendered
+    }
+
+    fn sidebar_row(&self, sidebar_row_num: usize, sidebar_width: usize) -> String {
+        let tab_width = self.options.tab_width;
+        let mut rendered = String::new();
         let sidebar_content = if sidebar_row_num < self.sidebar.rows() {
             let row = self.sidebar.row(sidebar_row_num).unwrap();
-            row.render(0, self.terminal.width - self.display_width - 3)
+            row.render_cols(0, sidebar_width, tab_width)
         } else {
             "~".to_string()
         };
-    
-        let (thumb_height, thumb_pos) = self.calculate_scrollbar();
-    
-        let mut rendered = String::new();
-        rendered.push_str(&main_content);
-        // Pad main content to its width
-        for _ in main_content.chars().count()..main_content_width {
+        rendered.push_str(&self.theme.switch(Role::Sidebar));
+        rendered.push_str(&sidebar_content);
+        // Pad sidebar content
+        for _ in sidebar_content.chars().count()..sidebar_width {
             rendered.push(' ');
         }
-        // Add scrollbar character if within the thumb
-        if line >= thumb_pos && line < thumb_pos + thumb_height {
-            rendered.push_str("▓");
+        rendered
+    }
+
+    // Every row of the main area: the rows of each window, with the lines between them
+    fn window_rows(&self) -> Vec<String> {
+        let area = self.layout.inner(layout::Panel::Main);
+        let mut pieces: Vec<Vec<(usize, String)>> = vec![Vec::new(); area.height];
+        for (id, rect) in self.windows.windows() {
+            let pane = self.window_pane(*id);
+            for (line, main) in self.visible_lines(&pane).iter().enumerate() {
+                if let Some(row) = pieces.get_mut(rect.y - area.y + line) {
+                    row.push((rect.x, self.render_row(&pane, line, main)));
+                }
+            }
+        }
+        for separator in self.windows.separators() {
+            let rect = separator.rect;
+            match separator.split {
+                window::Split::Columns => {
+                    for row in pieces.iter_mut().skip(rect.y - area.y).take(rect.height) {
+                        row.push((rect.x, format!("{}│", self.theme.switch(Role::Sidebar))));
+                    }
+                },
+                // Each window above the line has its name on it
+                window::Split::Rows => {
+                    let mut line = String::new();
+                    let mut x = rect.x;
+                    while x < rect.x + rect.width {
+                        let above = self.windows.windows().iter()
+                            .find(|(_, above)| above.x == x && above.y + above.height == rect.y);
+                        match above {
+                            Some((id, above)) => {
+                                line.push_str(&self.window_title(*id, above.width));
+                                x += above.width;
+                            },
+                            None => {
+                                line.push_str(&self.theme.switch(Role::Sidebar));
+                                line.push('┴');
+                                x += 1;
+                            },
+                        }
+                    }
+                    if let Some(row) = pieces.get_mut(rect.y - area.y) {
+                        row.push((rect.x, line));
+                    }
+                },
+            }
+        }
+        pieces.into_iter()
+            .map(|mut row| {
+                row.sort_by_key(|(x, _)| *x);
+                row.into_iter().map(|(_, piece)| piece).collect()
+            })
+            .collect()
+    }
+
+    // The name of the file in window `id`, on the line under it
+    fn window_title(&self, id: usize, width: usize) -> String {
+        let (role, buffer) = match self.parked.iter().find(|window| window.id == id) {
+            Some(window) => (Role::Sidebar, window.buffer),
+            None => (Role::StatusBar, self.buffer.id),
+        };
+        let title: String = format!("─ {} ", self.find_buffer(buffer).document.file_path).chars().chain(std::iter::repeat('─')).take(width).collect();
+        format!("{}{}", self.theme.switch(role), title)
+    }
+
+    // The open documents, with the one with focus standing out
+    fn tab_line(&self, width: usize) -> String {
+        let mut ids: Vec<usize> = self.buffers.iter().map(|buffer| buffer.id).chain([self.buffer.id]).collect();
+        ids.sort();
+        let mut rendered = String::new();
+        let mut used = 0;
+        for id in ids {
+            let role = if id == self.buffer.id { Role::StatusBar } else { Role::Sidebar };
+            let name: String = format!(" {} ", self.find_buffer(id).document.file_path).chars().take(width - used).collect();
+            used += name.chars().count();
+            rendered.push_str(&self.theme.switch(role));
+            rendered.push_str(&name);
+        }
+        rendered.push_str(&self.theme.switch(Role::Sidebar));
+        rendered.push_str(&" ".repeat(width - used));
+        rendered
+    }
+
+    pub fn render(&mut self) {
+        self.terminal.clear_screen();
+        self.layout.focus = match self.state {
+            EditorState::EDIT => layout::Panel::Main,
+            EditorState::COMMAND => layout::Panel::Message,
+        };
+
+        for (panel, border, rect) in self.layout.panels().to_vec() {
+            let width = self.layout.inner(panel).width;
+            let rows = match panel {
+                layout::Panel::Main => self.window_rows(),
+                layout::Panel::Sidebar => (0..rect.height).map(|row| self.sidebar_row(row, width)).collect(),
+                layout::Panel::TabLine => vec![self.tab_line(width)],
+                _ => vec![self.status_bar(panel, width)],
+            };
+            let edge = |c: Option<char>| match c {
+                Some(c) if panel == self.layout.focus => format!("{}{}", self.theme.switch(Role::StatusBar), c),
+                Some(c) => format!("{}{}", self.theme.switch(Role::Sidebar), c),
+                None => String::new(),
+            };
+            for (line, row) in rows.iter().enumerate().take(rect.height) {
+                self.terminal.cursor_position(rect.x, rect.y + line);
+                print!("{}{}{}{}", edge(border.left), row, edge(border.right), theme::RESET);
+            }
+        }
+    }
+
+    fn calculate_scrollbar(&self, pane: &Pane) -> (usize, usize) {
+        let (total, top) = if pane.buffer.image.is_some() {
+            // Images are shown whole
+            (1, 0)
+        } else if let Some(hex) = &pane.buffer.hex {
+            (hex.rows(self.hex_per_row(pane)), hex.top)
+        } else if let Some(options) = self.wrap_options(pane) {
+            // With soft wrap the scrollbar measures screen rows rather than lines
+            let (total, above) = pane.buffer.document.wrapped_height(&options, pane.view.top);
+            (total.max(1), above + pane.view.top_sub)
         } else {
-            rendered.push(' ');
+            (pane.buffer.document.rows().max(1), pane.view.top)
+        };
+        let visible = pane.height;
+        let ratio = visible as f32 / total as f32;
+        
+        let thumb_height = (visible as f32 * ratio).ceil() as usize;
+        let thumb_height = thumb_height.clamp(1, visible);
+        
+        let max_scroll = total.saturating_sub(visible);
+        let thumb_pos = (top * (visible - thumb_height))
+            .checked_div(max_scroll)
+            .unwrap_or(0);
+
+        (thumb_height, thumb_pos)
+    }
+
+    pub fn process_keys(&mut self) -> Result<(), std::io::Error> {
+        // Waits no longer than until the next timed job
+        let timeout = [self.swap_due(), self.autosave_due()].into_iter().flatten().min()
+            .map(|due| due.saturating_duration_since(Instant::now()));
+        // While a large file is being indexed the status bar shows how far it got
+        let indexing = self.buffer.document.indexing().map(|_| Duration::from_millis(200));
+        let timeout = timeout.into_iter().chain(indexing).min();
+        let watched: Vec<_> = self.buffer.watcher.iter().map(watch::Watcher::fd)
+            .chain(self.buffer.pipe.iter().map(pipe::Pipe::fd))
+            .chain(self.buffers.iter().filter_map(|buffer| buffer.pipe.as_ref().map(pipe::Pipe::fd)))
+            .collect();
+        let Some(key) = self.terminal.poll_key(timeout, &watched)? else {
+            self.tick();
+            return Ok(());
+        };
+        // Coming back to the editor is when changes made elsewhere are likely
+        if key == terminal::FOCUS_GAINED {
+            self.check_disk();
+            return Ok(());
+        }
+        self.last_key_at = Instant::now();
+        self.pending_keys.push(key);
+
+        // Everything one key does is undone in one step
+        let cursor = self.view.cursor;
+        self.buffer.document.history.begin((cursor.row, cursor.col));
+        self.typed = false;
+        let result = self.dispatch();
+        let cursor = self.view.cursor;
+        self.buffer.document.history.end((cursor.row, cursor.col), self.typed);
+        self.tick();
+        result
+    }
+
+    // When the swap file is next to be written, if there is anything to write
+    fn swap_due(&self) -> Option<Instant> {
+        let changed = self.buffer.document.is_modified() && self.buffer.document.version != self.buffer.swap_version;
+        // Swap files hold text, which is not what hex mode changes
+        (self.buffer.hex.is_none() && self.buffer.write_swap && self.options.swap_file && changed)
+            .then(|| match self.buffer.swapped_at {
+                Some(at) => at + Duration::from_secs(self.options.swap_interval as u64),
+                None => self.last_key_at,
+            })
+    }
+
+    // When to autosave, if it is on and there is anything to save
+    fn autosave_due(&self) -> Option<Instant> {
+        let changed = self.buffer.document.is_modified() && self.buffer.document.version != self.buffer.autosave_version;
+        (self.options.autosave > 0 && changed)
+            .then(|| self.last_key_at + Duration::from_secs(self.options.autosave as u64))
+    }
+
+    // Jobs run between keys: looking at changes to the file, keeping the swap file
+    // current and autosaving.
+    fn tick(&mut self) {
+        // Rows of a large file are kept for a screen above and below the view
+        let top = self.view.top.min(self.view.cursor.row);
+        let keep = top.saturating_sub(self.display_height)..self.view.cursor.row.max(top) + 2 * self.display_height;
+        self.buffer.document.page(keep);
+        self.follow_input();
+        if self.buffer.watcher.as_mut().is_some_and(watch::Watcher::changed) {
+            self.check_disk();
+        }
+        self.update_swap(false);
+        let now = Instant::now();
+        if self.autosave_due().is_some_and(|due| due <= now) {
+            self.buffer.autosave_version = self.buffer.document.version;
+            let text = match self.save() {
+                Ok(true) => format!("Autosaved {}", self.buffer.document.file_path),
+                Ok(false) => String::from("Not autosaved, the file changed on disk"),
+                Err(err) => format!("Autosave failed: {}", err),
+            };
+            self.status_text = util::GapBuffer::from_str(&text);
+        }
+    }
+
+    // Writes the swap file when it is due, or whenever it is behind the text with `now`.
+    fn update_swap(&mut self, now: bool) {
+        let at = Instant::now();
+        if self.swap_due().is_some_and(|due| now || due <= at) {
+            self.buffer.swap_version = self.buffer.document.version;
+            self.buffer.swapped_at = Some(at);
+            if let Err(err) = swap::write(&self.buffer.document.file_path, &self.buffer.document.text()) {
+                self.buffer.write_swap = false;
+                self.status_text = util::GapBuffer::from_str(&format!("Could not write swap file: {}", err));
+            }
+        }
+    }
+
+    // Adds what arrived on standard input since last time, with -f, also while another
+    // document has focus. A cursor on the last line stays on it, so the view keeps up with
+    // the input as tail -f does.
+    fn follow_input(&mut self) {
+        let at_end = self.view.cursor.row + 1 >= self.buffer.document.rows();
+        let mut added = false;
+        for (idx, buffer) in std::iter::once(&mut self.buffer).chain(&mut self.buffers).enumerate() {
+            let Some(reading) = buffer.pipe.as_mut() else {
+                continue;
+            };
+            match reading.read_into(&mut buffer.document) {
+                Ok(lines) => added |= idx == 0 && lines,
+                Err(err) => {
+                    buffer.pipe = None;
+                    self.status_text = util::GapBuffer::from_str(&format!("Could not read standard input: {}", err));
+                },
+            }
+            if buffer.pipe.as_ref().is_some_and(|pipe| pipe.closed) {
+                buffer.pipe = None;
+            }
+        }
+        if added && at_end && self.state == EditorState::EDIT {
+            self.set_cursor(self.buffer.document.rows() - 1, 0);
+        }
+    }
+
+    // Looks for a swap file left by another editor before this one starts writing its own.
+    fn check_swap(&mut self) {
+        let path = self.buffer.document.file_path.clone();
+        let Some(found) = swap::read(&path) else {
+            return;
+        };
+        if !found.is_stale() {
+            self.buffer.write_swap = false;
+            let text = format!("{} is also open in process {}, not writing a swap file", path, found.pid);
+            self.status_text = util::GapBuffer::from_str(&text);
+        } else if found.text == self.buffer.document.text() {
+            swap::remove(&path);
+        } else {
+            self.buffer.write_swap = false;
+            self.prompt = Some(Prompt::Recover(found));
+        }
+    }
+
+    // Looks for changes another program made to the file.
+    fn check_disk(&mut self) {
+        if self.prompt.is_some() || self.buffer.document.is_large() || self.buffer.piped {
+            return;
+        }
+        match self.buffer.document.disk_state() {
+            watch::Disk::Same => (),
+            watch::Disk::Missing => {
+                // Whatever turns up there later is a change too
+                self.buffer.document.disk = None;
+                let text = format!("{} was deleted, saving will create it again", self.buffer.document.file_path);
+                self.status_text = util::GapBuffer::from_str(&text);
+            },
+            watch::Disk::Changed if self.options.auto_reload && !self.buffer.document.is_modified() => {
+                // Undone on its own, like a key
+                let cursor = self.view.cursor;
+                self.buffer.document.history.begin((cursor.row, cursor.col));
+                self.reload();
+                let cursor = self.view.cursor;
+                self.buffer.document.history.end((cursor.row, cursor.col), false);
+            },
+            watch::Disk::Changed => self.prompt = Some(Prompt::Changed),
+        }
+    }
+
+    fn reload(&mut self) {
+        let path = self.buffer.document.file_path.clone();
+        let result = if self.buffer.hex.is_some() { self.reload_hex() } else { self.buffer.document.reload() };
+        match result {
+            Ok(()) => {
+                self.view.extra_cursors.clear();
+                self.view.block_anchor = None;
+                if self.options.detect_indent {
+                    self.buffer.document.detect_indent();
+                }
+                self.set_cursor(self.view.cursor.row, self.view.cursor.col);
+                self.status_text = util::GapBuffer::from_str(&format!("Reloaded {}", path));
+            },
+            Err(err) => self.status_text = util::GapBuffer::from_str(&format!("Could not reload {}: {}", path, err)),
+        }
+    }
+
+    // Reads the bytes again in hex mode, keeping the cursor where it was.
+    fn reload_hex(&mut self) -> Result<(), std::io::Error> {
+        let bytes = std::fs::read(&self.buffer.document.file_path)?;
+        let cursor = self.buffer.hex.as_ref().map_or(0, |hex| hex.cursor);
+        let mut hex = hex::Hex::new(&bytes);
+        hex.move_to(cursor);
+        self.buffer.hex = Some(hex);
+        if self.buffer.image.is_some() {
+            self.buffer.image = bmp::decode(&bytes).ok();
+        }
+        self.buffer.document.synced(&bytes);
+        Ok(())
+    }
+
+    // Shows in the sidebar how the file on disk differs from the text here, or from the
+    // bytes in hex mode.
+    fn show_differences(&mut self) {
+        let path = self.buffer.document.file_path.clone();
+        let disk = std::fs::read(&path).and_then(|bytes| match self.buffer.hex {
+            Some(_) => Ok(hex::dump(&bytes)),
+            None => document::decode(&path, &bytes).map(|(_, text)| text),
+        });
+        let disk = match disk {
+            Ok(disk) => disk,
+            Err(err) => {
+                self.status_text = util::GapBuffer::from_str(&format!("Could not read {}: {}", path, err));
+                return;
+            },
+        };
+        let mut lines = vec![format!("{} on disk:", path)];
+        let here = match &self.buffer.hex {
+            Some(hex) => hex::dump(&hex.bytes.bytes()),
+            None => self.buffer.document.text(),
+        };
+        lines.extend(diff::render(&here, &disk));
+        self.sidebar = document::Document::from_text("differences", &lines.join("\n"));
+        if !self.options.sidebar {
+            self.options.sidebar = true;
+            self.layout();
+        }
+    }
+
+    fn answer(&mut self, prompt: Prompt, keys: &[Key]) {
+        match prompt {
+            Prompt::Recover(found) => self.answer_recovery(found, keys),
+            Prompt::Changed => self.answer_changed(keys),
+        }
+    }
+
+    // Answers the question of what to do with the swap file of a crashed session.
+    fn answer_recovery(&mut self, found: swap::Swap, keys: &[Key]) {
+        match keys {
+            [Key::Char('r')] => {
+                self.buffer.document.replace_text(&found.text);
+                if self.options.detect_indent {
+                    self.buffer.document.detect_indent();
+                }
+                self.set_cursor(self.view.cursor.row, self.view.cursor.col);
+                self.buffer.write_swap = true;
+                self.status_text = util::GapBuffer::from_str("Recovered unsaved changes, save to keep them");
+            },
+            [Key::Char('d')] => {
+                swap::remove(&self.buffer.document.file_path);
+                self.buffer.write_swap = true;
+                self.status_text = util::GapBuffer::from_str("Discarded unsaved changes");
+            },
+            // The swap file is left alone for later, so this session cannot have one
+            [Key::Esc] => {
+                self.status_text = util::GapBuffer::from_str("Swap file kept, this session has none");
+            },
+            _ => self.prompt = Some(Prompt::Recover(found)),
+        }
+    }
+
+    // Answers the question of what to do about another program changing the file.
+    fn answer_changed(&mut self, keys: &[Key]) {
+        match keys {
+            [Key::Char('r')] => self.reload(),
+            // What is on disk now counts as seen, so saving overwrites it
+            [Key::Char('k')] => {
+                self.buffer.document.disk = watch::Stamp::of(&self.buffer.document.file_path);
+                self.status_text = util::GapBuffer::from_str("Keeping this version, saving will overwrite the file");
+            },
+            [Key::Char('d')] => {
+                self.show_differences();
+                self.prompt = Some(Prompt::Changed);
+            },
+            // Saving asks again
+            [Key::Esc] => (),
+            _ => self.prompt = Some(Prompt::Changed),
+        }
+    }
+
+    fn dispatch(&mut self) -> Result<(), std::io::Error> {
+        if let Some(prompt) = self.prompt.take() {
+            let keys = std::mem::take(&mut self.pending_keys);
+            self.answer(prompt, &keys);
+            return Ok(());
+        }
+        if let Some(action) = self.mark_action.take() {
+            let keys = std::mem::take(&mut self.pending_keys);
+            // Anything but a printable key cancels
+            if let [Key::Char(name)] = keys[..] {
+                if !name.is_control() && !name.is_whitespace() {
+                    self.mark(action, name);
+                }
+            }
+            return Ok(());
+        }
+        let bindings = match self.state {
+            EditorState::EDIT => &self.keymap.edit,
+            EditorState::COMMAND => &self.keymap.command,
+        };
+        match bindings.lookup(&self.pending_keys) {
+            Lookup::Action(action) => {
+                self.pending_keys.clear();
+                self.last_action = Some(action);
+                self.perform(action)?;
+            },
+            Lookup::Pending => (),
+            Lookup::Unbound => {
+                // Unbound printable keys are typed, anything else (including broken chords) is dropped
+                let keys = std::mem::take(&mut self.pending_keys);
+                self.last_action = None;
+                // Nothing is typed into a picture
+                if self.state == EditorState::EDIT && self.buffer.image.is_some() {
+                    return Ok(());
+                }
+                if let [Key::Char(c)] = keys[..] {
+                    // q leaves piped text, as it does a pager
+                    if self.state == EditorState::EDIT && self.buffer.piped && self.read_only() && c == 'q' {
+                        return self.perform(Action::Quit);
+                    }
+                    if self.state == EditorState::EDIT && self.refuse_edit() {
+                        return Ok(());
+                    }
+                    if self.state == EditorState::EDIT && self.buffer.hex.is_some() {
+                        self.type_in_hex(c);
+                    } else if self.state == EditorState::EDIT && self.view.block_anchor.is_some() {
+                        self.type_in_block(c);
+                    } else {
+                        self.at_each_cursor(|editor| editor.insert_text(c));
+                        self.typed = self.state == EditorState::EDIT;
+                    }
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn perform(&mut self, action: Action) -> Result<(), std::io::Error> {
+        if self.state == EditorState::EDIT && action.edits() && self.refuse_edit() {
+            return Ok(());
+        }
+        // A picture can only be left
+        if self.state == EditorState::EDIT && self.buffer.image.is_some() && !matches!(action, Action::ToggleMode | Action::Quit)
+            && !action.moves_focus() {
+            return Ok(());
+        }
+        if self.state == EditorState::EDIT && (self.hex_action(action) || self.block_action(action)) {
+            return Ok(());
+        }
+        match action {
+            // With several cursors, Esc first goes back to one
+            Action::ToggleMode if self.state == EditorState::EDIT && !self.view.extra_cursors.is_empty() => {
+                self.view.extra_cursors.clear();
+            },
+            Action::ToggleMode => self.escape(),
+            Action::Quit => {
+                // Every document is put away, the one with focus last
+                for buffer in self.buffers.iter().chain([&self.buffer]) {
+                    buffer.put_away(&self.options);
+                }
+                self.exit = true;
+            },
+            Action::Save => {
+                if self.save()? {
+                    self.buffer.store_marks(&self.options)?;
+                } else {
+                    // Nothing was saved, so there is no message saying it was
+                    self.last_action = None;
+                }
+            },
+            Action::SetMark | Action::JumpToMark if self.state == EditorState::EDIT => self.mark_action = Some(action),
+            Action::SetMark | Action::JumpToMark => (),
+            // Short motions move every cursor, long ones only the main cursor
+            Action::MoveUp | Action::MoveDown | Action::MoveLeft | Action::MoveRight | Action::MoveHome | Action::MoveEnd
+            | Action::MoveWordLeft | Action::MoveWordRight => {
+                self.at_each_cursor(|editor| editor.move_cursor(action));
+            },
+            Action::MoveParagraphUp | Action::MoveParagraphDown | Action::PageUp | Action::PageDown
+            | Action::MoveDocumentStart | Action::MoveDocumentEnd => {
+                self.move_cursor(action);
+            },
+            Action::Backspace => self.at_each_cursor(Self::backspace),
+            Action::Delete => self.at_each_cursor(Self::delete),
+            Action::DeleteWordBackward => self.at_each_cursor(|editor| editor.delete_word(false)),
+            Action::DeleteWordForward => self.at_each_cursor(|editor| editor.delete_word(true)),
+            Action::Enter => self.at_each_cursor(Self::enter),
+            Action::Indent => self.at_each_cursor(Self::indent),
+            Action::Dedent => self.at_each_cursor(Self::dedent),
+            // Extra cursors are only for editing
+            Action::AddCursorAbove | Action::AddCursorBelow | Action::AddCursorAtNextMatch if self.refuse_edit() => (),
+            Action::AddCursorAbove => self.add_cursor_vertical(-1),
+            Action::AddCursorBelow => self.add_cursor_vertical(1),
+            Action::AddCursorAtNextMatch => self.add_cursor_at_next_match(),
+            Action::BlockSelect => self.toggle_block(),
+            Action::Copy => self.copy_block(false),
+            Action::Cut => self.copy_block(true),
+            Action::Paste => self.paste_block(),
+            Action::Undo => self.undo(false),
+            Action::Redo => self.undo(true),
+            Action::MatchBracket => self.jump_to_bracket(),
+            Action::JumpBack => {
+                let cursor = self.view.cursor;
+                if let Some((row, col)) = self.view.jumps.back((cursor.row, cursor.col)) {
+                    self.set_cursor(row, col);
+                }
+            },
+            Action::JumpForward => {
+                if let Some((row, col)) = self.view.jumps.forward() {
+                    self.set_cursor(row, col);
+                }
+            },
+            Action::FocusLeft | Action::FocusRight | Action::FocusUp | Action::FocusDown => {
+                let direction = match action {
+                    Action::FocusLeft => window::Direction::Left,
+                    Action::FocusRight => window::Direction::Right,
+                    Action::FocusUp => window::Direction::Up,
+                    _ => window::Direction::Down,
+                };
+                let rect = self.windows.rect(self.window);
+                let (x, y) = self.cursor_on_screen();
+                if let Some(id) = self.windows.neighbour(self.window, direction, (rect.x + x, rect.y + y)) {
+                    self.focus_window(id);
+                }
+            },
+            Action::FocusNext => self.focus_window(self.windows.next(self.window)),
+        }
+        Ok(())
+    }
+
+    // Whether the text cannot be changed
+    fn read_only(&self) -> bool {
+        self.buffer.document.read_only
+    }
+
+    // Why the text cannot be changed
+    fn read_only_reason(&self) -> &'static str {
+        if self.buffer.document.is_large() {
+            "Large files are read-only"
+        } else if self.buffer.piped {
+            "Text from standard input is read-only"
+        } else {
+            "The file is read-only, :set noreadonly to change it"
+        }
+    }
+
+    // Says so and returns true if the text cannot be changed.
+    fn refuse_edit(&mut self) -> bool {
+        if self.read_only() {
+            self.status_text = util::GapBuffer::from_str(self.read_only_reason());
+        }
+        self.read_only()
+    }
+
+    // :set readonly / :set noreadonly. Large files and piped text stay read-only.
+    fn set_read_only(&mut self, read_only: bool) -> Result<(), String> {
+        if !read_only && (self.buffer.document.is_large() || self.buffer.piped) {
+            return Err(String::from(self.read_only_reason()));
+        }
+        let was = std::mem::replace(&mut self.buffer.document.read_only, read_only);
+        // Changes can be made from now on, so they need a swap file
+        if was && !read_only && self.prompt.is_none() {
+            self.buffer.write_swap = true;
+            self.check_swap();
+        }
+        Ok(())
+    }
+
+    // Does what an action does to the bytes in hex mode. Returns whether the action was
+    // taken care of, which those that mean nothing for bytes are by doing nothing.
+    fn hex_action(&mut self, action: Action) -> bool {
+        let (per_row, height) = (self.hex_per_row(&self.pane()), self.display_height);
+        let Some(hex) = self.buffer.hex.as_mut() else {
+            return false;
+        };
+        let row_start = hex.cursor - hex.cursor % per_row;
+        let changed = match action {
+            Action::ToggleMode | Action::Quit | Action::Save => return false,
+            _ if action.moves_focus() => return false,
+            // From the second digit of a byte, Left goes to its first
+            Action::MoveLeft if hex.low => {
+                hex.low = false;
+                false
+            },
+            Action::MoveLeft => {
+                hex.move_by(-1);
+                false
+            },
+            Action::MoveRight => {
+                hex.move_by(1);
+                false
+            },
+            Action::MoveUp => {
+                hex.move_by(-(per_row as isize));
+                false
+            },
+            Action::MoveDown => {
+                hex.move_by(per_row as isize);
+                false
+            },
+            Action::PageUp => {
+                hex.move_by(-((per_row * height) as isize));
+                false
+            },
+            Action::PageDown => {
+                hex.move_by((per_row * height) as isize);
+                false
+            },
+            Action::MoveHome => {
+                hex.move_to(row_start);
+                false
+            },
+            Action::MoveEnd => {
+                hex.move_to(row_start + per_row - 1);
+                false
+            },
+            Action::MoveDocumentStart => {
+                hex.move_to(0);
+                false
+            },
+            Action::MoveDocumentEnd => {
+                hex.move_to(hex.len());
+                false
+            },
+            // Tab switches between typing digits and typing characters
+            Action::Indent | Action::Dedent => {
+                hex.ascii = !hex.ascii;
+                hex.low = false;
+                false
+            },
+            Action::Backspace => hex.delete(false),
+            Action::Delete => hex.delete(true),
+            Action::Undo => hex.undo(false),
+            Action::Redo => hex.undo(true),
+            _ => false,
+        };
+        if changed {
+            self.buffer.document.changed();
+        }
+        true
+    }
+
+    fn type_in_hex(&mut self, c: char) {
+        let insert = self.options.hex_insert;
+        let Some(hex) = self.buffer.hex.as_mut() else {
+            return;
+        };
+        if hex.type_char(c, insert) {
+            self.buffer.document.changed();
+        } else {
+            self.status_text = util::GapBuffer::from_str("Only hex digits go here, Tab switches to the ASCII column");
+        }
+    }
+
+    // Does what an action does to a block selection instead of at the cursor, if there is
+    // a block and the action works on it. Returns whether the action was taken care of.
+    fn block_action(&mut self, action: Action) -> bool {
+        let tab_width = self.options.tab_width;
+        let Some(rect) = self.view.block(&self.buffer.document, tab_width) else {
+            return false;
+        };
+        match action {
+            Action::ToggleMode => self.view.block_anchor = None,
+            Action::Backspace | Action::Delete if !rect.is_empty() => {
+                block::delete(&mut self.buffer.document, &rect, tab_width);
+                self.reshape_block(rect.left, rect.left);
+            },
+            // Without width, the character before or at the column goes on every row
+            Action::Backspace => {
+                let Some(before) = rect.left.checked_sub(1) else {
+                    return true;
+                };
+                let row = &self.buffer.document.rows[self.view.cursor.row];
+                let range = block::char_range(row, before, rect.left, tab_width);
+                let x = if range.is_empty() { before } else { row.visual_width(0, range.start, tab_width) };
+                block::delete(&mut self.buffer.document, &block::Rect { left: before, ..rect }, tab_width);
+                self.reshape_block(x, x);
+            },
+            Action::Delete => {
+                block::delete(&mut self.buffer.document, &block::Rect { right: rect.left + 1, ..rect }, tab_width);
+                self.reshape_block(rect.left, rect.left);
+            },
+            Action::Indent | Action::Dedent => self.shift_lines(rect.rows(), action == Action::Indent),
+            // These drop the block and then do what they always do
+            Action::Enter | Action::Undo | Action::Redo | Action::AddCursorAbove | Action::AddCursorBelow
+            | Action::AddCursorAtNextMatch => {
+                self.view.block_anchor = None;
+                return false;
+            },
+            _ => return false,
+        }
+        true
+    }
+
+    // Starts a block selection at the cursor, or drops the one being made.
+    fn toggle_block(&mut self) {
+        if self.state != EditorState::EDIT || self.view.block_anchor.take().is_some() {
+            return;
+        }
+        self.view.extra_cursors.clear();
+        let x = self.view.cursor_column(&self.buffer.document, self.options.tab_width);
+        self.view.block_anchor = Some((self.view.cursor.row, x));
+    }
+
+    // Moves the anchor and the cursor of the block to new screen columns on their rows.
+    fn reshape_block(&mut self, anchor_x: usize, cursor_x: usize) {
+        let Some((anchor_row, _)) = self.view.block_anchor else {
+            return;
+        };
+        self.view.block_anchor = Some((anchor_row, anchor_x));
+        let row = self.view.cursor.row;
+        let col = block::char_range(&self.buffer.document.rows[row], cursor_x, cursor_x, self.options.tab_width).start;
+        self.view.cursor = view::Cursor { row, col, goal: Some(cursor_x) };
+    }
+
+    // Typing replaces what is in the block and goes on in a column on all its rows.
+    // An opening bracket or quote goes around the block instead when pairs are on.
+    fn type_in_block(&mut self, c: char) {
+        let tab_width = self.options.tab_width;
+        let Some(rect) = self.view.block(&self.buffer.document, tab_width) else {
+            return;
+        };
+        let pairs = pairs::pairs(&self.buffer.document.file_type);
+        let close = pairs::closer(pairs, c).filter(|_| self.options.auto_pairs && !rect.is_empty());
+        if let Some(close) = close {
+            block::surround(&mut self.buffer.document, &rect, c, close, tab_width);
+            let grown = util::char_width(c, 0, tab_width) + util::char_width(close, 0, tab_width);
+            let grow = |x: usize| if x == rect.right { x + grown } else { x };
+            let anchor_x = self.view.block_anchor.map(|(_, x)| x).unwrap_or(rect.left);
+            let cursor_x = self.view.cursor.goal.unwrap_or(rect.left);
+            return self.reshape_block(grow(anchor_x), grow(cursor_x));
+        }
+        block::delete(&mut self.buffer.document, &rect, tab_width);
+        block::insert(&mut self.buffer.document, rect.rows(), rect.left, &c.to_string(), tab_width);
+        let x = rect.left + util::char_width(c, rect.left, tab_width);
+        self.reshape_block(x, x);
+    }
+
+    fn copy_block(&mut self, cut: bool) {
+        if self.state != EditorState::EDIT {
+            return;
+        }
+        let tab_width = self.options.tab_width;
+        let Some(rect) = self.view.block(&self.buffer.document, tab_width).filter(|rect| !rect.is_empty()) else {
+            self.status_text = util::GapBuffer::from_str("Nothing selected");
+            return;
+        };
+        self.clipboard = block::copy(&self.buffer.document, &rect, tab_width);
+        self.view.block_anchor = None;
+        if cut {
+            block::delete(&mut self.buffer.document, &rect, tab_width);
+        }
+        let col = block::char_range(&self.buffer.document.rows[rect.top], rect.left, rect.left, tab_width).start;
+        self.set_cursor(rect.top, col);
+        let verb = if cut { "Cut" } else { "Copied" };
+        self.status_text = util::GapBuffer::from_str(&format!("{} {} lines", verb, self.clipboard.len()));
+    }
+
+    // Pastes the last block over the block selection, or with its corner at the cursor.
+    fn paste_block(&mut self) {
+        if self.state != EditorState::EDIT {
+            return;
+        }
+        if self.clipboard.is_empty() {
+            self.status_text = util::GapBuffer::from_str("Nothing to paste");
+            return;
+        }
+        let tab_width = self.options.tab_width;
+        let (row, x) = match self.view.block(&self.buffer.document, tab_width) {
+            Some(rect) => {
+                block::delete(&mut self.buffer.document, &rect, tab_width);
+                (rect.top, rect.left)
+            },
+            None => (self.view.cursor.row, self.view.cursor_column(&self.buffer.document, tab_width)),
+        };
+        self.view.block_anchor = None;
+        self.view.extra_cursors.clear();
+        block::paste(&mut self.buffer.document, row, x, &self.clipboard, tab_width);
+        let col = block::char_range(&self.buffer.document.rows[row], x, x, tab_width).start;
+        self.set_cursor(row, col);
+    }
+
+    // Runs `apply` at every cursor in turn. Edits at one cursor move the others along with the text,
+    // and cursors that end up in the same place become one.
+    fn at_each_cursor(&mut self, mut apply: impl FnMut(&mut Self)) {
+        if self.state != EditorState::EDIT || self.view.extra_cursors.is_empty() {
+            return apply(self);
         }
-        rendered.push_str(STATUS_BAR);
-        rendered.push('┊');
-        rendered.push_str(&sidebar_content);
-        // Pad sidebar content
-        for _ in sidebar_content.chars().count()..(self.terminal.width - self.display_width - 3) {
-            rendered.push(' ');
+        let mut cursors = std::mem::take(&mut self.view.extra_cursors);
+        cursors.push(self.view.cursor);
+        self.buffer.document.tracked = cursors.iter().map(|cursor| (cursor.row, cursor.col)).collect();
+        for (idx, cursor) in cursors.iter_mut().enumerate() {
+            let (row, col) = self.buffer.document.tracked[idx];
+            let goal = if (row, col) == (cursor.row, cursor.col) { cursor.goal } else { None };
+            self.view.cursor = view::Cursor { row, col, goal };
+            apply(self);
+            self.buffer.document.tracked[idx] = (self.view.cursor.row, self.view.cursor.col);
+            *cursor = self.view.cursor;
         }
-        rendered.push('┃');
-        rendered.push_str(ANSI_END);
-    
-        rendered
+        // Later edits may have moved cursors that were already done
+        for (cursor, (row, col)) in cursors.iter_mut().zip(std::mem::take(&mut self.buffer.document.tracked)) {
+            if (cursor.row, cursor.col) != (row, col) {
+                *cursor = view::Cursor { row, col, goal: None };
+            }
+        }
+        self.view.cursor = cursors.pop().unwrap();
+        let main = (self.view.cursor.row, self.view.cursor.col);
+        cursors.sort_by_key(|cursor| (cursor.row, cursor.col));
+        cursors.dedup_by_key(|cursor| (cursor.row, cursor.col));
+        cursors.retain(|cursor| (cursor.row, cursor.col) != main);
+        self.view.extra_cursors = cursors;
     }
-    
-    pub fn render(&self) {
-        self.terminal.clear_screen();
-    
-        for line in 0..self.display_height {
-            self.terminal.cursor_position(0, line);
-            self.terminal.clear_line();
-            let rendered = self.render_row(line);
-            println!("{}\r", rendered);
+
+    // Adds a cursor on the line above the topmost cursor or below the bottom one, at the same screen column.
+    fn add_cursor_vertical(&mut self, delta: isize) {
+        if self.state != EditorState::EDIT {
+            return;
+        }
+        let main = self.view.cursor;
+        let all = self.view.extra_cursors.iter().copied().chain([main]);
+        let edge = if delta < 0 { all.min_by_key(|c| (c.row, c.col)) } else { all.max_by_key(|c| (c.row, c.col)) };
+        self.view.cursor = edge.unwrap();
+        self.view.move_vertical(&self.buffer.document, delta, self.options.tab_width);
+        let added = self.view.cursor;
+        self.view.cursor = main;
+        if added.row != edge.unwrap().row {
+            self.view.extra_cursors.push(added);
         }
-        self.status_bar(0, &termion::event::Key::Null);
-        self.status_bar(1, &termion::event::Key::Null);
-        self.status_bar(2, self.key_pressed.as_ref().unwrap());
     }
 
-    fn calculate_scrollbar(&self) -> (usize, usize) {
-        let total = self.file_rows.max(1);
-        let visible = self.display_height;
-        let ratio = visible as f32 / total as f32;
-        
-        let thumb_height = (visible as f32 * ratio).ceil() as usize;
-        let thumb_height = thumb_height.clamp(1, visible);
-        
-        let max_scroll = total.saturating_sub(visible);
-        let thumb_pos = if max_scroll > 0 {
-            (self.display_y * (visible - thumb_height)) / max_scroll
-        } else {
-            0
+    // Adds a cursor at the next whole-word occurrence of the word under the main cursor,
+    // searching on from the cursor added last and wrapping around the document.
+    fn add_cursor_at_next_match(&mut self) {
+        if self.state != EditorState::EDIT {
+            return;
+        }
+        let main = self.view.cursor;
+        let line = self.buffer.document.rows[main.row].to_string();
+        let Some((start, end)) = motion::word_at(&line, main.col) else {
+            self.status_text = util::GapBuffer::from_str("No word under the cursor");
+            return;
+        };
+        let word: String = line.chars().skip(start).take(end - start).collect();
+        let offset = main.col - start;
+        let last = self.view.extra_cursors.last().copied().unwrap_or(main);
+        let taken = |row: usize, col: usize| {
+            (main.row, main.col) == (row, col) || self.view.extra_cursors.iter().any(|c| (c.row, c.col) == (row, col))
         };
 
-        (thumb_height, thumb_pos)
+        let rows = self.buffer.document.rows();
+        // The row of the last cursor comes up twice: after it first, then before it once the search wraps
+        for step in 0..=rows {
+            let row = (last.row + step) % rows;
+            let line = self.buffer.document.rows[row].to_string();
+            let found = motion::word_occurrences(&line, &word).into_iter().find(|&start| {
+                let after_last = start + offset > last.col;
+                let in_range = match step {
+                    0 => after_last,
+                    _ if step == rows => !after_last,
+                    _ => true,
+                };
+                in_range && !taken(row, start + offset)
+            });
+            if let Some(start) = found {
+                self.view.extra_cursors.push(view::Cursor { row, col: start + offset, goal: None });
+                return;
+            }
+        }
+        self.status_text = util::GapBuffer::from_str(&format!("No more occurrences of {}", word));
     }
 
-    pub fn process_keys(&mut self) -> Result<(), std::io::Error> {
-        let key = self.terminal.read_key()?;
-        self.key_pressed = Some(key);
-        match key {
-            termion::event::Key::Esc => {
-                self.escape();
-            }, 
-            termion::event::Key::Ctrl('q') => {
-                self.exit = true;
-            },
-            termion::event::Key::Ctrl('s') => {
-                self.save()?;
-            },
-            termion::event::Key::Up | termion::event::Key::Down | termion::event::Key::Left | termion::event::Key::Right | termion::event::Key::Home | termion::event::Key::End => {
-                self.move_cursor(key);
-            },
-            termion::event::Key::Backspace => {
-                self.backspace();
-            },
-            termion::event::Key::Delete => {
-                self.delete();
+    // The bracket paired with the one under the cursor, or else with the one just before it,
+    // looked for in `rows`
+    fn bracket_match(&self, pane: &Pane, rows: std::ops::Range<usize>) -> Option<(usize, usize)> {
+        // Finding strings and comments means reading the file from the start
+        if pane.buffer.document.is_large() {
+            return None;
+        }
+        let cursor = pane.view.cursor;
+        let rows = rows.start.min(cursor.row)..rows.end.max(cursor.row + 1);
+        let states = pane.buffer.document.line_states(rows.end);
+        motion::matching_bracket(&pane.buffer.document, &states, cursor.row, cursor.col, rows.clone()).or_else(|| {
+            let col = cursor.col.checked_sub(1)?;
+            motion::matching_bracket(&pane.buffer.document, &states, cursor.row, col, rows)
+        })
+    }
+
+    fn jump_to_bracket(&mut self) {
+        if self.state != EditorState::EDIT {
+            return;
+        }
+        match self.bracket_match(&self.pane(), 0..self.buffer.document.rows()) {
+            Some((row, col)) => self.jump(row, col),
+            None => self.status_text = util::GapBuffer::from_str("No matching bracket"),
+        }
+    }
+
+    // Moves the cursor far away, remembering where it was in the jump list.
+    fn jump(&mut self, row: usize, col: usize) {
+        let cursor = self.view.cursor;
+        self.view.jumps.record((cursor.row, cursor.col));
+        self.set_cursor(row, col);
+    }
+
+    fn go_to(&mut self, position: cli::Position) {
+        let rows = self.buffer.document.rows();
+        let (row, col) = match position {
+            cli::Position::Line(line, col) => (line.saturating_sub(1), col.unwrap_or(1).saturating_sub(1)),
+            // Rounded up, so 1% of a long document is not the first line
+            cli::Position::Percent(percent) => ((percent * rows).div_ceil(100).saturating_sub(1), 0),
+            cli::Position::LastLine => (rows.saturating_sub(1), 0),
+        };
+        self.jump(row, col);
+    }
+
+    fn undo(&mut self, redo: bool) {
+        self.view.extra_cursors.clear();
+        let cursor = if redo { self.buffer.document.redo() } else { self.buffer.document.undo() };
+        match cursor {
+            Some((row, col)) => {
+                if self.options.detect_indent {
+                    self.buffer.document.detect_indent();
+                }
+                self.set_cursor(row, col);
             },
-            termion::event::Key::Char('\n') => {
-                self.enter();
+            None => {
+                let text = if redo { "Already at newest change" } else { "Already at oldest change" };
+                self.status_text = util::GapBuffer::from_str(text);
             },
-            termion::event::Key::Char(_) => {
-                self.insert_text(key);
+        }
+    }
+
+    // Writes the file, unless another program changed it since it was read or written,
+    // in which case it asks what to do. Returns whether the file was written.
+    pub fn save(&mut self) -> Result<bool, std::io::Error> {
+        if self.refuse_edit() {
+            return Ok(false);
+        }
+        if self.buffer.document.disk_state() == watch::Disk::Changed {
+            self.prompt = Some(Prompt::Changed);
+            return Ok(false);
+        }
+        let result = match &self.buffer.hex {
+            Some(hex) => self.buffer.document.save_bytes(&hex.bytes.bytes()),
+            None => self.buffer.document.save(),
+        };
+        match result {
+            // The text does not fit the encoding, and the file was left alone
+            Err(err) if err.kind() == std::io::ErrorKind::InvalidData => {
+                self.status_text = util::GapBuffer::from_str(&format!("Not saved: {}", err));
+                return Ok(false);
             },
-            _ => (),
+            result => result?,
         }
-        Ok(())
+        // Everything in the swap file is in the file now
+        if self.buffer.write_swap {
+            swap::remove(&self.buffer.document.file_path);
+            self.buffer.swapped_at = None;
+        }
+        Ok(true)
     }
 
-    pub fn save(&self) -> Result<(), std::io::Error> {
-        self.document.save()
+    fn mark(&mut self, action: Action, name: char) {
+        let cursor = self.view.cursor;
+        match action {
+            Action::SetMark => {
+                self.buffer.document.marks.set(name, cursor.row, cursor.col);
+                self.status_text = util::GapBuffer::from_str(&format!("Mark {} set", name));
+            },
+            _ => match self.buffer.document.marks.get(name) {
+                Some((row, col)) => self.jump(row, col),
+                None => self.status_text = util::GapBuffer::from_str(&format!("No mark {}", name)),
+            },
+        }
     }
 
-    pub fn insert_text(&mut self, key: termion::event::Key) {
+    pub fn insert_text(&mut self, c: char) {
         match self.state {
             EditorState::EDIT => {
-                self.insert_text_edit(key);
+                self.insert_text_edit(c);
             },
             EditorState::COMMAND => {
-                self.insert_text_command(key);
+                self.insert_text_command(c);
             },
         }
     }
 
-    fn insert_text_edit(&mut self, key: termion::event::Key) {
-        match key {
-            termion::event::Key::Char(c) => {
-                let row = self.document.row_mut(self.display_y + self.cursor_y).unwrap();
-                row.insert_char(self.cursor_x, c);
-                self.cursor_x = self.cursor_x.saturating_add(1);
-            },
-            _ => (),
+    fn insert_text_edit(&mut self, c: char) {
+        let cursor = self.view.cursor;
+        let mut col = cursor.col;
+        let line: Vec<char> = self.buffer.document.rows[cursor.row].to_string().chars().collect();
+        let pairs = pairs::pairs(&self.buffer.document.file_type);
+        // Typing the closer that is already under the cursor steps over it
+        if self.options.auto_pairs && pairs::is_closer(pairs, c) && line.get(col) == Some(&c) {
+            return self.set_cursor(cursor.row, col + 1);
+        }
+        if self.options.auto_indent && indent::rules(&self.buffer.document.file_type).closers.contains(&c) {
+            let before: String = line[..col].iter().collect();
+            // A closer typed first on a line takes it back a level
+            if !before.is_empty() && before.trim().is_empty() {
+                let width = indent::shallower(indent::width(&before, self.options.tab_width), self.indent_level());
+                col = col.saturating_add_signed(self.reindent(cursor.row, width));
+            }
+        }
+        self.buffer.document.insert_char(cursor.row, col, c);
+        let (before, after) = (line[..cursor.col].last().copied(), line.get(cursor.col).copied());
+        if self.options.auto_pairs && pairs::should_pair(pairs, c, before, after) {
+            self.buffer.document.insert_char(cursor.row, col + 1, pairs::closer(pairs, c).unwrap());
         }
+        self.set_cursor(cursor.row, col + 1);
     }
 
-    fn insert_text_command(&mut self, key: termion::event::Key) {
-        match key {
-            termion::event::Key::Char(c) => {
-                self.status_text.insert_char(self.cursor_x, c);
-                self.cursor_x = self.cursor_x.saturating_add(1);
-            },
-            _ => (),
-        }
+    fn insert_text_command(&mut self, c: char) {
+        let offset = self.status_text.byte_offset(self.command_x);
+        self.status_text.insert_char(offset, c);
+        self.command_x += 1;
     }
 
     pub fn escape(&mut self) {
         match self.state {
             EditorState::EDIT => {
                 self.state = EditorState::COMMAND;
-                self.cursor_x = 0;
+                self.status_text = util::GapBuffer::from_str("");
+                self.command_x = 0;
             }
             EditorState::COMMAND => {
                 self.state = EditorState::EDIT;
@@ -263,62 +1754,30 @@ impl Editor {
         }
     }
 
-
-    // FIXME: Backspace command is not working, when backspace is pressed a big chunk of trailing whitespace is deleted.
     fn backspace_command(&mut self) {
-        if self.cursor_x == 0 { return }
-        self.status_text.remove_char(self.cursor_x);
-        self.cursor_x = self.cursor_x.saturating_sub(1);
+        if self.command_x == 0 { return }
+        self.command_x -= 1;
+        self.status_text.remove_chars(self.command_x..self.command_x + 1);
     }
 
-    //TODO: Pressing backspace and insertion in rapid succession causes the next character to be deleted also.
-    //TODO: Pressing backspace also removes a huge chunk of whitespace and offsets the sidebar.
-    //TODO: does not handle backspace at the beginning of the line
     fn backspace_edit(&mut self) {
-        let row = self.document.row_mut(self.display_y + self.cursor_y).unwrap();
-        if self.cursor_x > 0 {
-            row.remove_char(self.display_x + self.cursor_x - 1);
-            if self.display_x == 0 {
-                self.cursor_x = self.cursor_x.saturating_sub(1);
-            }
-            else {
-                self.display_x = self.display_x.saturating_sub(1);
-            }
-        }
-        else if self.cursor_x == 0 {
-            // line merging with previous line
-            let current_row = self.display_y + self.cursor_y;
-            if current_row > 0 {
-                // get current line content
-                let current_content = self.document.rows[current_row].to_string();
-                
-                // Remove current line
-                self.document.rows.remove(current_row);
-                
-                // Get previous line references
-                let prev_row = current_row - 1;
-                if let Some(prev_line) = self.document.rows.get_mut(prev_row) {
-                    // Get previous line's character count and byte length
-                    let prev_char_len = prev_line.str_len();
-                    let prev_byte_len = prev_line.buffer_length();
-                    
-                    // Insert current content at end of previous line
-                    prev_line.insert(prev_byte_len, current_content.as_bytes());
-                    
-                    // Update cursor position
-                    if self.display_y == 0 {
-                        self.cursor_y = self.cursor_y.saturating_sub(1);
-                    }
-                    else {
-                        self.display_y = self.display_y.saturating_sub(1);
-                    }
-                    self.cursor_x = prev_char_len;
-                    
-                    // Update document dimensions
-                    self.file_rows = self.document.rows();
-                    self.file_cols = self.document.cols();
-                }
-            }
+        let cursor = self.view.cursor;
+        let line: Vec<char> = self.buffer.document.rows[cursor.row].to_string().chars().collect();
+        let pairs = pairs::pairs(&self.buffer.document.file_type);
+        let empty_pair = cursor.col > 0
+            && pairs::closer(pairs, line[cursor.col - 1]).is_some_and(|close| line.get(cursor.col) == Some(&close));
+        if self.options.auto_pairs && empty_pair {
+            // Deleting the opener of an empty pair takes the closer with it
+            self.buffer.document.remove_chars(cursor.row, cursor.col - 1..cursor.col + 1);
+            self.set_cursor(cursor.row, cursor.col - 1);
+        } else if cursor.col > 0 {
+            self.buffer.document.remove_chars(cursor.row, cursor.col - 1..cursor.col);
+            self.set_cursor(cursor.row, cursor.col - 1);
+        } else if cursor.row > 0 {
+            // Merge with the previous line
+            let prev_len = self.row_len(cursor.row - 1);
+            self.buffer.document.join_lines(cursor.row - 1);
+            self.set_cursor(cursor.row - 1, prev_len);
         }
     }
 
@@ -333,41 +1792,54 @@ impl Editor {
         }
     }
 
-    // TODO: Implement delete command because its not working
     fn delete_command(&mut self) {
-        self.status_text.remove_char(self.cursor_x);
+        self.status_text.remove_chars(self.command_x..self.command_x + 1);
     }
 
     fn delete_edit(&mut self) {
-        let row = self.document.row_mut(self.display_y + self.cursor_y).unwrap();
-        if self.cursor_x < row.str_len() {
-            row.remove_char(self.display_x + self.cursor_x);
-        }
-        else if self.cursor_x == row.str_len() {
-            // line merging with next line
-            let current_row = self.display_y + self.cursor_y;
-            if current_row < self.file_rows - 1 {
-                // get current line content
-                let current_content = self.document.rows[current_row].to_string();
-                
-                // Remove current line
-                self.document.rows.remove(current_row);
-                
-                // Get next line references
-                let next_row = current_row;
-                if let Some(next_line) = self.document.rows.get_mut(next_row) {
-                    // Get next line's character count and byte length
-                    let next_char_len = next_line.str_len();
-                    let next_byte_len = next_line.buffer_length();
-                    
-                    // Insert current content at beginning of next line
-                    next_line.insert(0, current_content.as_bytes());
-                    
-                    // Update document dimensions
-                    self.file_rows = self.document.rows();
-                    self.file_cols = self.document.cols();
+        let cursor = self.view.cursor;
+        if cursor.col < self.row_len(cursor.row) {
+            self.buffer.document.remove_chars(cursor.row, cursor.col..cursor.col + 1);
+        } else {
+            // Merge with the next line
+            self.buffer.document.join_lines(cursor.row);
+        }
+        self.set_cursor(cursor.row, cursor.col);
+    }
+
+    // Deletes from the cursor to the start of the previous word, or to the end of the next one.
+    // At the edge of a line it joins lines like Backspace and Delete do.
+    fn delete_word(&mut self, forward: bool) {
+        match self.state {
+            EditorState::EDIT => {
+                let cursor = self.view.cursor;
+                let line = self.buffer.document.rows[cursor.row].to_string();
+                let len = line.chars().count();
+                if forward && cursor.col >= len {
+                    self.delete_edit();
+                } else if !forward && cursor.col == 0 {
+                    self.backspace_edit();
+                } else if forward {
+                    let end = motion::next_word_end(&line, cursor.col).unwrap_or(len);
+                    self.buffer.document.remove_chars(cursor.row, cursor.col..end);
+                    self.set_cursor(cursor.row, cursor.col);
+                } else {
+                    let start = motion::prev_word_start(&line, cursor.col).unwrap_or(0);
+                    self.buffer.document.remove_chars(cursor.row, start..cursor.col);
+                    self.set_cursor(cursor.row, start);
                 }
-            }
+            },
+            EditorState::COMMAND => {
+                let line = self.status_text.to_string();
+                if forward {
+                    let end = motion::next_word_end(&line, self.command_x).unwrap_or(line.chars().count());
+                    self.status_text.remove_chars(self.command_x..end);
+                } else {
+                    let start = motion::prev_word_start(&line, self.command_x).unwrap_or(0);
+                    self.status_text.remove_chars(start..self.command_x);
+                    self.command_x = start;
+                }
+            },
         }
     }
 
@@ -383,137 +1855,642 @@ impl Editor {
     }
 
     fn enter_command(&mut self) {
-        let text = format!("Executed {}", self.status_text.to_string());
+        let command = self.status_text.to_string();
+        let text = match self.execute_command(command.trim().trim_start_matches(':')) {
+            Ok(text) => text,
+            Err(text) => format!("Error: {}", text),
+        };
         self.status_text = util::GapBuffer::from_str(&text);
-        self.cursor_x = 0;
+        self.command_x = 0;
+    }
+
+    // Runs a command line and returns the message to show in the status line.
+    fn execute_command(&mut self, command: &str) -> Result<String, String> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        // :LINE, :LINE:COL and :PERCENT% go to a position and back to editing
+        if let Some(position) = cli::parse_position(name) {
+            self.go_to(position);
+            self.state = EditorState::EDIT;
+            return Ok(String::new());
+        }
+        match name {
+            "" => Ok(String::new()),
+            "map" => self.map_command(args.trim()),
+            "set" | "set?" => self.set_command(args.trim()),
+            "w" | "w!" => self.write_command(name == "w!"),
+            "retab" => self.retab_command(args.trim()),
+            "hex" => self.hex_command(),
+            "split" | "sp" => self.split_command(window::Split::Rows, args.trim()),
+            "vsplit" | "vs" => self.split_command(window::Split::Columns, args.trim()),
+            "close" | "close!" | "clo" | "clo!" => self.close_command(name.ends_with('!')),
+            "resize" => self.resize_command(window::Split::Rows, args.trim()),
+            "vresize" => self.resize_command(window::Split::Columns, args.trim()),
+            "mark" | "delmark" | "marks" => self.marks_command(name, args.trim()),
+            _ => Err(format!("Unknown command: {}", name)),
+        }
+    }
+
+    // :set                 list every option
+    // :set name? / :set name   show an option (booleans are switched on by the second form)
+    // :set name=value / :set noname
+    fn set_command(&mut self, args: &str) -> Result<String, String> {
+        if args.is_empty() {
+            let mut all: Vec<String> = config::OPTION_NAMES.iter()
+                .map(|name| format!("{}={}", name, self.options.get(name).unwrap()))
+                .collect();
+            all.push(format!("encoding={}", self.buffer.document.encoding.name()));
+            all.push(format!("readonly={}", self.buffer.document.read_only));
+            return Ok(all.join(" "));
+        }
+
+        let mut shown = Vec::new();
+        for arg in args.split_whitespace() {
+            // The encoding and read-only flag belong to the document rather than the options
+            if let Some(name) = arg.strip_prefix("encoding=") {
+                self.convert_encoding(name)?;
+                continue;
+            }
+            match arg {
+                "encoding" | "encoding?" => {
+                    shown.push(format!("encoding={}", self.buffer.document.encoding.name()));
+                    continue;
+                },
+                "readonly?" => {
+                    shown.push(format!("readonly={}", self.buffer.document.read_only));
+                    continue;
+                },
+                "readonly" | "noreadonly" => {
+                    self.set_read_only(arg == "readonly")?;
+                    continue;
+                },
+                _ => (),
+            }
+            if let Some((name, value)) = arg.split_once('=') {
+                // Bare words are accepted as strings, e.g. :set theme=light
+                let value = config::parse_value(value).unwrap_or_else(|_| config::Value::Str(String::from(value)));
+                self.options.set(name, &value)?;
+                self.apply_option(name)?;
+                continue;
+            }
+            let name = arg.trim_end_matches('?');
+            match self.options.get(name) {
+                Some(config::Value::Bool(_)) if !arg.ends_with('?') => {
+                    self.options.set(name, &config::Value::Bool(true))?;
+                    self.apply_option(name)?;
+                },
+                Some(value) => shown.push(format!("{}={}", name, value)),
+                None => match name.strip_prefix("no") {
+                    Some(flag) if matches!(self.options.get(flag), Some(config::Value::Bool(_))) => {
+                        self.options.set(flag, &config::Value::Bool(false))?;
+                        self.apply_option(flag)?;
+                    },
+                    _ => return Err(format!("unknown option `{}`", name)),
+                },
+            }
+        }
+        Ok(shown.join(" "))
+    }
+
+    // :w saves; :w! saves a read-only document too, if its file can be written
+    fn write_command(&mut self, force: bool) -> Result<String, String> {
+        // Anything save has to say replaces the command line
+        self.status_text = util::GapBuffer::from_str("");
+        let saved = if force && !self.buffer.document.is_large() && !self.buffer.piped {
+            let read_only = std::mem::replace(&mut self.buffer.document.read_only, false);
+            let saved = self.save();
+            self.buffer.document.read_only = read_only;
+            saved
+        } else {
+            self.save()
+        };
+        if !saved.map_err(|err| err.to_string())? {
+            return Ok(self.status_text.to_string());
+        }
+        self.buffer.store_marks(&self.options).map_err(|err| err.to_string())?;
+        Ok(format!("File saved to {}.", self.buffer.document.file_path))
+    }
+
+    // :set encoding=NAME writes the file in another encoding when it is next saved
+    fn convert_encoding(&mut self, name: &str) -> Result<(), String> {
+        let encoding = encoding::Encoding::from_name(name)
+            .ok_or_else(|| format!("unknown encoding `{}`, expected one of {}", name, encoding::NAMES.join(", ")))?;
+        if self.read_only() {
+            return Err(String::from(self.read_only_reason()));
+        }
+        if self.buffer.hex.is_some() {
+            return Err(String::from("not in hex mode"));
+        }
+        self.buffer.document.set_encoding(encoding)
+    }
+
+    // :hex switches between editing the text and editing the bytes it is saved as, or
+    // from a picture to its bytes. Going back to the text needs the bytes to still be text.
+    fn hex_command(&mut self) -> Result<String, String> {
+        if self.buffer.document.is_large() {
+            return Err(String::from(self.read_only_reason()));
+        }
+        if self.buffer.image.take().is_some() {
+            self.state = EditorState::EDIT;
+            return Ok(String::new());
+        }
+        // The bytes have one cursor, so they are shown in one window
+        if self.buffer.hex.is_none() && !self.buffer.document.windows.is_empty() {
+            return Err(String::from("the text is in more than one window"));
+        }
+        let Some(hex) = self.buffer.hex.take() else {
+            let bytes = self.buffer.document.encoding.encode(&self.buffer.document.text())?;
+            self.buffer.hex = Some(hex::Hex::new(&bytes));
+            self.view.extra_cursors.clear();
+            self.view.block_anchor = None;
+            self.state = EditorState::EDIT;
+            return Ok(String::new());
+        };
+        let bytes = hex.bytes.bytes();
+        let current = self.buffer.document.encoding;
+        let decoded = match encoding::is_binary(&bytes) {
+            true => None,
+            false => current.decode(&bytes).map(|text| (current, text)).or_else(|| encoding::detect(&bytes)),
+        };
+        let Some((encoding, text)) = decoded else {
+            self.buffer.hex = Some(hex);
+            return Err(String::from("the bytes are not text"));
+        };
+        // Looking at the bytes is not a change in itself
+        let saved = !self.buffer.document.is_modified();
+        if text != self.buffer.document.text() {
+            self.buffer.document.replace_text(&text);
+        }
+        self.buffer.document.encoding = encoding;
+        if saved {
+            self.buffer.document.mark_saved();
+        }
+        self.set_cursor(self.view.cursor.row, self.view.cursor.col);
+        self.state = EditorState::EDIT;
+        Ok(String::new())
+    }
+
+    // :split [FILE] and :vsplit [FILE] open a window above or left of the one with focus,
+    // onto FILE or the same document, and move the focus to it.
+    fn split_command(&mut self, split: window::Split, path: &str) -> Result<String, String> {
+        if path.is_empty() && (self.buffer.hex.is_some() || self.buffer.image.is_some()) {
+            return Err(String::from("bytes and pictures are shown in one window"));
+        }
+        let same = |other: &str| other == path
+            || std::fs::canonicalize(other).ok().is_some_and(|other| std::fs::canonicalize(path).ok() == Some(other));
+        let open = match path {
+            "" => Some(self.buffer.id),
+            _ if same(&self.buffer.document.file_path) => Some(self.buffer.id),
+            _ => self.buffers.iter().find(|buffer| same(&buffer.document.file_path)).map(|buffer| buffer.id),
+        };
+        // Opened before splitting, so a file that cannot be opened leaves the windows alone
+        let mut errors = Vec::new();
+        let opened = match open {
+            Some(_) => None,
+            None => Some(Buffer::open(self.next_buffer, path, &self.options, &mut errors).map_err(|err| err.to_string())?),
+        };
+        let Some(id) = self.windows.split(self.window, split) else {
+            return Err(String::from("not enough room to split the window"));
+        };
+        let mut view = view::View::default();
+        let buffer = match opened {
+            Some(opened) => {
+                self.next_buffer += 1;
+                self.buffers.push(opened);
+                self.next_buffer - 1
+            },
+            // The new window starts out where this one is
+            None if open == Some(self.buffer.id) => {
+                view.cursor = self.view.cursor;
+                (view.top, view.top_sub, view.left) = (self.view.top, self.view.top_sub, self.view.left);
+                self.buffer.id
+            },
+            None => open.unwrap_or(self.buffer.id),
+        };
+        self.parked.push(window::Window { id, buffer, view });
+        self.layout();
+        self.focus_window(id);
+        if open.is_none() {
+            self.start_document();
+        }
+        self.state = EditorState::EDIT;
+        Ok(errors.join(", "))
+    }
+
+    // :close closes the window with focus, and :close! does so even when it is the last
+    // one onto a document with unsaved changes, which are then lost.
+    fn close_command(&mut self, force: bool) -> Result<String, String> {
+        let last = self.parked.iter().all(|window| window.buffer != self.buffer.id);
+        if last && self.buffer.document.is_modified() && !force && self.windows.len() > 1 {
+            return Err(String::from("the text has unsaved changes, :w saves them and :close! throws them away"));
+        }
+        let closing = (self.window, self.buffer.id);
+        let Some(next) = self.windows.close(closing.0) else {
+            return Err(String::from("this is the only window"));
+        };
+        if last {
+            self.buffer.put_away(&self.options);
+        }
+        self.layout();
+        self.switch_window(next);
+        self.parked.retain(|window| window.id != closing.0);
+        if last {
+            self.buffers.retain(|buffer| buffer.id != closing.1);
+        } else if closing.1 == self.buffer.id {
+            self.buffer.document.windows.remove(&closing.0);
+        } else if let Some(buffer) = self.buffers.iter_mut().find(|buffer| buffer.id == closing.1) {
+            buffer.document.windows.remove(&closing.0);
+        }
+        self.check_disk();
+        self.state = EditorState::EDIT;
+        Ok(String::new())
+    }
+
+    // :resize [+|-]N and :vresize [+|-]N make the window with focus N rows tall or N
+    // columns wide, or that much larger or smaller.
+    fn resize_command(&mut self, split: window::Split, args: &str) -> Result<String, String> {
+        let (name, current) = match split {
+            window::Split::Rows => ("resize", self.windows.rect(self.window).height),
+            window::Split::Columns => ("vresize", self.windows.rect(self.window).width),
+        };
+        let size: isize = args.parse().map_err(|_| format!("usage: {} [+|-]N", name))?;
+        let delta = if args.starts_with(['+', '-']) { size } else { size - current as isize };
+        if !self.windows.resize(self.window, split, delta) {
+            return Err(format!("no window {} to take the space from", if name == "resize" { "above or below" } else { "beside this one" }));
+        }
+        self.layout();
+        Ok(String::new())
+    }
+
+    // :retab [tabs | spaces [width]]
+    // Converts the indentation of every line, by default to the style set in the options.
+    fn retab_command(&mut self, args: &str) -> Result<String, String> {
+        if self.read_only() {
+            return Err(String::from(self.read_only_reason()));
+        }
+        let target = match args.split_whitespace().collect::<Vec<&str>>()[..] {
+            [] => self.configured_indent(),
+            ["tabs"] => indent::Style::Tabs,
+            ["spaces"] => indent::Style::Spaces(self.options.indent_width),
+            ["spaces", width] => match width.parse::<usize>() {
+                Ok(width) if (1..=16).contains(&width) => indent::Style::Spaces(width),
+                _ => return Err(format!("invalid indent width `{}`", width)),
+            },
+            _ => return Err(String::from("usage: retab [tabs | spaces [width]]")),
+        };
+        let tab_width = self.options.tab_width;
+        let from = self.indent_style().level(tab_width);
+        let to = target.level(tab_width);
+        for row in 0..self.buffer.document.rows() {
+            let width = indent::width(indent::leading(&self.buffer.document.rows[row].to_string()), tab_width);
+            // Whole levels are converted, anything past the last level is kept as it is
+            let new = indent::make(width / from * to + width % from, target.expand_tab(), tab_width);
+            self.replace_indent(row, &new);
+        }
+        self.buffer.document.indent = Some(target);
+        let cursor = self.view.cursor;
+        self.set_cursor(cursor.row, cursor.col);
+        Ok(format!("Indentation converted to {}", target))
+    }
+
+    // :mark <name>     set a mark at the cursor
+    // :delmark <name>  remove a mark
+    // :marks           list the marks and their lines
+    fn marks_command(&mut self, name: &str, args: &str) -> Result<String, String> {
+        let mut chars = args.chars();
+        let mark = match (chars.next(), chars.next()) {
+            (Some(mark), None) if !mark.is_control() => Some(mark),
+            (None, _) => None,
+            _ => return Err(format!("invalid mark name `{}`", args)),
+        };
+        match (name, mark) {
+            ("mark", Some(mark)) => {
+                let cursor = self.view.cursor;
+                self.buffer.document.marks.set(mark, cursor.row, cursor.col);
+                Ok(format!("Mark {} set", mark))
+            },
+            ("delmark", Some(mark)) if self.buffer.document.marks.remove(mark) => Ok(format!("Mark {} removed", mark)),
+            ("delmark", Some(mark)) => Err(format!("no mark {}", mark)),
+            ("marks", None) if self.buffer.document.marks.is_empty() => Ok(String::from("No marks")),
+            ("marks", None) => {
+                let marks: Vec<String> = self.buffer.document.marks.iter()
+                    .map(|(mark, (row, col))| format!("{} {}:{}", mark, row + 1, col + 1))
+                    .collect();
+                Ok(marks.join("  "))
+            },
+            _ => Err(format!("usage: {}", if name == "marks" { "marks" } else { "mark|delmark <name>" })),
+        }
+    }
+
+    // Brings the editor state in line with an option that was just changed.
+    fn apply_option(&mut self, name: &str) -> Result<(), String> {
+        match name {
+            "theme" => self.theme = theme::Theme::load(&self.options.theme, self.theme.support)?,
+            "colors" => {
+                let support = theme::ColorSupport::from_name(&self.options.colors).unwrap_or(self.theme.support);
+                self.theme.set_support(support);
+            },
+            // Choosing an indentation explicitly overrides what was found in the file
+            "expand_tab" | "indent_width" => self.buffer.document.indent = None,
+            // What was found is kept but not used while detection is off
+            "detect_indent" if self.options.detect_indent => self.buffer.document.detect_indent(),
+            "detect_indent" => (),
+            "swap_file" if !self.options.swap_file && self.buffer.write_swap => {
+                swap::remove(&self.buffer.document.file_path);
+                self.buffer.swap_version = 0;
+                self.buffer.swapped_at = None;
+            },
+            "persist_marks" if self.options.persist_marks && self.buffer.document.marks.is_empty() && !self.buffer.piped => {
+                self.buffer.document.marks = marks::load(&self.buffer.document.file_path);
+            },
+            _ => self.layout(),
+        }
+        Ok(())
+    }
+
+    // :map [edit|command] <keys> <action>
+    fn map_command(&mut self, args: &str) -> Result<String, String> {
+        let (mode, rest) = match args.split_once(' ') {
+            Some(("edit", rest)) => ("edit", rest),
+            Some(("command", rest)) => ("command", rest),
+            _ => ("edit", args),
+        };
+        let (keys, action) = rest.trim().rsplit_once(' ').ok_or("usage: map [edit|command] <keys> <action>")?;
+        let bindings = match mode {
+            "command" => &mut self.keymap.command,
+            _ => &mut self.keymap.edit,
+        };
+        keymap::bind(bindings, keys, action)?;
+        Ok(format!("Mapped {} to {} in {} mode", keys.trim(), action, mode))
     }
 
     fn enter_edit(&mut self) {
-        let actual_row = self.display_y + self.cursor_y;
-        let current_col = self.cursor_x;
-        
-        self.document.new_line(actual_row, current_col);
+        let cursor = self.view.cursor;
+        let line = self.buffer.document.rows[cursor.row].to_string();
+        self.buffer.document.new_line(cursor.row, cursor.col);
+        if !self.options.auto_indent {
+            return self.set_cursor(cursor.row + 1, 0);
+        }
 
-        self.file_rows = self.document.rows();
-        self.file_cols = self.document.cols();
-        
-        self.cursor_x = 0;
-        self.display_y = self.display_y.saturating_add(1);
+        let (before, after) = line.split_at(self.buffer.document.rows[cursor.row].buffer_length());
+        let base = indent::width(indent::leading(before), self.options.tab_width);
+        let opener = indent::ends_with_opener(before, indent::rules(&self.buffer.document.file_type));
+        let width = match opener {
+            Some(_) => indent::deeper(base, self.indent_level()),
+            None => base,
+        };
+        // A line left holding nothing but indentation is cleared
+        if before.trim().is_empty() {
+            self.reindent(cursor.row, 0);
+        }
+        let col = (self.reindent(cursor.row + 1, width) + indent::leading(after).chars().count() as isize) as usize;
+        // Enter between a pair such as {} puts the closer on a line of its own
+        if opener.and_then(indent::closer_for).is_some_and(|closer| after.trim_start().starts_with(closer)) {
+            self.buffer.document.new_line(cursor.row + 1, col);
+            self.reindent(cursor.row + 2, base);
+        }
+        self.set_cursor(cursor.row + 1, col);
+    }
+
+    fn configured_indent(&self) -> indent::Style {
+        if self.options.expand_tab {
+            indent::Style::Spaces(self.options.indent_width)
+        } else {
+            indent::Style::Tabs
+        }
+    }
+
+    // The indentation used in the document: what was found in it, or else the configured one
+    fn indent_style(&self) -> indent::Style {
+        self.buffer.document.indent.filter(|_| self.options.detect_indent).unwrap_or_else(|| self.configured_indent())
+    }
+
+    // Columns in one level of indentation
+    fn indent_level(&self) -> usize {
+        self.indent_style().level(self.options.tab_width)
+    }
+
+    // Replaces the leading whitespace of a row with an indent `width` columns wide.
+    // Returns how many characters longer the row got.
+    fn reindent(&mut self, row: usize, width: usize) -> isize {
+        let new = indent::make(width, self.indent_style().expand_tab(), self.options.tab_width);
+        self.replace_indent(row, &new)
+    }
+
+    fn replace_indent(&mut self, row: usize, new: &str) -> isize {
+        let line = self.buffer.document.rows[row].to_string();
+        let old = indent::leading(&line);
+        if old == new {
+            return 0;
+        }
+        let old = old.chars().count();
+        self.buffer.document.remove_chars(row, 0..old);
+        self.buffer.document.insert_str(row, 0, new);
+        new.chars().count() as isize - old as isize
+    }
+
+    // Indents or dedents every row in `rows` by a level, keeping the cursor on the same text.
+    fn shift_lines(&mut self, rows: std::ops::Range<usize>, deeper: bool) {
+        let cursor = self.view.cursor;
+        let mut col = cursor.col;
+        for row in rows {
+            let line = self.buffer.document.rows[row].to_string();
+            if deeper && line.trim().is_empty() {
+                continue;
+            }
+            let current = indent::width(indent::leading(&line), self.options.tab_width);
+            let width = if deeper {
+                indent::deeper(current, self.indent_level())
+            } else {
+                indent::shallower(current, self.indent_level())
+            };
+            let change = self.reindent(row, width);
+            if row == cursor.row {
+                col = col.saturating_add_signed(change);
+            }
+        }
+        self.set_cursor(cursor.row, col);
+    }
+
+    pub fn indent(&mut self) {
+        match self.state {
+            EditorState::EDIT => {
+                // Pads to the next indent stop, or inserts a tab
+                let cursor = self.view.cursor;
+                let text = if self.indent_style().expand_tab() {
+                    let column = self.view.cursor_column(&self.buffer.document, self.options.tab_width);
+                    " ".repeat(indent::deeper(column, self.indent_level()) - column)
+                } else {
+                    String::from("\t")
+                };
+                self.buffer.document.insert_str(cursor.row, cursor.col, &text);
+                self.set_cursor(cursor.row, cursor.col + text.chars().count());
+            },
+            EditorState::COMMAND => self.insert_text_command('\t'),
+        }
     }
 
-    pub fn move_cursor(&mut self, key: termion::event::Key) {
+    pub fn dedent(&mut self) {
+        if self.state == EditorState::EDIT {
+            let row = self.view.cursor.row;
+            self.shift_lines(row..row + 1, false);
+        }
+    }
+
+    pub fn move_cursor(&mut self, action: Action) {
         match self.state {
             EditorState::EDIT => {
-                self.move_cursor_edit(key);
+                self.move_cursor_edit(action);
             },
             EditorState::COMMAND => {
-                self.move_cursor_command(key);
+                self.move_cursor_command(action);
             },
         }
     }
 
-    pub fn move_cursor_command(&mut self, key: termion::event::Key) {
+    pub fn move_cursor_command(&mut self, action: Action) {
+        let len = self.status_text.str_len();
+        match action {
+            Action::MoveLeft => self.command_x = self.command_x.saturating_sub(1),
+            Action::MoveRight => self.command_x = (self.command_x + 1).min(len),
+            Action::MoveHome => self.command_x = 0,
+            Action::MoveEnd => self.command_x = len,
+            Action::MoveWordLeft => {
+                self.command_x = motion::prev_word_start(&self.status_text.to_string(), self.command_x).unwrap_or(0);
+            },
+            Action::MoveWordRight => {
+                self.command_x = motion::next_word_end(&self.status_text.to_string(), self.command_x).unwrap_or(len);
+            },
+            _ => (),
+        }
     }
 
-    // TODO: Fix the cursor state save where it should return to previous position
-    // Error occurs when moving up and down not corresponding to the previous position.
-    pub fn move_cursor_edit(&mut self, key: termion::event::Key) {
-        let min_y = self.display_height/2;
-        let min_x = 0;
-        let max_y = self.file_rows - self.display_height/2;
-        let max_x = self.file_cols;
+    // Moves the cursor to a document position and forgets the goal column.
+    fn set_cursor(&mut self, row: usize, col: usize) {
+        self.view.set_cursor(&self.buffer.document, row, col);
+    }
 
-        match key {
-            termion::event::Key::Up => {
-                    self.display_y = self.display_y.saturating_sub(1);
-                    if self.display_y == 0 {
-                        self.cursor_y = self.cursor_y.saturating_sub(1);
-                    }
-                    let curr_row_len = self.document.row(self.display_y+self.cursor_y).unwrap().str_len();
-                    if self.cursor_x <= curr_row_len {
-                        match self.previous_positions.pop_back() {
-                            Some((x, _)) => {
-                                self.cursor_x = x;
-                            },
-                            None => (),
-                        }
-                    }
-                    if self.cursor_x > curr_row_len {
-                        self.previous_positions.push_back((self.cursor_x, self.cursor_y + 1));
-                        self.cursor_x = curr_row_len;
-                    }
-            },
-            termion::event::Key::Down => {
-                if self.display_y == max_y - 1 { // -1 because of 0 indexing
-                    return;
-                }
+    fn row_len(&self, row: usize) -> usize {
+        self.buffer.document.row(row).map(|r| r.str_len()).unwrap_or(0)
+    }
 
-                if self.display_y < max_y && self.cursor_y == min_y {
-                    self.display_y = self.display_y.saturating_add(1);
-                }
-                if self.display_y == 0 {
-                    self.cursor_y = self.cursor_y.saturating_add(1);
-                }
-                let curr_row_len = self.document.row(self.display_y + self.cursor_y).unwrap().str_len();
-                if self.cursor_x <= curr_row_len {
-                    match self.previous_positions.pop_back() {
-                        Some((x, _)) => {
-                            self.cursor_x = x;
-                        },
-                        None => (),
-                    }
-                }
-                if self.cursor_x > curr_row_len {
-                    self.previous_positions.push_back((self.cursor_x, self.cursor_y - 1));
-                    self.cursor_x = curr_row_len;
-                }
-            },
-            termion::event::Key::Left => {
-                if self.cursor_x > min_x {
-                    self.cursor_x = self.cursor_x.saturating_sub(1);
-                }
-                if self.cursor_x == min_x && self.display_x > 0 {
-                    self.display_x = self.display_x.saturating_sub(1);
+    pub fn move_cursor_edit(&mut self, action: Action) {
+        let view::Cursor { row, col, .. } = self.view.cursor;
+        let last_row = self.buffer.document.rows().saturating_sub(1);
+        let tab_width = self.options.tab_width;
+        let page = self.display_height as isize;
+        let blank = |r: usize| motion::is_blank(&self.buffer.document.rows[r].to_string());
+        // Paging is a jump, so the jump list can come back from it
+        if matches!(action, Action::PageUp | Action::PageDown) {
+            self.view.jumps.record((row, col));
+        }
+        if let Some(options) = self.wrap_options(&self.pane()) {
+            // With soft wrap vertical motions go by screen rows
+            let delta = match action {
+                Action::MoveUp => -1,
+                Action::MoveDown => 1,
+                Action::PageUp => -page,
+                Action::PageDown => page,
+                _ => 0,
+            };
+            if delta != 0 {
+                return self.view.move_visual(&self.buffer.document, delta, &options);
+            }
+        }
+        let (row, col) = match action {
+            // Vertical motions keep the goal column
+            Action::MoveUp => return self.view.move_vertical(&self.buffer.document, -1, tab_width),
+            Action::MoveDown => return self.view.move_vertical(&self.buffer.document, 1, tab_width),
+            Action::PageUp => return self.view.move_vertical(&self.buffer.document, -page, tab_width),
+            Action::PageDown => return self.view.move_vertical(&self.buffer.document, page, tab_width),
+            Action::MoveLeft if col > 0 => (row, col - 1),
+            Action::MoveLeft if row > 0 => (row - 1, self.row_len(row - 1)),
+            Action::MoveRight if col < self.row_len(row) => (row, col + 1),
+            Action::MoveRight if row < last_row => (row + 1, 0),
+            Action::MoveHome => (row, 0),
+            Action::MoveEnd => (row, self.row_len(row)),
+            Action::MoveWordLeft => {
+                match motion::prev_word_start(&self.buffer.document.rows[row].to_string(), col) {
+                    Some(start) => (row, start),
+                    None if col > 0 => (row, 0),
+                    None if row > 0 => (row - 1, self.row_len(row - 1)),
+                    None => (row, col),
                 }
-                self.previous_positions = VecDeque::new();
             },
-            termion::event::Key::Right => {
-                if self.cursor_x == self.document.row(self.display_y + self.cursor_y).unwrap().str_len() {
-                    return;
+            Action::MoveWordRight => {
+                match motion::next_word_end(&self.buffer.document.rows[row].to_string(), col) {
+                    Some(end) => (row, end),
+                    None if col < self.row_len(row) => (row, self.row_len(row)),
+                    None if row < last_row => (row + 1, 0),
+                    None => (row, col),
                 }
-                if self.cursor_x < self.display_width - 1 {
-                    self.cursor_x = self.cursor_x.saturating_add(1);
-                }
-                if self.cursor_x == self.display_width - 1 && self.display_x < max_x {
-                    self.display_x = self.display_x.saturating_add(1);
-                }
-                self.previous_positions = VecDeque::new();
-            },
-            termion::event::Key::Home => {
-                self.cursor_x = 0;
-                self.display_x = 0;
-                self.previous_positions = VecDeque::new();
-            },
-            termion::event::Key::End => {
-                self.cursor_x = self.document.row(self.display_y + self.cursor_y).unwrap().str_len();
-                self.display_x = self.cursor_x.saturating_sub(self.display_width);
-                self.previous_positions = VecDeque::new();
             },
-            _ => (),
+            Action::MoveParagraphUp => return self.jump(motion::prev_paragraph(row, blank), 0),
+            Action::MoveParagraphDown => return self.jump(motion::next_paragraph(self.buffer.document.rows(), row, blank), 0),
+            Action::MoveDocumentStart => return self.jump(0, 0),
+            Action::MoveDocumentEnd => return self.jump(last_row, self.row_len(last_row)),
+            _ => (row, col),
+        };
+        self.set_cursor(row, col);
+    }
+
+    // Scrolls the main view so the cursor is on screen.
+    fn scroll(&mut self) {
+        let per_row = self.hex_per_row(&self.pane());
+        if let Some(hex) = self.buffer.hex.as_mut() {
+            return hex.scroll(self.display_height, per_row);
         }
+        let policy = view::ScrollPolicy {
+            scrolloff: self.options.scrolloff,
+            center: self.options.center_cursor,
+            line_only: self.options.cursor_line_scroll,
+        };
+        if let Some(options) = self.wrap_options(&self.pane()) {
+            return self.view.scroll_wrapped(&self.buffer.document, self.display_height, &options, &policy);
+        }
+        let text_width = self.text_width(&self.pane()).max(1);
+        self.view.scroll_to_cursor(&self.buffer.document, self.display_height, text_width, self.options.tab_width, &policy);
     }
 
-    pub fn status_bar(&self, row: usize, key: &termion::event::Key) {
+    fn status_bar(&self, panel: layout::Panel, width: usize) -> String {
         let mut status = String::new();
 
-        match row {
-            0 => {
-                status.push_str(format!("File: {} - {} lines", self.document.file_path, self.file_rows).as_str());
+        match panel {
+            layout::Panel::Status => match (&self.buffer.image, &self.buffer.hex) {
+                (Some(image), _) => {
+                    status.push_str(format!("File: {} - {}x{} image", self.buffer.document.file_path, image.width, image.height).as_str());
+                    status.push_str(" - :hex edits its bytes");
+                },
+                (None, Some(hex)) => {
+                    status.push_str(format!("File: {} - {} bytes - Offset {:#x}", self.buffer.document.file_path, hex.len(), hex.cursor).as_str());
+                    status.push_str(if self.options.hex_insert { " - Hex, insert" } else { " - Hex, overwrite" });
+                    if hex.ascii {
+                        status.push_str(" - typing ASCII");
+                    }
+                },
+                (None, None) => {
+                    let cursor = self.view.cursor;
+                    status.push_str(format!("File: {} - {} lines", self.buffer.document.file_path, self.buffer.document.rows()).as_str());
+                    status.push_str(format!(" - Ln {}, Col {}", cursor.row + 1, cursor.col + 1).as_str());
+                    status.push_str(format!(" - {}", self.indent_style()).as_str());
+                    if self.buffer.document.encoding != encoding::Encoding::Utf8 {
+                        status.push_str(format!(" - {}", self.buffer.document.encoding.name()).as_str());
+                    }
+                    if self.buffer.document.line_ending == "\r\n" {
+                        status.push_str(" - CRLF");
+                    }
+                    match self.buffer.document.indexing() {
+                        Some(percent) => status.push_str(format!(" - indexing {}%", percent).as_str()),
+                        None if self.buffer.document.is_large() => status.push_str(" - large file, read-only"),
+                        None if self.buffer.pipe.is_some() => status.push_str(" - following input"),
+                        None if self.buffer.document.read_only => status.push_str(" - read-only"),
+                        None => (),
+                    }
+                },
             },
-            1 => {
-                status.push_str(format!("{}", self.status_text).as_str());
+            layout::Panel::Message => {
+                status.push_str(self.status_text.to_string().as_str());
             },
-            2 => {
+            layout::Panel::Mode => {
                 match self.state {
                     EditorState::EDIT => {
                         status.push_str("EDIT MODE");
@@ -522,25 +2499,38 @@ impl Editor {
                         status.push_str("COMMAND MODE");
                     },
                 }
-                match key {
-                    termion::event::Key::Ctrl('s') => {
-                        status.push_str(format!("File saved to {}.", self.document.file_path).as_str());
+                if !self.pending_keys.is_empty() {
+                    let keys: Vec<String> = self.pending_keys.iter().map(keymap::key_name).collect();
+                    status.push_str(format!(" {}-", keys.join(" ")).as_str());
+                }
+                match self.prompt {
+                    Some(Prompt::Recover(_)) => {
+                        status.push_str(" Unsaved changes from a crash: r recover, d discard, Esc later");
+                    },
+                    Some(Prompt::Changed) => {
+                        status.push_str(" File changed on disk: r reload, k keep mine, d differences, Esc later");
                     },
-                    _ => {},
+                    None => (),
+                }
+                if let Some(action) = self.mark_action {
+                    status.push_str(format!(" {}: press the mark's key", action.name()).as_str());
+                }
+                if self.last_action == Some(Action::Save) {
+                    status.push_str(format!(" - File saved to {}.", self.buffer.document.file_path).as_str());
                 }
             },
             _ => {},
         }
         
-        for _ in 0..self.terminal.width - status.to_string().chars().count() {
-            status.push(' ');
-        }
-        print!("{}{}{}", STATUS_BAR, status, ANSI_END);
+        let status: String = status.chars().chain(std::iter::repeat(' ')).take(width).collect();
+        format!("{}{}", self.theme.switch(Role::StatusBar), status)
     }
 
     pub fn refresh_screen(&mut self) -> Result<(), std::io::Error> {
         self.terminal.hide_cursor();
-        self.terminal.cursor_position(0, self.display_height/2);
+        self.terminal.cursor_position(0, 0);
+        self.scroll();
+        self.follow_edits();
 
         if self.exit {
             self.terminal.clear_screen();
@@ -550,18 +2540,53 @@ impl Editor {
             self.render();
             match self.state {
                 EditorState::EDIT => {
-                    self.terminal.cursor_position(self.cursor_x, self.cursor_y);
+                    let rect = self.windows.rect(self.window);
+                    let (x, y) = self.cursor_on_screen();
+                    self.terminal.cursor_position(rect.x + x, rect.y + y);
                 },
                 EditorState::COMMAND => {
-                    self.terminal.cursor_position(self.cursor_x, self.terminal.height - 3);
+                    let x = self.status_text.visual_width(0, self.command_x, self.options.tab_width);
+                    let line = self.layout.inner(layout::Panel::Message);
+                    self.terminal.cursor_position(line.x + x, line.y);
                 },
             }
         }
-        self.terminal.show_cursor();
+        // There is nowhere to put it on a picture
+        if self.buffer.image.is_none() || self.state == EditorState::COMMAND {
+            self.terminal.show_cursor();
+        }
         self.terminal.flush()
     }
 
+    // Where the cursor is within the window with focus
+    fn cursor_on_screen(&self) -> (usize, usize) {
+        let (x, y) = match self.wrap_options(&self.pane()) {
+            _ if self.buffer.hex.is_some() => {
+                let hex = self.buffer.hex.as_ref().unwrap();
+                let (per_row, idx) = (self.hex_per_row(&self.pane()), hex.cursor % self.hex_per_row(&self.pane()));
+                let x = if hex.ascii { hex::ascii_column(idx, per_row) } else { hex::hex_column(idx) + usize::from(hex.low) };
+                (x, hex.cursor / per_row - hex.top)
+            },
+            Some(options) => {
+                let (pos, x) = self.view.cursor_visual(&self.buffer.document, &options);
+                let top = (self.view.top, self.view.top_sub);
+                (x, wrap::distance(&self.buffer.document, top, pos, self.display_height, &options))
+            },
+            None => {
+                let x = self.view.cursor_column(&self.buffer.document, self.options.tab_width) - self.view.left;
+                (x, self.view.cursor.row - self.view.top)
+            },
+        };
+        (self.gutter_width(&self.pane()) + x, y)
+    }
+
     fn kill(&self, err: std::io::Error) {
+        // Whatever was not saved, in any buffer, is left for the next session to recover
+        for buffer in std::iter::once(&self.buffer).chain(&self.buffers) {
+            if buffer.hex.is_none() && buffer.write_swap && self.options.swap_file && buffer.document.is_modified() {
+                let _ = swap::write(&buffer.document.file_path, &buffer.document.text());
+            }
+        }
         self.terminal.clear_screen();
         panic!("Error: {}", err);
     }
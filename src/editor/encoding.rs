@@ -0,0 +1,167 @@
+// Character encodings of files. Text is kept as UTF-8 while it is edited and turned back
+// into the encoding the file had when it is saved, so files that are not UTF-8 keep their
+// encoding and characters that are not ASCII are not lost.
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Encoding {
+    #[default]
+    Utf8,
+    // UTF-8 starting with a byte order mark
+    Utf8Bom,
+    Utf16Le,
+    Utf16Be,
+    Latin1,
+}
+
+pub const NAMES: [&str; 5] = ["utf-8", "utf-8-bom", "utf-16le", "utf-16be", "latin-1"];
+
+const UTF8_BOM: &[u8] = b"\xEF\xBB\xBF";
+const UTF16LE_BOM: &[u8] = b"\xFF\xFE";
+const UTF16BE_BOM: &[u8] = b"\xFE\xFF";
+
+// Files with a NUL byte this close to the start are taken to be binary, as git does
+const SNIFF_LEN: usize = 8000;
+
+impl Encoding {
+    pub fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().replace('_', "-").as_str() {
+            "utf-8" | "utf8" => Some(Encoding::Utf8),
+            "utf-8-bom" | "utf8-bom" => Some(Encoding::Utf8Bom),
+            "utf-16le" | "utf16le" => Some(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Some(Encoding::Utf16Be),
+            "latin-1" | "latin1" | "iso-8859-1" => Some(Encoding::Latin1),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Encoding::Utf8 => NAMES[0],
+            Encoding::Utf8Bom => NAMES[1],
+            Encoding::Utf16Le => NAMES[2],
+            Encoding::Utf16Be => NAMES[3],
+            Encoding::Latin1 => NAMES[4],
+        }
+    }
+
+    // The text of a file in this encoding, or None if the bytes are not valid in it.
+    // The byte order mark, if the encoding has one, is left out of the text.
+    pub fn decode(self, bytes: &[u8]) -> Option<String> {
+        match self {
+            Encoding::Utf8 => String::from_utf8(bytes.to_vec()).ok(),
+            Encoding::Utf8Bom => String::from_utf8(bytes.strip_prefix(UTF8_BOM)?.to_vec()).ok(),
+            Encoding::Utf16Le => decode_utf16(bytes.strip_prefix(UTF16LE_BOM)?, u16::from_le_bytes),
+            Encoding::Utf16Be => decode_utf16(bytes.strip_prefix(UTF16BE_BOM)?, u16::from_be_bytes),
+            // Every byte is the character with the same number
+            Encoding::Latin1 => Some(bytes.iter().map(|b| char::from(*b)).collect()),
+        }
+    }
+
+    // The bytes written to a file for `text`, or why it cannot be written in this encoding.
+    pub fn encode(self, text: &str) -> Result<Vec<u8>, String> {
+        match self {
+            Encoding::Utf8 => Ok(text.as_bytes().to_vec()),
+            Encoding::Utf8Bom => Ok([UTF8_BOM, text.as_bytes()].concat()),
+            Encoding::Utf16Le => Ok(UTF16LE_BOM.iter().copied().chain(text.encode_utf16().flat_map(u16::to_le_bytes)).collect()),
+            Encoding::Utf16Be => Ok(UTF16BE_BOM.iter().copied().chain(text.encode_utf16().flat_map(u16::to_be_bytes)).collect()),
+            Encoding::Latin1 => text.chars()
+                .map(|c| u8::try_from(c).map_err(|_| unwritable(text, c, self)))
+                .collect(),
+        }
+    }
+}
+
+fn decode_utf16(bytes: &[u8], unit: fn([u8; 2]) -> u16) -> Option<String> {
+    if !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let units = bytes.chunks_exact(2).map(|pair| unit([pair[0], pair[1]]));
+    char::decode_utf16(units).collect::<Result<String, _>>().ok()
+}
+
+fn unwritable(text: &str, c: char, encoding: Encoding) -> String {
+    let line = text.lines().position(|line| line.contains(c)).unwrap_or(0) + 1;
+    format!("`{}` on line {} cannot be written in {}", c, line, encoding.name())
+}
+
+// Whether the start of a file looks like binary data rather than text
+pub fn is_binary(bytes: &[u8]) -> bool {
+    !bytes.starts_with(UTF16LE_BOM) && !bytes.starts_with(UTF16BE_BOM)
+        && bytes[..bytes.len().min(SNIFF_LEN)].contains(&0)
+}
+
+// The encoding of a file from its first bytes, for files too large to read whole. Files
+// in UTF-16 are not told apart, as they cannot be paged in.
+pub fn detect_start(bytes: &[u8]) -> Encoding {
+    if bytes.starts_with(UTF8_BOM) {
+        return Encoding::Utf8Bom;
+    }
+    match std::str::from_utf8(bytes) {
+        // The last character may be cut off where the bytes end
+        Err(err) if err.error_len().is_some() => Encoding::Latin1,
+        _ => Encoding::Utf8,
+    }
+}
+
+// The encoding of a file and its text, or None for binary files. Files with a byte order
+// mark are in the encoding it names, others are UTF-8 if they can be and Latin-1 if not,
+// as any bytes are valid Latin-1.
+pub fn detect(bytes: &[u8]) -> Option<(Encoding, String)> {
+    if is_binary(bytes) {
+        return None;
+    }
+    let encoding = if bytes.starts_with(UTF8_BOM) {
+        Encoding::Utf8Bom
+    } else if bytes.starts_with(UTF16LE_BOM) {
+        Encoding::Utf16Le
+    } else if bytes.starts_with(UTF16BE_BOM) {
+        Encoding::Utf16Be
+    } else if std::str::from_utf8(bytes).is_ok() {
+        Encoding::Utf8
+    } else {
+        Encoding::Latin1
+    };
+    encoding.decode(bytes).map(|text| (encoding, text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_encodings() {
+        assert_eq!(detect("héllo".as_bytes()), Some((Encoding::Utf8, String::from("héllo"))));
+        assert_eq!(detect(b"\xEF\xBB\xBFhi"), Some((Encoding::Utf8Bom, String::from("hi"))));
+        assert_eq!(detect(b"\xFF\xFEh\0i\0"), Some((Encoding::Utf16Le, String::from("hi"))));
+        assert_eq!(detect(b"\xFE\xFF\0h\0i"), Some((Encoding::Utf16Be, String::from("hi"))));
+        assert_eq!(detect(b"caf\xE9"), Some((Encoding::Latin1, String::from("café"))));
+        assert_eq!(detect(b"\x7FELF\0\x01"), None);
+    }
+
+    #[test]
+    fn detects_the_start_of_large_files() {
+        assert_eq!(detect_start(b"\xEF\xBB\xBFhi"), Encoding::Utf8Bom);
+        assert_eq!(detect_start(b"caf\xE9 au lait"), Encoding::Latin1);
+        // A character cut off at the end is still UTF-8
+        assert_eq!(detect_start(b"caf\xC3"), Encoding::Utf8);
+    }
+
+    #[test]
+    fn encodes_back_to_the_same_bytes() {
+        for bytes in [&b"\xEF\xBB\xBFa\r\nb"[..], b"\xFF\xFEa\0\r\0\n\0", b"\xFE\xFF\xD8\x3D\xDE\x00", b"\xFF\xE9"] {
+            let (encoding, text) = detect(bytes).unwrap();
+            assert_eq!(encoding.encode(&text).as_deref(), Ok(bytes));
+        }
+        assert_eq!(Encoding::Latin1.encode("a\nb€"), Err(String::from("`€` on line 2 cannot be written in latin-1")));
+        assert_eq!(Encoding::Utf16Le.decode(b"\xFF\xFEa"), None);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for name in NAMES {
+            assert_eq!(Encoding::from_name(name).map(Encoding::name), Some(name));
+        }
+        assert_eq!(Encoding::from_name("ISO_8859-1"), Some(Encoding::Latin1));
+        assert_eq!(Encoding::from_name("ebcdic"), None);
+    }
+}
@@ -0,0 +1,281 @@
+use crate::editor::util::GapBuffer;
+
+// Editing the bytes of a file in hexadecimal. Each row shows the offset of its first byte,
+// the bytes in hex and the same bytes as ASCII, with a dot for anything else. The cursor is
+// on a byte, or just past the last one to add more; in the hex column a byte is typed a
+// digit at a time.
+
+// Columns taken by the offset and the gap after it
+const OFFSET_WIDTH: usize = 10;
+
+// One change: `removed` bytes at `offset` were replaced by `inserted`
+struct Edit {
+    offset: usize,
+    removed: Vec<u8>,
+    inserted: Vec<u8>,
+}
+
+pub struct Hex {
+    pub bytes: GapBuffer,
+    pub cursor: usize,
+    // The cursor is on the second digit of its byte
+    pub low: bool,
+    // Typing goes to the ASCII column rather than the hex one
+    pub ascii: bool,
+    // First row on screen
+    pub top: usize,
+    undo: Vec<Edit>,
+    redo: Vec<Edit>,
+}
+
+impl Hex {
+    pub fn new(bytes: &[u8]) -> Hex {
+        let mut buffer = GapBuffer::new(bytes.len());
+        buffer.insert(0, bytes);
+        Hex {
+            bytes: buffer,
+            cursor: 0,
+            low: false,
+            ascii: false,
+            top: 0,
+            undo: Vec::new(),
+            redo: Vec::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.buffer_length()
+    }
+
+    // Rows of `per_row` bytes, and one more to add bytes on when the last is full
+    pub fn rows(&self, per_row: usize) -> usize {
+        self.len() / per_row + 1
+    }
+
+    pub fn move_to(&mut self, offset: usize) {
+        self.cursor = offset.min(self.len());
+        self.low = false;
+    }
+
+    pub fn move_by(&mut self, delta: isize) {
+        self.move_to(self.cursor.saturating_add_signed(delta));
+    }
+
+    // Keeps the cursor's row among the `height` rows on screen.
+    pub fn scroll(&mut self, height: usize, per_row: usize) {
+        let row = self.cursor / per_row;
+        if row < self.top {
+            self.top = row;
+        } else if row >= self.top + height {
+            self.top = row + 1 - height;
+        }
+    }
+
+    // Types `c` at the cursor, inserting a byte or writing over the one there. Returns
+    // false when the character does not go in the column the cursor is in.
+    pub fn type_char(&mut self, c: char, insert: bool) -> bool {
+        let over = !insert && self.cursor < self.len();
+        if self.ascii {
+            let mut buffer = [0; 4];
+            let bytes = c.encode_utf8(&mut buffer).as_bytes().to_vec();
+            let removed = if over { bytes.len().min(self.len() - self.cursor) } else { 0 };
+            let end = self.cursor + bytes.len();
+            self.change(self.cursor, removed, bytes);
+            self.move_to(end);
+            return true;
+        }
+        let Some(digit) = c.to_digit(16).map(|digit| digit as u8) else {
+            return false;
+        };
+        if self.low {
+            let byte = self.bytes.byte(self.cursor).unwrap_or(0) & 0xF0 | digit;
+            self.replace(self.cursor, 1, &[byte]);
+            // The second digit finishes the change the first one made, so one undo takes the byte back
+            if let Some(edit) = self.undo.last_mut() {
+                edit.inserted = vec![byte];
+            }
+            self.move_to(self.cursor + 1);
+        } else {
+            let byte = if over { self.bytes.byte(self.cursor).unwrap_or(0) } else { 0 };
+            self.change(self.cursor, usize::from(over), vec![digit << 4 | byte & 0x0F]);
+            self.low = true;
+        }
+        true
+    }
+
+    // Deletes the byte under the cursor, or the one before it. Returns whether there was one.
+    pub fn delete(&mut self, forward: bool) -> bool {
+        let offset = match forward {
+            true => self.cursor,
+            false if self.low => self.cursor,
+            false => match self.cursor.checked_sub(1) {
+                Some(offset) => offset,
+                None => return false,
+            },
+        };
+        if offset >= self.len() {
+            return false;
+        }
+        self.change(offset, 1, Vec::new());
+        self.move_to(offset);
+        true
+    }
+
+    // Takes back the last change, or makes the last one taken back again. Returns whether
+    // there was one.
+    pub fn undo(&mut self, redo: bool) -> bool {
+        let edit = if redo { self.redo.pop() } else { self.undo.pop() };
+        let Some(edit) = edit else {
+            return false;
+        };
+        let (old, new) = if redo { (&edit.removed, &edit.inserted) } else { (&edit.inserted, &edit.removed) };
+        self.replace(edit.offset, old.len(), new);
+        self.move_to(edit.offset);
+        if redo { self.undo.push(edit) } else { self.redo.push(edit) }
+        true
+    }
+
+    fn change(&mut self, offset: usize, count: usize, inserted: Vec<u8>) {
+        let removed = self.replace(offset, count, &inserted);
+        self.undo.push(Edit { offset, removed, inserted });
+        self.redo.clear();
+    }
+
+    // Replaces `count` bytes at `offset` and returns them.
+    fn replace(&mut self, offset: usize, count: usize, inserted: &[u8]) -> Vec<u8> {
+        let removed = (offset..offset + count).filter_map(|idx| self.bytes.byte(idx)).collect();
+        self.bytes.remove(offset..offset + count);
+        self.bytes.insert(offset, inserted);
+        removed
+    }
+
+    pub fn render_row(&self, row: usize, per_row: usize) -> String {
+        let start = row * per_row;
+        let bytes: Vec<u8> = (start..start + per_row).map_while(|idx| self.bytes.byte(idx)).collect();
+        line(start, &bytes, per_row)
+    }
+}
+
+// Bytes shown on a row that is `width` columns wide: as many as fit, up to 16 and in powers of two
+pub fn per_row(width: usize) -> usize {
+    let fit = width.saturating_sub(OFFSET_WIDTH + 1) / 4;
+    [16, 8, 4, 2].into_iter().find(|n| *n <= fit).unwrap_or(1)
+}
+
+// Screen column of the first hex digit of the `idx`th byte of a row
+pub fn hex_column(idx: usize) -> usize {
+    OFFSET_WIDTH + idx * 3
+}
+
+// Screen column of the ASCII character of the `idx`th byte of a row
+pub fn ascii_column(idx: usize, per_row: usize) -> usize {
+    OFFSET_WIDTH + per_row * 3 + 1 + idx
+}
+
+// A row of bytes starting at `offset`
+fn line(offset: usize, bytes: &[u8], per_row: usize) -> String {
+    let mut line = format!("{:08x}  ", offset);
+    for idx in 0..per_row {
+        match bytes.get(idx) {
+            Some(byte) => line.push_str(&format!("{:02x} ", byte)),
+            None => line.push_str("   "),
+        }
+    }
+    line.push(' ');
+    line.extend(bytes.iter().map(|byte| if byte.is_ascii_graphic() || *byte == b' ' { char::from(*byte) } else { '.' }));
+    line
+}
+
+// All the rows of some bytes, 16 to a row, as text to compare
+pub fn dump(bytes: &[u8]) -> String {
+    let lines: Vec<String> = bytes.chunks(16).enumerate().map(|(row, chunk)| line(row * 16, chunk, 16)).collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn typed(bytes: &[u8], text: &str, insert: bool) -> Hex {
+        let mut hex = Hex::new(bytes);
+        for c in text.chars() {
+            hex.type_char(c, insert);
+        }
+        hex
+    }
+
+    #[test]
+    fn types_bytes_a_digit_at_a_time() {
+        let hex = typed(b"\x12\x34", "a", false);
+        assert_eq!((hex.bytes.bytes(), hex.cursor, hex.low), (vec![0xa2, 0x34], 0, true));
+        let hex = typed(b"\x12\x34", "abc", false);
+        assert_eq!((hex.bytes.bytes(), hex.cursor, hex.low), (vec![0xab, 0xc4], 1, true));
+        let hex = typed(b"\x12\x34", "abc", true);
+        assert_eq!((hex.bytes.bytes(), hex.cursor), (vec![0xab, 0xc0, 0x12, 0x34], 1));
+        // Past the end, overwriting adds bytes
+        let mut hex = Hex::new(b"\x12");
+        hex.move_to(1);
+        hex.type_char('f', false);
+        hex.type_char('f', false);
+        assert_eq!((hex.bytes.bytes(), hex.cursor), (vec![0x12, 0xff], 2));
+        assert!(!hex.type_char('g', false));
+    }
+
+    #[test]
+    fn types_characters_in_the_ascii_column() {
+        let mut hex = Hex::new(b"abc");
+        hex.ascii = true;
+        hex.type_char('X', false);
+        assert_eq!((hex.bytes.bytes(), hex.cursor), (b"Xbc".to_vec(), 1));
+        hex.type_char('é', false);
+        assert_eq!((hex.bytes.bytes(), hex.cursor), ("Xé".bytes().collect(), 3));
+        hex.move_to(1);
+        hex.type_char('-', true);
+        assert_eq!(hex.bytes.bytes(), "X-é".bytes().collect::<Vec<u8>>());
+    }
+
+    #[test]
+    fn undoes_a_byte_at_a_time() {
+        let mut hex = typed(b"\x12\x34", "abcd", false);
+        assert_eq!(hex.bytes.bytes(), vec![0xab, 0xcd]);
+        assert!(hex.undo(false));
+        assert_eq!((hex.bytes.bytes(), hex.cursor), (vec![0xab, 0x34], 1));
+        assert!(hex.undo(false));
+        assert_eq!((hex.bytes.bytes(), hex.cursor), (vec![0x12, 0x34], 0));
+        assert!(!hex.undo(false));
+        assert!(hex.undo(true));
+        assert_eq!(hex.bytes.bytes(), vec![0xab, 0x34]);
+        // A change forgets what was undone
+        hex.delete(true);
+        assert!(!hex.undo(true));
+        assert!(hex.undo(false));
+        assert_eq!(hex.bytes.bytes(), vec![0xab, 0x34]);
+
+        let mut hex = typed(b"\x12", "ab", true);
+        assert!(hex.undo(false));
+        assert_eq!(hex.bytes.bytes(), vec![0x12]);
+    }
+
+    #[test]
+    fn deletes_around_the_cursor() {
+        let mut hex = Hex::new(b"\x01\x02\x03");
+        assert!(!hex.delete(false));
+        hex.move_to(1);
+        assert!(hex.delete(false));
+        assert_eq!((hex.bytes.bytes(), hex.cursor), (vec![0x02, 0x03], 0));
+        assert!(hex.delete(true));
+        assert_eq!(hex.bytes.bytes(), vec![0x03]);
+        hex.move_to(1);
+        assert!(!hex.delete(true));
+    }
+
+    #[test]
+    fn lays_out_rows() {
+        let hex = Hex::new(b"Hi\x00 there, world!\x7f");
+        assert_eq!(hex.rows(16), 2);
+        assert_eq!(hex.render_row(0, 4), "00000000  48 69 00 20  Hi. ");
+        assert_eq!(hex.render_row(4, 4), "00000010  21 7f        !.");
+        assert_eq!((per_row(80), per_row(40), per_row(20)), (16, 4, 2));
+        assert_eq!((hex_column(2), ascii_column(2, 4)), (16, 25));
+    }
+}
@@ -1 +1,151 @@
-//TODO: Implement syntax highlighting for the editor
+use crate::editor::document::Document;
+
+// Tells code apart from strings and comments. This is the first step towards
+// syntax highlighting and is what keeps brackets in strings from being matched.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Kind {
+    Code,
+    String,
+    Comment,
+}
+
+// Where the lexer is at the end of a line
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum State {
+    #[default]
+    Code,
+    // Inside a string closed by this quote
+    String(char),
+    BlockComment,
+}
+
+pub struct Syntax {
+    line_comment: &'static [&'static str],
+    block_comment: Option<(&'static str, &'static str)>,
+    quotes: &'static [char],
+    // Quotes whose strings may run on past the end of a line
+    multiline: &'static [char],
+    // Single quotes start character literals, but also lifetimes and labels
+    char_literals: bool,
+}
+
+const C_LIKE: Syntax = Syntax {
+    line_comment: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"', '\'', '`'],
+    multiline: &['`'],
+    char_literals: false,
+};
+
+const RUST: Syntax = Syntax {
+    line_comment: &["//"],
+    block_comment: Some(("/*", "*/")),
+    quotes: &['"'],
+    multiline: &['"'],
+    char_literals: true,
+};
+
+const SCRIPT: Syntax = Syntax {
+    line_comment: &["#"],
+    block_comment: None,
+    quotes: &['"', '\''],
+    multiline: &[],
+    char_literals: false,
+};
+
+const PLAIN: Syntax = Syntax {
+    line_comment: &[],
+    block_comment: None,
+    quotes: &[],
+    multiline: &[],
+    char_literals: false,
+};
+
+pub fn syntax(file_type: &str) -> &'static Syntax {
+    match file_type {
+        "rs" => &RUST,
+        "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "java" | "kt" | "scala" | "swift" | "go" | "zig" | "js" | "jsx"
+        | "ts" | "tsx" | "json" | "css" | "scss" | "php" => &C_LIKE,
+        "py" | "pyw" | "sh" | "bash" | "rb" | "toml" | "yaml" | "yml" | "conf" => &SCRIPT,
+        _ => &PLAIN,
+    }
+}
+
+// Kind of every character of `line` when it starts in `state`, and the state at its end.
+pub fn classify(line: &str, mut state: State, syntax: &Syntax) -> (Vec<Kind>, State) {
+    let chars: Vec<char> = line.chars().collect();
+    let starts = |idx: usize, token: &str| token.chars().enumerate().all(|(offset, c)| chars.get(idx + offset) == Some(&c));
+    let mut kinds = Vec::with_capacity(chars.len());
+    let mut idx = 0;
+    while idx < chars.len() {
+        let (kind, len) = match state {
+            State::BlockComment => {
+                let end = syntax.block_comment.map(|(_, end)| end).unwrap_or("");
+                if starts(idx, end) {
+                    state = State::Code;
+                    (Kind::Comment, end.chars().count())
+                } else {
+                    (Kind::Comment, 1)
+                }
+            },
+            State::String(quote) => {
+                if chars[idx] == '\\' {
+                    (Kind::String, 2)
+                } else {
+                    if chars[idx] == quote {
+                        state = State::Code;
+                    }
+                    (Kind::String, 1)
+                }
+            },
+            State::Code => {
+                if syntax.line_comment.iter().any(|token| starts(idx, token)) {
+                    (Kind::Comment, chars.len() - idx)
+                } else if let Some((start, _)) = syntax.block_comment.filter(|(start, _)| starts(idx, start)) {
+                    state = State::BlockComment;
+                    (Kind::Comment, start.chars().count())
+                } else if syntax.quotes.contains(&chars[idx]) {
+                    state = State::String(chars[idx]);
+                    (Kind::String, 1)
+                } else if syntax.char_literals && chars[idx] == '\'' {
+                    (Kind::String, char_literal(&chars[idx..]).unwrap_or(1))
+                } else {
+                    (Kind::Code, 1)
+                }
+            },
+        };
+        kinds.extend(std::iter::repeat_n(kind, len.min(chars.len() - idx)));
+        idx += len;
+    }
+    match state {
+        State::String(quote) if !syntax.multiline.contains(&quote) => (kinds, State::Code),
+        _ => (kinds, state),
+    }
+}
+
+// Length of a character literal such as 'a' or '\n' at the start of `chars`.
+// Anything else starting with a quote is a lifetime or a label.
+fn char_literal(chars: &[char]) -> Option<usize> {
+    match chars {
+        ['\'', '\\', _, rest @ ..] => rest.iter().take(10).position(|&c| c == '\'').map(|end| end + 4),
+        ['\'', _, '\'', ..] => Some(3),
+        _ => None,
+    }
+}
+
+// Adds the lexer state at the start of the rows of `document` after those in `states`,
+// until there are `count`.
+pub fn extend_states(states: &mut Vec<State>, document: &Document, count: usize) {
+    let syntax = syntax(&document.file_type);
+    let mut state = match states.last() {
+        Some(last) => classify(&document.rows[states.len() - 1].to_string(), *last, syntax).1,
+        None => State::Code,
+    };
+    for row in states.len()..count {
+        states.push(state);
+        if row + 1 < count {
+            state = classify(&document.rows[row].to_string(), state, syntax).1;
+        }
+    }
+}
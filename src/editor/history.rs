@@ -0,0 +1,210 @@
+use std::time::{Duration, Instant};
+
+// Undo and redo. Every edit is recorded as a replacement of whole rows, and the
+// edits made by one command are grouped so they are undone together. Characters typed
+// one after another are undone together too, until something else happens or typing stops.
+
+// Typing after a pause this long starts a new group
+const PAUSE: Duration = Duration::from_secs(1);
+// Groups kept for undoing, the oldest are dropped past this
+const MAX_GROUPS: usize = 1000;
+
+// The `removed` rows starting at `row` were replaced by `inserted`
+pub struct Change {
+    pub row: usize,
+    pub removed: Vec<String>,
+    pub inserted: Vec<String>,
+}
+
+pub struct Group {
+    pub changes: Vec<Change>,
+    // Cursor row and column before the first change and after the last
+    pub before: (usize, usize),
+    pub after: (usize, usize),
+}
+
+#[derive(Default)]
+pub struct History {
+    undo: Vec<Group>,
+    redo: Vec<Group>,
+    open: Option<Group>,
+    // When the last group was closed, if it was typing that more typing may join
+    typed_at: Option<Instant>,
+}
+
+impl History {
+    // Starts grouping changes, unless a group is already open.
+    pub fn begin(&mut self, cursor: (usize, usize)) {
+        if self.open.is_none() {
+            self.open = Some(Group {
+                changes: Vec::new(),
+                before: cursor,
+                after: cursor,
+            });
+        }
+    }
+
+    pub fn record(&mut self, change: Change) {
+        if change.removed == change.inserted {
+            return;
+        }
+        let cursor = (change.row, 0);
+        let group = self.open.get_or_insert(Group {
+            changes: Vec::new(),
+            before: cursor,
+            after: cursor,
+        });
+        push(&mut group.changes, change);
+    }
+
+    // Closes the open group, which joins the one before it when both are `typing`.
+    // A new edit makes everything undone so far unreachable.
+    pub fn end(&mut self, cursor: (usize, usize), typing: bool) {
+        let Some(mut group) = self.open.take().filter(|group| !group.changes.is_empty()) else {
+            // Moving the cursor in between keeps typing apart
+            if !typing {
+                self.typed_at = None;
+            }
+            return;
+        };
+        group.after = cursor;
+        match self.undo.last_mut() {
+            Some(last) if typing && self.typed_at.is_some_and(|at| at.elapsed() < PAUSE) => {
+                for change in group.changes {
+                    push(&mut last.changes, change);
+                }
+                last.after = cursor;
+            },
+            _ => {
+                self.undo.push(group);
+                if self.undo.len() > MAX_GROUPS {
+                    self.undo.remove(0);
+                }
+            },
+        }
+        self.typed_at = typing.then(Instant::now);
+        self.redo.clear();
+    }
+
+    pub fn take_undo(&mut self) -> Option<Group> {
+        self.typed_at = None;
+        self.undo.pop()
+    }
+
+    pub fn take_redo(&mut self) -> Option<Group> {
+        self.typed_at = None;
+        self.redo.pop()
+    }
+
+    pub fn push_undo(&mut self, group: Group) {
+        self.undo.push(group);
+    }
+
+    pub fn push_redo(&mut self, group: Group) {
+        self.redo.push(group);
+    }
+}
+
+// Adds `change` to `changes`, folded into the last one when it only changes again the
+// rows that one left, as typing along a row does.
+fn push(changes: &mut Vec<Change>, change: Change) {
+    match changes.last_mut() {
+        Some(last) if last.row == change.row && last.inserted == change.removed => last.inserted = change.inserted,
+        _ => changes.push(change),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(row: usize, removed: &str, inserted: &str) -> Change {
+        Change { row, removed: vec![String::from(removed)], inserted: vec![String::from(inserted)] }
+    }
+
+    // Types `text` at the end of row 0 a character at a time, one key per group
+    fn type_text(history: &mut History, line: &mut String, text: &str) {
+        for c in text.chars() {
+            history.begin((0, line.len()));
+            let before = line.clone();
+            line.push(c);
+            history.record(change(0, &before, line));
+            history.end((0, line.len()), true);
+        }
+    }
+
+    #[test]
+    fn a_typing_run_is_one_group() {
+        let mut history = History::default();
+        let mut line = String::new();
+        type_text(&mut history, &mut line, "hello");
+        let group = history.take_undo().unwrap();
+        assert_eq!((group.before, group.after), ((0, 0), (0, 5)));
+        // The keys' changes fold into one
+        assert_eq!(group.changes.len(), 1);
+        assert_eq!((group.changes[0].removed[0].as_str(), group.changes[0].inserted[0].as_str()), ("", "hello"));
+        assert!(history.take_undo().is_none());
+    }
+
+    #[test]
+    fn moving_or_pausing_ends_a_typing_run() {
+        let mut history = History::default();
+        let mut line = String::new();
+        type_text(&mut history, &mut line, "ab");
+        // A motion: a key with no changes
+        history.begin((0, 2));
+        history.end((0, 1), false);
+        type_text(&mut history, &mut line, "cd");
+        if let Some(at) = Instant::now().checked_sub(PAUSE) {
+            history.typed_at = Some(at);
+            type_text(&mut history, &mut line, "ef");
+            assert_eq!(history.take_undo().unwrap().changes[0].inserted[0], "abcdef");
+        }
+        assert_eq!(history.take_undo().unwrap().changes[0].inserted[0], "abcd");
+        assert_eq!(history.take_undo().unwrap().changes[0].inserted[0], "ab");
+    }
+
+    #[test]
+    fn other_commands_are_groups_of_their_own() {
+        let mut history = History::default();
+        let mut line = String::new();
+        type_text(&mut history, &mut line, "a");
+        history.begin((0, 1));
+        history.record(change(0, "a", "A"));
+        history.record(change(1, "x", "y"));
+        history.end((1, 0), false);
+        let group = history.take_undo().unwrap();
+        assert_eq!(group.changes.len(), 2);
+        assert_eq!(group.before, (0, 1));
+        assert_eq!(history.take_undo().unwrap().changes[0].inserted[0], "a");
+    }
+
+    #[test]
+    fn drops_the_oldest_groups_past_the_cap() {
+        let mut history = History::default();
+        for row in 0..MAX_GROUPS + 5 {
+            history.begin((row, 0));
+            history.record(change(row, "", "x"));
+            history.end((row, 1), false);
+        }
+        let mut rows = Vec::new();
+        while let Some(group) = history.take_undo() {
+            rows.push(group.changes[0].row);
+        }
+        assert_eq!(rows.len(), MAX_GROUPS);
+        assert_eq!(rows.first(), Some(&(MAX_GROUPS + 4)));
+        assert_eq!(rows.last(), Some(&5));
+    }
+
+    #[test]
+    fn a_new_edit_clears_redo() {
+        let mut history = History::default();
+        history.record(change(0, "a", "b"));
+        history.end((0, 0), false);
+        let group = history.take_undo().unwrap();
+        history.push_redo(group);
+        history.record(change(0, "a", "c"));
+        history.end((0, 0), false);
+        assert!(history.take_redo().is_none());
+    }
+}
@@ -0,0 +1,172 @@
+// Automatic indentation: measuring and building indents, and the
+// per-language characters that open and close a block.
+
+pub struct Rules {
+    // A line ending in one of these indents the next line
+    pub openers: &'static [char],
+    // Typing one of these first on a line dedents it
+    pub closers: &'static [char],
+}
+
+const BRACES: Rules = Rules {
+    openers: &['{', '[', '('],
+    closers: &['}', ']', ')'],
+};
+
+const PYTHON: Rules = Rules {
+    openers: &[':', '{', '[', '('],
+    closers: &['}', ']', ')'],
+};
+
+const PLAIN: Rules = Rules {
+    openers: &[],
+    closers: &[],
+};
+
+pub fn rules(file_type: &str) -> &'static Rules {
+    match file_type {
+        "rs" | "c" | "h" | "cc" | "cpp" | "hpp" | "cs" | "java" | "kt" | "scala" | "swift" | "go" | "zig"
+        | "js" | "jsx" | "ts" | "tsx" | "json" | "css" | "scss" | "php" | "sh" => &BRACES,
+        "py" | "pyw" => &PYTHON,
+        _ => &PLAIN,
+    }
+}
+
+// The closer that ends a block started by `opener`, if any
+pub fn closer_for(opener: char) -> Option<char> {
+    match opener {
+        '{' => Some('}'),
+        '[' => Some(']'),
+        '(' => Some(')'),
+        _ => None,
+    }
+}
+
+// Leading whitespace of a line
+pub fn leading(line: &str) -> &str {
+    &line[..line.len() - line.trim_start().len()]
+}
+
+// Screen columns taken by an indent
+pub fn width(indent: &str, tab_width: usize) -> usize {
+    indent.chars().fold(0, |col, c| if c == '\t' { col + tab_width - col % tab_width } else { col + 1 })
+}
+
+// An indent `width` columns wide, with tabs where possible unless `expand_tab` is set.
+pub fn make(width: usize, expand_tab: bool, tab_width: usize) -> String {
+    if expand_tab {
+        " ".repeat(width)
+    } else {
+        "\t".repeat(width / tab_width) + &" ".repeat(width % tab_width)
+    }
+}
+
+// Width of the indent one level deeper than `width`
+pub fn deeper(width: usize, level: usize) -> usize {
+    (width / level + 1) * level
+}
+
+// Width of the indent one level shallower than `width`
+pub fn shallower(width: usize, level: usize) -> usize {
+    width.saturating_sub(1) / level * level
+}
+
+// The opener `text` ends with, ignoring trailing whitespace
+pub fn ends_with_opener(text: &str, rules: &Rules) -> Option<char> {
+    text.trim_end().chars().next_back().filter(|c| rules.openers.contains(c))
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Style {
+    Tabs,
+    Spaces(usize),
+}
+
+impl Style {
+    pub fn expand_tab(&self) -> bool {
+        matches!(self, Style::Spaces(_))
+    }
+
+    // Columns in one level of indentation
+    pub fn level(&self, tab_width: usize) -> usize {
+        match self {
+            Style::Tabs => tab_width,
+            Style::Spaces(width) => *width,
+        }
+    }
+}
+
+impl std::fmt::Display for Style {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Style::Tabs => write!(f, "Tabs"),
+            Style::Spaces(width) => write!(f, "Spaces: {}", width),
+        }
+    }
+}
+
+// Guesses how a file is indented: with tabs if most indented lines start with one,
+// otherwise by the most common step between the indents of neighbouring lines.
+pub fn detect<'a>(lines: impl Iterator<Item = &'a str>) -> Option<Style> {
+    let (mut tabs, mut spaces) = (0, 0);
+    let mut steps = [0usize; 9];
+    let mut previous = 0;
+    for line in lines.filter(|line| !line.trim().is_empty()) {
+        if line.starts_with('\t') {
+            tabs += 1;
+            continue;
+        }
+        let width = line.len() - line.trim_start_matches(' ').len();
+        if width > 0 {
+            spaces += 1;
+        }
+        if width > previous && width - previous < steps.len() {
+            steps[width - previous] += 1;
+        }
+        previous = width;
+    }
+    if tabs > spaces {
+        return Some(Style::Tabs);
+    }
+    // Ties go to the wider step
+    (1..steps.len()).max_by_key(|&step| steps[step]).filter(|&step| steps[step] > 0).map(Style::Spaces)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detected(text: &str) -> Option<Style> {
+        detect(text.lines())
+    }
+
+    #[test]
+    fn detects_tabs_and_space_steps() {
+        assert_eq!(detected("fn a() {\n\tb();\n\tif c {\n\t\td();\n\t}\n}\n"), Some(Style::Tabs));
+        assert_eq!(detected("a:\n  b:\n    c\n  d\ne\n"), Some(Style::Spaces(2)));
+        // Steps count between neighbouring lines, so deeper nesting still finds 4
+        assert_eq!(detected("a {\n    b {\n        c {\n            d\n        }\n    }\n}\n"), Some(Style::Spaces(4)));
+        // Continuation lines lined up with something else are outvoted
+        assert_eq!(detected("a {\n    b(1,\n      2);\n    c {\n        d\n    }\n}\n"), Some(Style::Spaces(4)));
+    }
+
+    #[test]
+    fn ignores_blank_lines_and_ties_go_wider() {
+        assert_eq!(detected("a\n\n    \n  b\nc\n    d\n"), Some(Style::Spaces(4)));
+        assert_eq!(detected("a\nb\n"), None);
+        assert_eq!(detected(""), None);
+    }
+
+    #[test]
+    fn builds_and_measures_indents() {
+        assert_eq!(width("\t  ", 4), 6);
+        assert_eq!(width("  \t", 4), 4);
+        assert_eq!(make(6, false, 4), "\t  ");
+        assert_eq!(make(6, true, 4), "      ");
+        assert_eq!((deeper(4, 4), deeper(5, 4), shallower(8, 4), shallower(6, 4), shallower(0, 4)), (8, 8, 4, 4, 0));
+        assert_eq!(leading("\t  x "), "\t  ");
+        assert_eq!(ends_with_opener("if a {  ", rules("rs")), Some('{'));
+        assert_eq!(ends_with_opener("if a:", rules("rs")), None);
+        assert_eq!(ends_with_opener("if a:", rules("py")), Some(':'));
+    }
+}
@@ -0,0 +1,441 @@
+use std::collections::HashMap;
+
+use termion::event::Key;
+
+use crate::editor::{config, terminal};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Quit,
+    Save,
+    ToggleMode,
+    MoveUp,
+    MoveDown,
+    MoveLeft,
+    MoveRight,
+    MoveHome,
+    MoveEnd,
+    MoveWordLeft,
+    MoveWordRight,
+    MoveParagraphUp,
+    MoveParagraphDown,
+    PageUp,
+    PageDown,
+    MoveDocumentStart,
+    MoveDocumentEnd,
+    Backspace,
+    Delete,
+    DeleteWordBackward,
+    DeleteWordForward,
+    Enter,
+    Indent,
+    Dedent,
+    Undo,
+    Redo,
+    MatchBracket,
+    JumpBack,
+    JumpForward,
+    // Both take the name of the mark from the next key
+    SetMark,
+    JumpToMark,
+    AddCursorAbove,
+    AddCursorBelow,
+    AddCursorAtNextMatch,
+    BlockSelect,
+    Copy,
+    Cut,
+    Paste,
+    // Moving to the window in a direction, or to the next one
+    FocusLeft,
+    FocusRight,
+    FocusUp,
+    FocusDown,
+    FocusNext,
+}
+
+// Names used in the config file and by :map
+const ACTIONS: &[(&str, Action)] = &[
+    ("quit", Action::Quit),
+    ("save", Action::Save),
+    ("toggle-mode", Action::ToggleMode),
+    ("move-up", Action::MoveUp),
+    ("move-down", Action::MoveDown),
+    ("move-left", Action::MoveLeft),
+    ("move-right", Action::MoveRight),
+    ("move-home", Action::MoveHome),
+    ("move-end", Action::MoveEnd),
+    ("move-word-left", Action::MoveWordLeft),
+    ("move-word-right", Action::MoveWordRight),
+    ("move-paragraph-up", Action::MoveParagraphUp),
+    ("move-paragraph-down", Action::MoveParagraphDown),
+    ("page-up", Action::PageUp),
+    ("page-down", Action::PageDown),
+    ("move-document-start", Action::MoveDocumentStart),
+    ("move-document-end", Action::MoveDocumentEnd),
+    ("backspace", Action::Backspace),
+    ("delete", Action::Delete),
+    ("delete-word-backward", Action::DeleteWordBackward),
+    ("delete-word-forward", Action::DeleteWordForward),
+    ("enter", Action::Enter),
+    ("indent", Action::Indent),
+    ("dedent", Action::Dedent),
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("match-bracket", Action::MatchBracket),
+    ("jump-back", Action::JumpBack),
+    ("jump-forward", Action::JumpForward),
+    ("set-mark", Action::SetMark),
+    ("jump-to-mark", Action::JumpToMark),
+    ("add-cursor-above", Action::AddCursorAbove),
+    ("add-cursor-below", Action::AddCursorBelow),
+    ("add-cursor-at-next-match", Action::AddCursorAtNextMatch),
+    ("block-select", Action::BlockSelect),
+    ("copy", Action::Copy),
+    ("cut", Action::Cut),
+    ("paste", Action::Paste),
+    ("focus-left", Action::FocusLeft),
+    ("focus-right", Action::FocusRight),
+    ("focus-up", Action::FocusUp),
+    ("focus-down", Action::FocusDown),
+    ("focus-next", Action::FocusNext),
+];
+
+impl Action {
+    pub fn from_name(name: &str) -> Option<Action> {
+        ACTIONS.iter().find(|(n, _)| *n == name).map(|(_, action)| *action)
+    }
+
+    pub fn name(&self) -> &'static str {
+        ACTIONS.iter().find(|(_, a)| a == self).map(|(n, _)| *n).unwrap_or("?")
+    }
+
+    // Whether the action changes the text when editing
+    pub fn edits(&self) -> bool {
+        matches!(self, Action::Backspace | Action::Delete | Action::DeleteWordBackward | Action::DeleteWordForward
+            | Action::Enter | Action::Indent | Action::Dedent | Action::Undo | Action::Redo | Action::Cut | Action::Paste)
+    }
+
+    // Whether the action moves the focus to another window
+    pub fn moves_focus(&self) -> bool {
+        matches!(self, Action::FocusLeft | Action::FocusRight | Action::FocusUp | Action::FocusDown | Action::FocusNext)
+    }
+}
+
+pub enum Lookup {
+    Action(Action),
+    // The keys so far are the start of a longer chord
+    Pending,
+    Unbound,
+}
+
+#[derive(Default)]
+pub struct Bindings {
+    map: HashMap<Vec<Key>, Action>,
+}
+
+impl Bindings {
+    pub fn bind(&mut self, keys: Vec<Key>, action: Action) {
+        self.map.insert(keys, action);
+    }
+
+    pub fn lookup(&self, keys: &[Key]) -> Lookup {
+        if let Some(action) = self.map.get(keys) {
+            return Lookup::Action(*action);
+        }
+        let is_prefix = self.map.keys().any(|chord| chord.len() > keys.len() && chord.starts_with(keys));
+        if is_prefix {
+            Lookup::Pending
+        } else {
+            Lookup::Unbound
+        }
+    }
+}
+
+pub struct Keymap {
+    pub edit: Bindings,
+    pub command: Bindings,
+}
+
+impl Keymap {
+    pub fn new() -> Keymap {
+        let mut keymap = Keymap {
+            edit: Bindings::default(),
+            command: Bindings::default(),
+        };
+        let defaults = [
+            (Key::Esc, Action::ToggleMode),
+            (Key::Ctrl('q'), Action::Quit),
+            (Key::Ctrl('s'), Action::Save),
+            (Key::Up, Action::MoveUp),
+            (Key::Down, Action::MoveDown),
+            (Key::Left, Action::MoveLeft),
+            (Key::Right, Action::MoveRight),
+            (Key::Home, Action::MoveHome),
+            (Key::End, Action::MoveEnd),
+            (Key::CtrlLeft, Action::MoveWordLeft),
+            (Key::CtrlRight, Action::MoveWordRight),
+            (Key::CtrlUp, Action::MoveParagraphUp),
+            (Key::CtrlDown, Action::MoveParagraphDown),
+            (Key::PageUp, Action::PageUp),
+            (Key::PageDown, Action::PageDown),
+            (Key::CtrlHome, Action::MoveDocumentStart),
+            (Key::CtrlEnd, Action::MoveDocumentEnd),
+            (Key::Backspace, Action::Backspace),
+            (Key::Delete, Action::Delete),
+            // Most terminals send ^H for Ctrl+Backspace and DEL for Backspace. Those that
+            // send ^H for Backspace delete words with it unless C-h is bound to backspace.
+            (Key::Ctrl('h'), Action::DeleteWordBackward),
+            (Key::Alt('\x7F'), Action::DeleteWordBackward),
+            (terminal::CTRL_DELETE, Action::DeleteWordForward),
+            (Key::Char('\n'), Action::Enter),
+            (Key::Ctrl('z'), Action::Undo),
+            (Key::Ctrl('y'), Action::Redo),
+        ];
+        for (key, action) in defaults {
+            keymap.edit.bind(vec![key], action);
+            keymap.command.bind(vec![key], action);
+        }
+        // The command line takes tabs literally
+        keymap.edit.bind(vec![Key::Char('\t')], Action::Indent);
+        keymap.edit.bind(vec![Key::BackTab], Action::Dedent);
+        keymap.edit.bind(vec![Key::Ctrl('5')], Action::MatchBracket);
+        keymap.edit.bind(vec![Key::AltLeft], Action::JumpBack);
+        keymap.edit.bind(vec![Key::AltRight], Action::JumpForward);
+        keymap.edit.bind(vec![Key::Alt('m')], Action::SetMark);
+        keymap.edit.bind(vec![Key::Alt('\'')], Action::JumpToMark);
+        keymap.edit.bind(vec![Key::AltUp], Action::AddCursorAbove);
+        keymap.edit.bind(vec![Key::AltDown], Action::AddCursorBelow);
+        keymap.edit.bind(vec![Key::Ctrl('d')], Action::AddCursorAtNextMatch);
+        keymap.edit.bind(vec![Key::Ctrl('b')], Action::BlockSelect);
+        keymap.edit.bind(vec![Key::Ctrl('c')], Action::Copy);
+        keymap.edit.bind(vec![Key::Ctrl('x')], Action::Cut);
+        keymap.edit.bind(vec![Key::Ctrl('v')], Action::Paste);
+        // Windows are moved between with C-w and a direction, as in vi
+        keymap.edit.bind(vec![Key::Ctrl('w'), Key::Char('h')], Action::FocusLeft);
+        keymap.edit.bind(vec![Key::Ctrl('w'), Key::Char('l')], Action::FocusRight);
+        keymap.edit.bind(vec![Key::Ctrl('w'), Key::Char('k')], Action::FocusUp);
+        keymap.edit.bind(vec![Key::Ctrl('w'), Key::Char('j')], Action::FocusDown);
+        keymap.edit.bind(vec![Key::Ctrl('w'), Key::Char('w')], Action::FocusNext);
+        keymap
+    }
+
+    // Applies the [keys], [keys.edit] and [keys.command] sections of the config file.
+    // Returns an error message for every binding that could not be applied.
+    pub fn load(&mut self, config: &config::ConfigFile) -> Vec<String> {
+        let mut errors = Vec::new();
+        for section in ["keys", "keys.edit", "keys.command"] {
+            for entry in config.section(section) {
+                let action = match &entry.value {
+                    config::Value::Str(name) => name.clone(),
+                    other => {
+                        errors.push(format!("{}: expected an action name, found {}", config.location(entry.line), other));
+                        continue;
+                    }
+                };
+                let result = match section {
+                    "keys.edit" => bind(&mut self.edit, &entry.key, &action),
+                    "keys.command" => bind(&mut self.command, &entry.key, &action),
+                    _ => bind(&mut self.edit, &entry.key, &action)
+                        .and_then(|_| bind(&mut self.command, &entry.key, &action)),
+                };
+                if let Err(msg) = result {
+                    errors.push(format!("{}: {}", config.location(entry.line), msg));
+                }
+            }
+        }
+        errors
+    }
+}
+
+pub fn bind(bindings: &mut Bindings, keys: &str, action: &str) -> Result<(), String> {
+    let keys = parse_keys(keys)?;
+    let action = Action::from_name(action).ok_or_else(|| format!("unknown action `{}`", action))?;
+    bindings.bind(keys, action);
+    Ok(())
+}
+
+// Parses a chord such as "C-x C-s" into its keys.
+pub fn parse_keys(text: &str) -> Result<Vec<Key>, String> {
+    let keys = text.split_whitespace().map(parse_key).collect::<Result<Vec<Key>, String>>()?;
+    if keys.is_empty() {
+        return Err(String::from("empty key sequence"));
+    }
+    Ok(keys)
+}
+
+// Key notation: a single character, a named key ("up", "pagedown", "f5"),
+// or either of those prefixed with "C-" (ctrl) or "A-" (alt).
+pub fn parse_key(text: &str) -> Result<Key, String> {
+    let lower = text.to_ascii_lowercase();
+    if let Some(rest) = lower.strip_prefix("c-").filter(|r| !r.is_empty()) {
+        return match (rest, single_char(&text[2..])) {
+            // Arrives as ^H, which is also Backspace on some terminals
+            ("backspace", _) => Err(format!("`{}` cannot be told apart from C-h, bind C-h instead", text)),
+            ("delete" | "del", _) => Ok(terminal::CTRL_DELETE),
+            ("left", _) => Ok(Key::CtrlLeft),
+            ("right", _) => Ok(Key::CtrlRight),
+            ("up", _) => Ok(Key::CtrlUp),
+            ("down", _) => Ok(Key::CtrlDown),
+            ("home", _) => Ok(Key::CtrlHome),
+            ("end", _) => Ok(Key::CtrlEnd),
+            (_, Some(c)) => match CTRL_PUNCTUATION.iter().find(|(p, _)| *p == c) {
+                Some((_, code)) => Ok(Key::Ctrl(*code)),
+                None => Ok(Key::Ctrl(c.to_ascii_lowercase())),
+            },
+            _ => Err(format!("unknown key `{}`", text)),
+        };
+    }
+    if let Some(rest) = lower.strip_prefix("a-").filter(|r| !r.is_empty()) {
+        return match (rest, single_char(&text[2..])) {
+            ("backspace", _) => Ok(Key::Alt('\x7F')),
+            ("left", _) => Ok(Key::AltLeft),
+            ("right", _) => Ok(Key::AltRight),
+            ("up", _) => Ok(Key::AltUp),
+            ("down", _) => Ok(Key::AltDown),
+            (_, Some(c)) => Ok(Key::Alt(c)),
+            _ => Err(format!("unknown key `{}`", text)),
+        };
+    }
+    if let Some(rest) = lower.strip_prefix("s-").filter(|r| !r.is_empty()) {
+        return match rest {
+            "left" => Ok(Key::ShiftLeft),
+            "right" => Ok(Key::ShiftRight),
+            "up" => Ok(Key::ShiftUp),
+            "down" => Ok(Key::ShiftDown),
+            "tab" => Ok(Key::BackTab),
+            _ => Err(format!("unknown key `{}`", text)),
+        };
+    }
+    if let Some(c) = single_char(text) {
+        return Ok(Key::Char(c));
+    }
+    let key = match lower.as_str() {
+        "up" => Key::Up,
+        "down" => Key::Down,
+        "left" => Key::Left,
+        "right" => Key::Right,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        "backspace" => Key::Backspace,
+        "pgup" => Key::PageUp,
+        "pgdn" => Key::PageDown,
+        "delete" | "del" => Key::Delete,
+        "insert" => Key::Insert,
+        "enter" | "return" => Key::Char('\n'),
+        "tab" => Key::Char('\t'),
+        "space" => Key::Char(' '),
+        "esc" | "escape" => Key::Esc,
+        _ => match lower.strip_prefix('f').and_then(|n| n.parse::<u8>().ok()) {
+            Some(n) if (1..=12).contains(&n) => Key::F(n),
+            _ => return Err(format!("unknown key `{}`", text)),
+        },
+    };
+    Ok(key)
+}
+
+// Terminals send C-\ C-] C-^ C-_ as the control codes after ^[, which termion reports as C-4 to C-7
+const CTRL_PUNCTUATION: [(char, char); 4] = [('\\', '4'), (']', '5'), ('^', '6'), ('_', '7')];
+
+fn single_char(text: &str) -> Option<char> {
+    let mut chars = text.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+// The inverse of parse_key, used to show pending chords in the status bar.
+pub fn key_name(key: &Key) -> String {
+    match key {
+        Key::Char('\n') => String::from("enter"),
+        Key::Char('\t') => String::from("tab"),
+        Key::Char(' ') => String::from("space"),
+        Key::Char(c) => c.to_string(),
+        Key::Ctrl('\x7F') => String::from("C-delete"),
+        Key::Ctrl(c @ '4'..='7') => format!("C-{}", CTRL_PUNCTUATION.iter().find(|(_, d)| d == c).unwrap().0),
+        Key::Ctrl(c) => format!("C-{}", c),
+        Key::Alt('\x7F') => String::from("A-backspace"),
+        Key::Alt(c) => format!("A-{}", c),
+        Key::F(n) => format!("f{}", n),
+        Key::CtrlLeft => String::from("C-left"),
+        Key::CtrlRight => String::from("C-right"),
+        Key::CtrlUp => String::from("C-up"),
+        Key::CtrlDown => String::from("C-down"),
+        Key::CtrlHome => String::from("C-home"),
+        Key::CtrlEnd => String::from("C-end"),
+        Key::AltLeft => String::from("A-left"),
+        Key::AltRight => String::from("A-right"),
+        Key::AltUp => String::from("A-up"),
+        Key::AltDown => String::from("A-down"),
+        Key::ShiftLeft => String::from("S-left"),
+        Key::ShiftRight => String::from("S-right"),
+        Key::ShiftUp => String::from("S-up"),
+        Key::ShiftDown => String::from("S-down"),
+        Key::BackTab => String::from("S-tab"),
+        other => format!("{:?}", other).to_ascii_lowercase(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_key_notation() {
+        assert_eq!(parse_key("x"), Ok(Key::Char('x')));
+        assert_eq!(parse_key("C-S"), Ok(Key::Ctrl('s')));
+        assert_eq!(parse_key("C-]"), Ok(Key::Ctrl('5')));
+        assert_eq!(parse_key("A-left"), Ok(Key::AltLeft));
+        assert_eq!(parse_key("S-tab"), Ok(Key::BackTab));
+        assert_eq!(parse_key("PageDown"), Ok(Key::PageDown));
+        assert_eq!(parse_key("enter"), Ok(Key::Char('\n')));
+        assert_eq!(parse_key("f12"), Ok(Key::F(12)));
+        assert_eq!(parse_key("f13"), Err(String::from("unknown key `f13`")));
+        assert_eq!(parse_key("C-backspace"), Err(String::from("`C-backspace` cannot be told apart from C-h, bind C-h instead")));
+        assert_eq!(parse_key("S-x"), Err(String::from("unknown key `S-x`")));
+        assert_eq!(parse_keys("C-x  C-s"), Ok(vec![Key::Ctrl('x'), Key::Ctrl('s')]));
+        assert_eq!(parse_keys(" "), Err(String::from("empty key sequence")));
+    }
+
+    #[test]
+    fn names_keys_as_they_are_written() {
+        for text in ["x", "C-s", "C-]", "A-backspace", "C-left", "S-tab", "enter", "space", "f5"] {
+            assert_eq!(key_name(&parse_key(text).unwrap()), text);
+        }
+    }
+
+    #[test]
+    fn chords_are_pending_until_complete() {
+        let mut bindings = Bindings::default();
+        bind(&mut bindings, "C-x C-s", "save").unwrap();
+        bind(&mut bindings, "C-x k", "quit").unwrap();
+        assert!(matches!(bindings.lookup(&[Key::Ctrl('x')]), Lookup::Pending));
+        assert!(matches!(bindings.lookup(&[Key::Ctrl('x'), Key::Ctrl('s')]), Lookup::Action(Action::Save)));
+        assert!(matches!(bindings.lookup(&[Key::Ctrl('x'), Key::Char('k')]), Lookup::Action(Action::Quit)));
+        assert!(matches!(bindings.lookup(&[Key::Ctrl('x'), Key::Char('j')]), Lookup::Unbound));
+        assert!(matches!(bindings.lookup(&[Key::Char('j')]), Lookup::Unbound));
+        assert_eq!(bind(&mut bindings, "C-x", "fly"), Err(String::from("unknown action `fly`")));
+    }
+
+    #[test]
+    fn ctrl_h_deletes_words_and_del_characters() {
+        let keymap = Keymap::new();
+        assert!(matches!(keymap.edit.lookup(&[Key::Ctrl('h')]), Lookup::Action(Action::DeleteWordBackward)));
+        assert!(matches!(keymap.edit.lookup(&[Key::Alt('\x7F')]), Lookup::Action(Action::DeleteWordBackward)));
+        assert!(matches!(keymap.edit.lookup(&[Key::Backspace]), Lookup::Action(Action::Backspace)));
+    }
+
+    #[test]
+    fn config_sections_bind_in_their_modes() {
+        let config = config::ConfigFile::parse("config.toml", "[keys]\nf2 = \"save\"\n[keys.edit]\nf3 = \"undo\"\nf4 = 1\n");
+        let mut keymap = Keymap::new();
+        let errors = keymap.load(&config);
+        assert_eq!(errors, ["config.toml:5: expected an action name, found 1"]);
+        assert!(matches!(keymap.edit.lookup(&[Key::F(2)]), Lookup::Action(Action::Save)));
+        assert!(matches!(keymap.command.lookup(&[Key::F(2)]), Lookup::Action(Action::Save)));
+        assert!(matches!(keymap.edit.lookup(&[Key::F(3)]), Lookup::Action(Action::Undo)));
+        assert!(matches!(keymap.command.lookup(&[Key::F(3)]), Lookup::Unbound));
+    }
+}
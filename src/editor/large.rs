@@ -0,0 +1,269 @@
+use std::fs::File;
+use std::ops::Range;
+use std::os::unix::io::AsRawFd;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+use std::{io, ptr, slice, thread};
+
+use crate::editor::encoding::Encoding;
+use crate::editor::util::GapBuffer;
+
+// Files too big to read into memory. The file is memory-mapped and a background thread
+// finds where its lines start; lines are turned into rows a chunk at a time, only when
+// something looks at them, and chunks far from the view are dropped again.
+// The file has to stay as it is while it is open: if another program truncates it,
+// reading the lost part of the mapping kills the editor.
+
+// Rows in a chunk, loaded and dropped together
+pub const CHUNK: usize = 4096;
+
+// A read-only mapping of a whole file
+struct Mapping {
+    ptr: *mut libc::c_void,
+    len: usize,
+}
+
+// Nothing writes through the mapping, so the indexing thread can read it too
+unsafe impl Send for Mapping {}
+unsafe impl Sync for Mapping {}
+
+impl Mapping {
+    fn new(file: &File) -> Result<Mapping, io::Error> {
+        let len = file.metadata()?.len() as usize;
+        if len == 0 {
+            return Ok(Mapping { ptr: ptr::null_mut(), len });
+        }
+        let ptr = unsafe { libc::mmap(ptr::null_mut(), len, libc::PROT_READ, libc::MAP_PRIVATE, file.as_raw_fd(), 0) };
+        if ptr == libc::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Mapping { ptr, len })
+    }
+
+    fn bytes(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+}
+
+impl Drop for Mapping {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            unsafe { libc::munmap(self.ptr, self.len) };
+        }
+    }
+}
+
+// What the indexing thread has found so far
+#[derive(Default)]
+struct Progress {
+    // Byte offset of the first line of every chunk
+    chunk_starts: Vec<usize>,
+    // Lines in the complete chunks, or in the whole file once done
+    lines: usize,
+    scanned: usize,
+    done: bool,
+}
+
+pub struct LargeFile {
+    map: Arc<Mapping>,
+    progress: Arc<Mutex<Progress>>,
+    // Tells the indexing thread to stop when the file is closed
+    closed: Arc<AtomicBool>,
+    // Copies of the progress made as of the last update, so the row count only changes then
+    chunk_starts: Vec<usize>,
+    lines: usize,
+    scanned: usize,
+    done: bool,
+    chunks: Vec<OnceLock<Vec<GapBuffer>>>,
+    // UTF-8, with or without a byte order mark, or Latin-1
+    encoding: Encoding,
+}
+
+impl LargeFile {
+    pub fn open(path: &str, encoding: Encoding) -> Result<LargeFile, io::Error> {
+        let map = Arc::new(Mapping::new(&File::open(path)?)?);
+        let progress = Arc::new(Mutex::new(Progress::default()));
+        let closed = Arc::new(AtomicBool::new(false));
+        let (indexed_map, indexed_progress, indexed_closed) = (Arc::clone(&map), Arc::clone(&progress), Arc::clone(&closed));
+        thread::spawn(move || index(&indexed_map, &indexed_progress, &indexed_closed));
+        let mut file = LargeFile {
+            map,
+            progress,
+            closed,
+            chunk_starts: Vec::new(),
+            lines: 0,
+            scanned: 0,
+            done: false,
+            chunks: Vec::new(),
+            encoding,
+        };
+        // The first screen can be shown as soon as the first chunk is indexed
+        while file.lines == 0 && !file.done {
+            thread::sleep(Duration::from_millis(1));
+            file.update();
+        }
+        Ok(file)
+    }
+
+    // Takes in what the indexing thread found since the last call. Returns whether there
+    // are more rows.
+    pub fn update(&mut self) -> bool {
+        let progress = self.progress.lock().unwrap();
+        let grown = progress.lines != self.lines;
+        self.chunk_starts.extend_from_slice(&progress.chunk_starts[self.chunk_starts.len()..]);
+        self.lines = progress.lines;
+        self.scanned = progress.scanned;
+        self.done = progress.done;
+        drop(progress);
+        self.chunks.resize_with(self.len().div_ceil(CHUNK), OnceLock::new);
+        grown
+    }
+
+    // Rows found so far. An empty file still has one, like any document.
+    pub fn len(&self) -> usize {
+        self.lines.max(1)
+    }
+
+    // Percentage of the file indexed, or None when all of it is
+    pub fn indexing(&self) -> Option<usize> {
+        (!self.done).then(|| self.scanned * 100 / self.map.len.max(1))
+    }
+
+    pub fn get(&self, row: usize) -> Option<&GapBuffer> {
+        let chunk = self.chunks.get(row / CHUNK)?.get_or_init(|| self.load(row / CHUNK));
+        chunk.get(row % CHUNK)
+    }
+
+    pub fn get_mut(&mut self, row: usize) -> Option<&mut GapBuffer> {
+        self.get(row)?;
+        self.chunks[row / CHUNK].get_mut()?.get_mut(row % CHUNK)
+    }
+
+    // Drops the loaded chunks that hold none of the rows in `keep`.
+    pub fn trim(&mut self, keep: Range<usize>) {
+        let keep = keep.start / CHUNK..keep.end.div_ceil(CHUNK);
+        for (idx, chunk) in self.chunks.iter_mut().enumerate() {
+            if !keep.contains(&idx) {
+                chunk.take();
+            }
+        }
+    }
+
+    fn load(&self, chunk: usize) -> Vec<GapBuffer> {
+        let count = CHUNK.min(self.len() - chunk * CHUNK);
+        let bytes = self.map.bytes();
+        let start = match self.chunk_starts.get(chunk) {
+            // The byte order mark is not part of the first line
+            Some(0) if self.encoding == Encoding::Utf8Bom => 3.min(bytes.len()),
+            Some(start) => *start,
+            None => bytes.len(),
+        };
+        let mut rows: Vec<GapBuffer> = bytes[start..]
+            .split(|b| *b == b'\n')
+            .take(count)
+            .map(|line| {
+                let line = line.strip_suffix(b"\r").unwrap_or(line);
+                match self.encoding {
+                    Encoding::Latin1 => GapBuffer::from_str(&line.iter().map(|b| char::from(*b)).collect::<String>()),
+                    _ => GapBuffer::from_str(&String::from_utf8_lossy(line)),
+                }
+            })
+            .collect();
+        rows.resize_with(count, || GapBuffer::from_str(""));
+        rows
+    }
+}
+
+impl Drop for LargeFile {
+    fn drop(&mut self) {
+        self.closed.store(true, Ordering::Relaxed);
+    }
+}
+
+// Counts lines, publishing the start of every chunk as it is found.
+fn index(map: &Mapping, progress: &Mutex<Progress>, closed: &AtomicBool) {
+    let bytes = map.bytes();
+    progress.lock().unwrap().chunk_starts.push(0);
+    let mut in_chunk = 0;
+    let mut start = 0;
+    while let Some(newline) = bytes[start..].iter().position(|b| *b == b'\n') {
+        start += newline + 1;
+        in_chunk += 1;
+        if in_chunk == CHUNK {
+            if closed.load(Ordering::Relaxed) {
+                return;
+            }
+            let mut progress = progress.lock().unwrap();
+            progress.chunk_starts.push(start);
+            progress.lines += CHUNK;
+            progress.scanned = start;
+            in_chunk = 0;
+        }
+    }
+    let mut progress = progress.lock().unwrap();
+    // The last line need not end with a newline
+    progress.lines += in_chunk + usize::from(start < bytes.len());
+    progress.scanned = bytes.len();
+    progress.done = true;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, process};
+
+    // Opens `content` as a large file and waits for it to be indexed
+    fn open(name: &str, content: &[u8], encoding: Encoding) -> LargeFile {
+        let path = env::temp_dir().join(format!("deft-txt-{}-{}", process::id(), name));
+        fs::write(&path, content).unwrap();
+        let mut file = LargeFile::open(path.to_str().unwrap(), encoding).unwrap();
+        // The mapping outlives the file's name
+        fs::remove_file(&path).unwrap();
+        while file.indexing().is_some() {
+            thread::sleep(Duration::from_millis(1));
+            file.update();
+        }
+        file
+    }
+
+    fn rows(file: &LargeFile) -> Vec<String> {
+        (0..file.len()).map(|row| file.get(row).unwrap().to_string()).collect()
+    }
+
+    #[test]
+    fn reads_lines_with_and_without_a_final_newline() {
+        assert_eq!(rows(&open("final", b"a\nb\r\nc\n", Encoding::Utf8)), vec!["a", "b", "c"]);
+        assert_eq!(rows(&open("unterminated", b"a\n\nc", Encoding::Utf8)), vec!["a", "", "c"]);
+        assert_eq!(rows(&open("newline", b"\n", Encoding::Utf8)), vec![""]);
+        let empty = open("empty", b"", Encoding::Utf8);
+        assert_eq!(rows(&empty), vec![""]);
+        assert!(empty.get(1).is_none());
+    }
+
+    #[test]
+    fn decodes_each_encoding() {
+        assert_eq!(rows(&open("bom", b"\xef\xbb\xbfa\xc3\xa9\nb", Encoding::Utf8Bom)), vec!["aé", "b"]);
+        assert_eq!(rows(&open("latin1", b"caf\xe9\n", Encoding::Latin1)), vec!["café"]);
+    }
+
+    #[test]
+    fn indexes_and_reloads_chunks() {
+        let text: String = (0..CHUNK * 2 + 10).map(|line| format!("{}\n", line)).collect();
+        let mut file = open("chunks", text.trim_end().as_bytes(), Encoding::Utf8);
+        assert_eq!(file.len(), CHUNK * 2 + 10);
+        for row in [0, CHUNK - 1, CHUNK, CHUNK * 2 + 9] {
+            assert_eq!(file.get(row).unwrap().to_string(), row.to_string());
+        }
+        assert!(file.get(CHUNK * 2 + 10).is_none());
+        file.get_mut(CHUNK).unwrap().insert_char(0, 'x');
+        // Dropped chunks come back as they are in the file
+        file.trim(0..10);
+        assert!(file.chunks[1].get().is_none());
+        assert_eq!(file.get(CHUNK).unwrap().to_string(), CHUNK.to_string());
+        assert!(file.chunks[0].get().is_some());
+    }
+}
@@ -0,0 +1,188 @@
+use crate::editor::window::{Rect, Split};
+
+// Where the panels of the screen go: the windows, the sidebar, the tab line and the status
+// lines. A layout is a tree of splits with a panel at each leaf, and each part of a split
+// asks for its size with a constraint. A panel's borders are drawn inside its rectangle.
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Constraint {
+    // This many cells, or what is left when there are fewer
+    Length(usize),
+    // A share of the split, in percent
+    Percent(usize),
+    // What the other parts leave, shared evenly with the other parts that fill
+    Fill,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Panel {
+    // The names of the open documents
+    TabLine,
+    // The windows
+    Main,
+    Sidebar,
+    // The status lines: the document, messages and the command line, and the mode
+    Status,
+    Message,
+    Mode,
+}
+
+// Characters drawn down the left and right edges of a panel
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Border {
+    pub left: Option<char>,
+    pub right: Option<char>,
+}
+
+impl Border {
+    pub const NONE: Border = Border { left: None, right: None };
+
+    fn width(&self) -> usize {
+        usize::from(self.left.is_some()) + usize::from(self.right.is_some())
+    }
+}
+
+pub enum Node {
+    Panel(Panel, Border),
+    Split(Split, Vec<(Constraint, Node)>),
+}
+
+pub struct Layout {
+    root: Node,
+    // Each panel shown and its rectangle, borders included, when the screen was last divided
+    placed: Vec<(Panel, Border, Rect)>,
+    // The panel the cursor is in, whose borders stand out
+    pub focus: Panel,
+}
+
+impl Layout {
+    pub fn new(root: Node) -> Layout {
+        Layout { root, placed: Vec::new(), focus: Panel::Main }
+    }
+
+    // Divides `area` among the panels.
+    pub fn place(&mut self, area: Rect) {
+        self.placed.clear();
+        place(&self.root, area, &mut self.placed);
+    }
+
+    pub fn panels(&self) -> &[(Panel, Border, Rect)] {
+        &self.placed
+    }
+
+    // Where what `panel` shows goes, inside its borders. Empty when it is not shown.
+    pub fn inner(&self, panel: Panel) -> Rect {
+        let Some((_, border, rect)) = self.placed.iter().find(|(shown, _, _)| *shown == panel) else {
+            return Rect::default();
+        };
+        Rect {
+            x: rect.x + usize::from(border.left.is_some()),
+            width: rect.width.saturating_sub(border.width()),
+            ..*rect
+        }
+    }
+}
+
+fn place(node: &Node, area: Rect, placed: &mut Vec<(Panel, Border, Rect)>) {
+    match node {
+        Node::Panel(panel, border) => placed.push((*panel, *border, area)),
+        Node::Split(split, parts) => {
+            let constraints: Vec<Constraint> = parts.iter().map(|(constraint, _)| *constraint).collect();
+            for ((_, child), rect) in parts.iter().zip(divide(area, *split, &constraints)) {
+                place(child, rect, placed);
+            }
+        },
+    }
+}
+
+// Divides `area` along `split` among parts asking for `constraints`. Lengths and percentages
+// are given out first, from the first part on, and the parts that fill share what is left.
+// Without any, the last part takes it.
+pub fn divide(area: Rect, split: Split, constraints: &[Constraint]) -> Vec<Rect> {
+    let total = area.length(split);
+    let mut left = total;
+    let mut sizes: Vec<usize> = constraints.iter()
+        .map(|constraint| {
+            let size = match constraint {
+                Constraint::Length(length) => (*length).min(left),
+                Constraint::Percent(percent) => (total * percent / 100).min(left),
+                Constraint::Fill => 0,
+            };
+            left -= size;
+            size
+        })
+        .collect();
+    let fills = constraints.iter().filter(|constraint| **constraint == Constraint::Fill).count();
+    match left.checked_div(fills) {
+        None => if let Some(last) = sizes.last_mut() {
+            *last += left;
+        },
+        Some(share) => {
+            let mut fill = 0;
+            for (size, constraint) in sizes.iter_mut().zip(constraints) {
+                if *constraint == Constraint::Fill {
+                    // The first ones take a cell of what does not divide evenly
+                    *size = share + usize::from(fill < left % fills);
+                    fill += 1;
+                }
+            }
+        },
+    }
+    let mut offset = 0;
+    sizes.into_iter()
+        .map(|size| {
+            let rect = area.part(split, offset, size);
+            offset += size;
+            rect
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Rect = Rect { x: 2, y: 1, width: 100, height: 30 };
+
+    fn widths(constraints: &[Constraint]) -> Vec<usize> {
+        divide(AREA, Split::Columns, constraints).iter().map(|rect| rect.width).collect()
+    }
+
+    #[test]
+    fn divide_gives_fixed_sizes_first() {
+        use Constraint::{Fill, Length, Percent};
+        assert_eq!(widths(&[Length(10), Fill, Percent(25)]), [10, 65, 25]);
+        // The parts that fill share what is left, the first ones taking what does not divide
+        assert_eq!(widths(&[Fill, Length(1), Fill, Fill]), [33, 1, 33, 33]);
+        assert_eq!(widths(&[Fill, Length(3), Fill]), [49, 3, 48]);
+        // Without one, the last part takes it
+        assert_eq!(widths(&[Percent(30), Length(5)]), [30, 70]);
+        // Later parts get what is left when earlier ones take it all
+        assert_eq!(widths(&[Length(90), Percent(50), Fill]), [90, 10, 0]);
+        assert_eq!(widths(&[]), Vec::<usize>::new());
+    }
+
+    #[test]
+    fn divide_places_parts_one_after_another() {
+        let rects = divide(AREA, Split::Rows, &[Constraint::Length(1), Constraint::Fill, Constraint::Length(2)]);
+        assert_eq!(rects, [
+            Rect { x: 2, y: 1, width: 100, height: 1 },
+            Rect { x: 2, y: 2, width: 100, height: 27 },
+            Rect { x: 2, y: 29, width: 100, height: 2 },
+        ]);
+    }
+
+    #[test]
+    fn panels_go_inside_their_borders() {
+        let border = Border { left: Some('┊'), right: Some('┃') };
+        let mut layout = Layout::new(Node::Split(Split::Columns, vec![
+            (Constraint::Percent(70), Node::Panel(Panel::Main, Border::NONE)),
+            (Constraint::Fill, Node::Panel(Panel::Sidebar, border)),
+        ]));
+        layout.place(AREA);
+        assert_eq!(layout.panels().len(), 2);
+        assert_eq!(layout.inner(Panel::Main), Rect { x: 2, y: 1, width: 70, height: 30 });
+        assert_eq!(layout.inner(Panel::Sidebar), Rect { x: 73, y: 1, width: 28, height: 30 });
+        assert_eq!(layout.inner(Panel::Status), Rect::default());
+    }
+}
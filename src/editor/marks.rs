@@ -0,0 +1,198 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::{env, fs};
+
+// Named positions in a document. The document moves them along as text is
+// inserted and removed so they stay on the text they were set on.
+
+#[derive(Default)]
+pub struct Marks {
+    positions: BTreeMap<char, (usize, usize)>,
+}
+
+impl Marks {
+    pub fn set(&mut self, name: char, row: usize, col: usize) {
+        self.positions.insert(name, (row, col));
+    }
+
+    pub fn get(&self, name: char) -> Option<(usize, usize)> {
+        self.positions.get(&name).copied()
+    }
+
+    pub fn remove(&mut self, name: char) -> bool {
+        self.positions.remove(&name).is_some()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (char, (usize, usize))> + '_ {
+        self.positions.iter().map(|(name, position)| (*name, *position))
+    }
+
+    // The first mark on a row, for the gutter
+    pub fn on_row(&self, row: usize) -> Option<char> {
+        self.iter().find(|(_, (r, _))| *r == row).map(|(name, _)| name)
+    }
+
+    pub fn positions_mut(&mut self) -> impl Iterator<Item = &mut (usize, usize)> {
+        self.positions.values_mut()
+    }
+}
+
+// Moving positions along with edits, for marks and anything else that has to stay on its text
+
+// `count` characters were inserted at `row`, `col`
+pub fn inserted<'a>(positions: impl Iterator<Item = &'a mut (usize, usize)>, row: usize, col: usize, count: usize) {
+    for (r, c) in positions {
+        if *r == row && *c >= col {
+            *c += count;
+        }
+    }
+}
+
+// The characters in `start`..`end` of `row` were removed
+pub fn removed<'a>(positions: impl Iterator<Item = &'a mut (usize, usize)>, row: usize, start: usize, end: usize) {
+    for (r, c) in positions {
+        if *r == row && *c >= start {
+            *c = start + c.saturating_sub(end);
+        }
+    }
+}
+
+// `row` was split in two at `col`
+pub fn split<'a>(positions: impl Iterator<Item = &'a mut (usize, usize)>, row: usize, col: usize) {
+    for (r, c) in positions {
+        if *r > row {
+            *r += 1;
+        } else if *r == row && *c >= col {
+            *r += 1;
+            *c -= col;
+        }
+    }
+}
+
+// The row after `row` was appended to it, which was `len` characters long
+pub fn joined<'a>(positions: impl Iterator<Item = &'a mut (usize, usize)>, row: usize, len: usize) {
+    for (r, c) in positions {
+        if *r == row + 1 {
+            *r = row;
+            *c += len;
+        } else if *r > row + 1 {
+            *r -= 1;
+        }
+    }
+}
+
+// `removed` rows at `row` were replaced by `inserted` rows, as undo and redo do
+pub fn replaced<'a>(positions: impl Iterator<Item = &'a mut (usize, usize)>, row: usize, removed: usize, inserted: usize) {
+    rows_replaced(positions.map(|(r, _)| r), row, removed, inserted);
+}
+
+// The same for rows that are not positions in the text, such as the top row of a window
+pub fn rows_replaced<'a>(rows: impl Iterator<Item = &'a mut usize>, row: usize, removed: usize, inserted: usize) {
+    for r in rows {
+        if *r >= row + removed {
+            *r = *r + inserted - removed;
+        } else if *r >= row + inserted {
+            *r = (row + inserted).saturating_sub(1).max(row);
+        }
+    }
+}
+
+// Marks of every file that has any are kept in one file, a line per mark:
+// path, name, row and column separated by tabs.
+fn store_path() -> Option<PathBuf> {
+    let base = match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => PathBuf::from(env::var_os("HOME")?).join(".local/share"),
+    };
+    Some(base.join("deft-txt").join("marks"))
+}
+
+// Marks are stored under the absolute path so the same file is found from any directory
+fn key(path: &str) -> String {
+    fs::canonicalize(path).unwrap_or_else(|_| Path::new(path).to_path_buf()).display().to_string()
+}
+
+fn parse_line(line: &str) -> Option<(&str, char, usize, usize)> {
+    let mut fields = line.split('\t');
+    let path = fields.next()?;
+    let name = fields.next()?.chars().next()?;
+    let row = fields.next()?.parse().ok()?;
+    let col = fields.next()?.parse().ok()?;
+    Some((path, name, row, col))
+}
+
+pub fn load(path: &str) -> Marks {
+    let mut marks = Marks::default();
+    let key = key(path);
+    let content = store_path().and_then(|store| fs::read_to_string(store).ok()).unwrap_or_default();
+    for (_, name, row, col) in content.lines().filter_map(parse_line).filter(|(p, ..)| *p == key) {
+        marks.set(name, row, col);
+    }
+    marks
+}
+
+// Replaces the stored marks of `path` with `marks`.
+pub fn store(path: &str, marks: &Marks) -> Result<(), std::io::Error> {
+    let Some(store) = store_path() else {
+        return Ok(());
+    };
+    let key = key(path);
+    let content = fs::read_to_string(&store).unwrap_or_default();
+    let mut lines: Vec<String> = content.lines()
+        .filter(|line| parse_line(line).is_some_and(|(p, ..)| p != key))
+        .map(String::from)
+        .collect();
+    lines.extend(marks.iter().map(|(name, (row, col))| format!("{}\t{}\t{}\t{}", key, name, row, col)));
+    if let Some(dir) = store.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::write(store, lines.join("\n") + "\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The positions once `edit` has moved them
+    fn moved(mut positions: Vec<(usize, usize)>, edit: impl FnOnce(std::slice::IterMut<(usize, usize)>)) -> Vec<(usize, usize)> {
+        edit(positions.iter_mut());
+        positions
+    }
+
+    #[test]
+    fn follows_inserted_and_removed_characters() {
+        let positions = vec![(0, 1), (0, 2), (0, 4), (0, 7), (1, 4)];
+        assert_eq!(moved(positions.clone(), |p| inserted(p, 0, 2, 3)), vec![(0, 1), (0, 5), (0, 7), (0, 10), (1, 4)]);
+        // Marks inside the removed text go to where it was
+        assert_eq!(moved(positions, |p| removed(p, 0, 2, 5)), vec![(0, 1), (0, 2), (0, 2), (0, 4), (1, 4)]);
+    }
+
+    #[test]
+    fn follows_split_and_joined_rows() {
+        let positions = vec![(0, 9), (1, 2), (1, 3), (1, 5), (2, 0)];
+        assert_eq!(moved(positions.clone(), |p| split(p, 1, 3)), vec![(0, 9), (1, 2), (2, 0), (2, 2), (3, 0)]);
+        assert_eq!(moved(positions, |p| joined(p, 0, 10)), vec![(0, 9), (0, 12), (0, 13), (0, 15), (1, 0)]);
+        let positions = vec![(0, 9), (1, 2), (2, 0), (2, 2), (3, 0)];
+        assert_eq!(moved(positions, |p| joined(p, 1, 3)), vec![(0, 9), (1, 2), (1, 3), (1, 5), (2, 0)]);
+    }
+
+    #[test]
+    fn follows_replaced_rows() {
+        let rows = |removed: usize, inserted: usize| {
+            let mut rows = vec![1, 2, 3, 4, 5, 9];
+            rows_replaced(rows.iter_mut(), 2, removed, inserted);
+            rows
+        };
+        // Rows that went away collapse onto the last of the new ones
+        assert_eq!(rows(3, 1), vec![1, 2, 2, 2, 3, 7]);
+        assert_eq!(rows(3, 2), vec![1, 2, 3, 3, 4, 8]);
+        assert_eq!(rows(1, 3), vec![1, 2, 5, 6, 7, 11]);
+        assert_eq!(rows(2, 0), vec![1, 2, 2, 2, 3, 7]);
+        let positions = vec![(1, 4), (3, 4), (6, 1)];
+        assert_eq!(moved(positions, |p| replaced(p, 2, 2, 4)), vec![(1, 4), (3, 4), (8, 1)]);
+    }
+}
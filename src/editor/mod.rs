@@ -1,5 +1,28 @@
+#[allow(clippy::module_inception)]
 pub mod editor;
 pub mod util;
 pub mod document;
 pub mod terminal;
-pub mod highlighting;
\ No newline at end of file
+pub mod highlighting;
+pub mod config;
+pub mod keymap;
+pub mod theme;
+pub mod motion;
+pub mod view;
+pub mod wrap;
+pub mod indent;
+pub mod history;
+pub mod pairs;
+pub mod cli;
+pub mod marks;
+pub mod block;
+pub mod swap;
+pub mod watch;
+pub mod diff;
+pub mod large;
+pub mod encoding;
+pub mod hex;
+pub mod bmp;
+pub mod pipe;
+pub mod window;
+pub mod layout;
@@ -0,0 +1,216 @@
+use std::ops::Range;
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editor::document::Document;
+use crate::editor::highlighting::{self, Kind};
+
+// Word and paragraph motions over document text. Columns are character indices.
+
+// Character ranges of the words in a line, using Unicode word boundaries.
+// Runs of whitespace are not words, punctuation is.
+fn words(line: &str) -> Vec<(usize, usize)> {
+    let mut words = Vec::new();
+    let mut col = 0;
+    for segment in line.split_word_bounds() {
+        let len = segment.chars().count();
+        if !segment.chars().all(char::is_whitespace) {
+            words.push((col, col + len));
+        }
+        col += len;
+    }
+    words
+}
+
+// Column at the end of the word under or after `col`, or None if there is none.
+pub fn next_word_end(line: &str, col: usize) -> Option<usize> {
+    words(line).into_iter().find(|(_, end)| *end > col).map(|(_, end)| end)
+}
+
+// Column at the start of the word under or before `col`, or None if there is none.
+pub fn prev_word_start(line: &str, col: usize) -> Option<usize> {
+    words(line).into_iter().rev().find(|(start, _)| *start < col).map(|(start, _)| start)
+}
+
+// Range of the word under `col`, or of the one ending there.
+pub fn word_at(line: &str, col: usize) -> Option<(usize, usize)> {
+    let words = words(line);
+    let under = words.iter().find(|(start, end)| *start <= col && col < *end);
+    under.or_else(|| words.iter().find(|(_, end)| *end == col)).copied()
+}
+
+// Start columns of the whole-word occurrences of `word` in a line
+pub fn word_occurrences(line: &str, word: &str) -> Vec<usize> {
+    let chars: Vec<char> = line.chars().collect();
+    words(line)
+        .into_iter()
+        .filter(|(start, end)| chars[*start..*end].iter().copied().eq(word.chars()))
+        .map(|(start, _)| start)
+        .collect()
+}
+
+pub fn is_blank(line: &str) -> bool {
+    line.chars().all(char::is_whitespace)
+}
+
+// Row of the first blank line after the paragraph at or below `row`,
+// or the last row if the document ends first.
+pub fn next_paragraph(rows: usize, row: usize, blank: impl Fn(usize) -> bool) -> usize {
+    let last = rows.saturating_sub(1);
+    let mut row = row + 1;
+    while row < last && blank(row) {
+        row += 1;
+    }
+    while row < last && !blank(row) {
+        row += 1;
+    }
+    row.min(last)
+}
+
+// Row of the first blank line before the paragraph at or above `row`, or 0.
+pub fn prev_paragraph(row: usize, blank: impl Fn(usize) -> bool) -> usize {
+    let mut row = row.saturating_sub(1);
+    while row > 0 && blank(row) {
+        row -= 1;
+    }
+    while row > 0 && !blank(row) {
+        row -= 1;
+    }
+    row
+}
+
+// Position of the bracket matching the one at `row`, `col`, ignoring brackets in strings and comments.
+// Only `rows` are searched, and `states` holds the lexer state at the start of each row up to their end.
+pub fn matching_bracket(document: &Document, states: &[highlighting::State], row: usize, col: usize, rows: Range<usize>) -> Option<(usize, usize)> {
+    let syntax = highlighting::syntax(&document.file_type);
+    let line = |r: usize| -> (Vec<char>, Vec<Kind>) {
+        let text = document.rows[r].to_string();
+        let kinds = highlighting::classify(&text, states[r], syntax).0;
+        (text.chars().collect(), kinds)
+    };
+    let (chars, kinds) = line(row);
+    if kinds.get(col) != Some(&Kind::Code) {
+        return None;
+    }
+    let (open, close, forward) = match chars[col] {
+        '(' => ('(', ')', true),
+        '[' => ('[', ']', true),
+        '{' => ('{', '}', true),
+        ')' => ('(', ')', false),
+        ']' => ('[', ']', false),
+        '}' => ('{', '}', false),
+        _ => return None,
+    };
+
+    let mut depth = 0usize;
+    let mut visit = |r: usize, chars: &[char], kinds: &[Kind], idx: usize| {
+        if kinds[idx] != Kind::Code {
+            return None;
+        }
+        let (inner, outer) = if forward { (open, close) } else { (close, open) };
+        if chars[idx] == inner {
+            depth += 1;
+        } else if chars[idx] == outer {
+            depth -= 1;
+            if depth == 0 {
+                return Some((r, idx));
+            }
+        }
+        None
+    };
+    if forward {
+        for r in row..rows.end.min(document.rows()) {
+            let (chars, kinds) = if r == row { (chars.clone(), kinds.clone()) } else { line(r) };
+            let start = if r == row { col } else { 0 };
+            if let Some(found) = (start..chars.len()).find_map(|idx| visit(r, &chars, &kinds, idx)) {
+                return Some(found);
+            }
+        }
+    } else {
+        for r in (rows.start..=row).rev() {
+            let (chars, kinds) = if r == row { (chars.clone(), kinds.clone()) } else { line(r) };
+            let end = if r == row { col + 1 } else { chars.len() };
+            if let Some(found) = (0..end).rev().find_map(|idx| visit(r, &chars, &kinds, idx)) {
+                return Some(found);
+            }
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bracket(text: &str, row: usize, col: usize) -> Option<(usize, usize)> {
+        let document = Document::from_text("test.rs", text);
+        let states = document.line_states(document.rows());
+        matching_bracket(&document, &states, row, col, 0..document.rows())
+    }
+
+    #[test]
+    fn matches_nested_brackets() {
+        let text = "f(a[0], (b))";
+        assert_eq!(bracket(text, 0, 1), Some((0, 11)));
+        assert_eq!(bracket(text, 0, 11), Some((0, 1)));
+        assert_eq!(bracket(text, 0, 3), Some((0, 5)));
+        assert_eq!(bracket(text, 0, 8), Some((0, 10)));
+        assert_eq!(bracket(text, 0, 0), None);
+        assert_eq!(bracket("(()", 0, 0), None);
+    }
+
+    #[test]
+    fn matches_across_lines_skipping_strings_and_comments() {
+        let text = "fn a() {\n    b(\"}\", ')'); // }\n    /* { */\n}\n";
+        assert_eq!(bracket(text, 0, 7), Some((3, 0)));
+        assert_eq!(bracket(text, 3, 0), Some((0, 7)));
+        assert_eq!(bracket(text, 1, 5), Some((1, 14)));
+        // A bracket in a string has no match
+        assert_eq!(bracket(text, 1, 7), None);
+    }
+
+    #[test]
+    fn only_searches_the_given_rows() {
+        let document = Document::from_text("test.rs", "{\n\n}\n");
+        let states = document.line_states(document.rows());
+        assert_eq!(matching_bracket(&document, &states, 0, 0, 0..2), None);
+        assert_eq!(matching_bracket(&document, &states, 0, 0, 0..3), Some((2, 0)));
+    }
+
+    #[test]
+    fn moves_by_words_punctuation_and_whitespace_runs() {
+        let line = "  let x = foo(bar,  baz);";
+        assert_eq!(next_word_end(line, 0), Some(5));
+        assert_eq!(next_word_end(line, 5), Some(7));
+        assert_eq!(next_word_end(line, 10), Some(13));
+        assert_eq!(next_word_end(line, 13), Some(14));
+        assert_eq!(next_word_end(line, 17), Some(18));
+        assert_eq!(next_word_end(line, 25), None);
+        assert_eq!(prev_word_start(line, 25), Some(24));
+        assert_eq!(prev_word_start(line, 20), Some(17));
+        assert_eq!(prev_word_start(line, 2), None);
+        assert_eq!(next_word_end("", 0), None);
+    }
+
+    #[test]
+    fn finds_words_under_the_cursor() {
+        let line = "é_x, ab ab_c ab";
+        assert_eq!(word_at(line, 1), Some((0, 3)));
+        assert_eq!(word_at(line, 3), Some((3, 4)));
+        assert_eq!(word_at(line, 4), Some((3, 4)));
+        assert_eq!(word_at(line, 7), Some((5, 7)));
+        assert_eq!(word_occurrences(line, "ab"), vec![5, 13]);
+    }
+
+    #[test]
+    fn moves_between_paragraphs() {
+        let lines = ["a", "b", "", "", "c", "", "d"];
+        let blank = |row: usize| is_blank(lines[row]);
+        assert_eq!(next_paragraph(lines.len(), 0, blank), 2);
+        assert_eq!(next_paragraph(lines.len(), 2, blank), 5);
+        assert_eq!(next_paragraph(lines.len(), 5, blank), 6);
+        assert_eq!(prev_paragraph(6, blank), 3);
+        assert_eq!(prev_paragraph(4, blank), 0);
+        assert_eq!(prev_paragraph(1, blank), 0);
+        assert!(is_blank(" \t"));
+    }
+}
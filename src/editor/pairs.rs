@@ -0,0 +1,80 @@
+// Brackets and quotes that are typed in pairs.
+
+const CODE: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"'), ('\'', '\'')];
+// Single quotes also start lifetimes and character literals
+const RUST: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')];
+const LISP: &[(char, char)] = &[('(', ')'), ('[', ']'), ('"', '"')];
+const MARKUP: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('<', '>'), ('"', '"')];
+// Prose is full of apostrophes
+const TEXT: &[(char, char)] = &[('(', ')'), ('[', ']'), ('{', '}'), ('"', '"')];
+
+pub fn pairs(file_type: &str) -> &'static [(char, char)] {
+    match file_type {
+        "rs" => RUST,
+        "lisp" | "el" | "clj" | "scm" => LISP,
+        "html" | "xml" | "svg" => MARKUP,
+        "txt" | "md" => TEXT,
+        _ => CODE,
+    }
+}
+
+pub fn closer(pairs: &[(char, char)], opener: char) -> Option<char> {
+    pairs.iter().find(|(open, _)| *open == opener).map(|(_, close)| *close)
+}
+
+pub fn is_closer(pairs: &[(char, char)], c: char) -> bool {
+    pairs.iter().any(|(_, close)| *close == c)
+}
+
+// Whether typing `opener` between `before` and `after` should also insert its closer.
+// Only done where the pair cannot be taken for part of a word.
+pub fn should_pair(pairs: &[(char, char)], opener: char, before: Option<char>, after: Option<char>) -> bool {
+    let Some(close) = closer(pairs, opener) else {
+        return false;
+    };
+    let free_after = after.is_none_or(|c| c.is_whitespace() || is_closer(pairs, c));
+    if close == opener {
+        // A quote right after a word or backslash is an apostrophe or escaped
+        free_after && before.is_none_or(|c| !c.is_alphanumeric() && c != '\\' && c != opener)
+    } else {
+        free_after
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pairs_only_where_it_cannot_join_a_word() {
+        assert!(should_pair(CODE, '(', None, None));
+        assert!(should_pair(CODE, '(', Some('f'), Some(' ')));
+        assert!(should_pair(CODE, '(', Some(' '), Some(')')));
+        // Before a word character the opener is wrapping what follows
+        assert!(!should_pair(CODE, '(', Some(' '), Some('x')));
+        assert!(!should_pair(CODE, '"', None, Some('1')));
+        assert!(!should_pair(CODE, ')', None, None));
+    }
+
+    #[test]
+    fn quotes_after_words_and_backslashes_stay_single() {
+        assert!(should_pair(CODE, '"', Some(' '), None));
+        assert!(should_pair(CODE, '\'', Some('('), Some(')')));
+        assert!(!should_pair(CODE, '\'', Some('n'), Some(' ')));
+        assert!(!should_pair(CODE, '"', Some('\\'), None));
+        assert!(!should_pair(CODE, '"', Some('"'), None));
+        // Lifetimes and apostrophes are not pairs at all
+        assert!(!should_pair(RUST, '\'', Some('<'), Some('>')));
+        assert!(!should_pair(pairs("md"), '\'', Some(' '), None));
+    }
+
+    #[test]
+    fn closers_are_typed_over() {
+        assert!(is_closer(CODE, ')'));
+        assert!(is_closer(CODE, '"'));
+        assert!(!is_closer(CODE, '('));
+        assert!(is_closer(MARKUP, '>'));
+        assert!(!is_closer(CODE, '>'));
+        assert_eq!(closer(LISP, '{'), None);
+    }
+}
@@ -0,0 +1,117 @@
+use std::io::{self, Read};
+use std::os::unix::io::RawFd;
+
+use crate::editor::document::Document;
+
+// Text piped to the editor on standard input, for using it as a pager. Other programs
+// color their output and man pages make text bold by typing characters over themselves,
+// so escape sequences and backspaces are taken out to leave the text as it would look.
+
+// Reads all of standard input.
+pub fn read_all() -> Result<String, io::Error> {
+    let mut bytes = Vec::new();
+    io::stdin().read_to_end(&mut bytes)?;
+    Ok(clean(&String::from_utf8_lossy(&bytes)))
+}
+
+// The text as a terminal would show it, without escape sequences or what backspaces undo
+pub fn clean(text: &str) -> String {
+    let mut cleaned = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\x1B' => match chars.next() {
+                // A control sequence ends with a byte from @ to ~
+                Some('[') => {
+                    chars.by_ref().find(|c| ('@'..='~').contains(c));
+                },
+                // An operating system command ends with BEL or ESC \
+                Some(']') => while let Some(c) = chars.next() {
+                    if c == '\x07' || c == '\x1B' && chars.next_if_eq(&'\\').is_some() {
+                        break;
+                    }
+                },
+                _ => (),
+            },
+            '\x08' => {
+                cleaned.pop();
+            },
+            '\r' if chars.peek() == Some(&'\n') => (),
+            c => cleaned.push(c),
+        }
+    }
+    cleaned
+}
+
+// Chunks read at a time
+const MAX_CHUNKS: usize = 16;
+
+// Standard input read as it arrives, to follow a program that is still writing to it
+pub struct Pipe {
+    fd: RawFd,
+    // The start of a line whose end has not arrived yet
+    partial: Vec<u8>,
+    // Lines read so far
+    pub lines: usize,
+    pub closed: bool,
+}
+
+impl Pipe {
+    pub fn new() -> Pipe {
+        let fd = libc::STDIN_FILENO;
+        // Reading never waits, the editor polls for input instead
+        unsafe { libc::fcntl(fd, libc::F_SETFL, libc::fcntl(fd, libc::F_GETFL) | libc::O_NONBLOCK) };
+        Pipe { fd, partial: Vec::new(), lines: 0, closed: false }
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    // The lines that arrived since the last call. The last line need not end with a
+    // newline once the input is closed.
+    pub fn read(&mut self) -> Result<Vec<String>, io::Error> {
+        let mut chunk = [0u8; 65536];
+        // A program that writes without pause still leaves time for keys
+        for _ in 0..MAX_CHUNKS {
+            let count = unsafe { libc::read(self.fd, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) };
+            match count {
+                0 => {
+                    self.closed = true;
+                    break;
+                },
+                n if n > 0 => self.partial.extend_from_slice(&chunk[..n as usize]),
+                _ => match io::Error::last_os_error() {
+                    err if err.kind() == io::ErrorKind::Interrupted => (),
+                    err if err.kind() == io::ErrorKind::WouldBlock => break,
+                    err => return Err(err),
+                },
+            }
+        }
+        // The last line need not end with a newline
+        if self.closed && self.partial.last().is_some_and(|b| *b != b'\n') {
+            self.partial.push(b'\n');
+        }
+        let Some(newline) = self.partial.iter().rposition(|b| *b == b'\n') else {
+            return Ok(Vec::new());
+        };
+        let complete: Vec<u8> = self.partial.drain(..=newline).collect();
+        let lines: Vec<String> = String::from_utf8_lossy(&complete[..newline])
+            .split('\n')
+            .map(|line| clean(line.strip_suffix('\r').unwrap_or(line)))
+            .collect();
+        self.lines += lines.len();
+        Ok(lines)
+    }
+
+    // Adds the lines that arrived since the last call to the end of `document`. Returns
+    // whether there were any.
+    pub fn read_into(&mut self, document: &mut Document) -> Result<bool, io::Error> {
+        let first = self.lines == 0;
+        let lines = self.read()?;
+        if !lines.is_empty() {
+            document.append_lines(&lines, first);
+        }
+        Ok(!lines.is_empty())
+    }
+}
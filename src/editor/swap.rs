@@ -0,0 +1,49 @@
+use std::path::{Path, PathBuf};
+use std::{fs, io, process};
+
+// Swap files keep a copy of unsaved text next to the file being edited so it can be
+// recovered after a crash. The swap file of `dir/NAME` is `dir/.NAME.swp`: a header line
+// naming the editor process that writes it, then the text.
+
+const HEADER: &str = "deft-txt swap";
+
+pub struct Swap {
+    pub pid: u32,
+    pub text: String,
+}
+
+impl Swap {
+    // Whether the editor that wrote it has gone, rather than still editing the file
+    pub fn is_stale(&self) -> bool {
+        if self.pid == process::id() {
+            return true;
+        }
+        let alive = unsafe { libc::kill(self.pid as libc::pid_t, 0) } == 0;
+        !alive && io::Error::last_os_error().raw_os_error() == Some(libc::ESRCH)
+    }
+}
+
+pub fn path(file_path: &str) -> PathBuf {
+    let path = Path::new(file_path);
+    let name = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+    path.with_file_name(format!(".{}.swp", name))
+}
+
+pub fn read(file_path: &str) -> Option<Swap> {
+    let content = fs::read_to_string(path(file_path)).ok()?;
+    let (header, text) = content.split_once('\n')?;
+    let pid = header.strip_prefix(HEADER)?.trim().parse().ok()?;
+    Some(Swap { pid, text: String::from(text) })
+}
+
+pub fn write(file_path: &str, text: &str) -> Result<(), io::Error> {
+    let swap = path(file_path);
+    // Written beside it first, so a crash halfway leaves the last swap file whole
+    let partial = swap.with_extension("swp~");
+    fs::write(&partial, format!("{} {}\n{}", HEADER, process::id(), text))?;
+    fs::rename(partial, swap)
+}
+
+pub fn remove(file_path: &str) {
+    let _ = fs::remove_file(path(file_path));
+}
@@ -1,11 +1,69 @@
-use std::io::{self, stdout, Write};
-use termion::event::Key;
-use termion::input::TermRead;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, stdout, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::rc::Rc;
+use std::time::Duration;
+use termion::event::{Event, Key};
+use termion::input::{EventsAndRaw, TermReadEventsAndRaw};
 use termion::raw::{IntoRawMode, RawTerminal};
 
+// termion has no key for these, so they are reported as otherwise unreachable chords.
+// Ctrl+Delete arrives as CSI 3;5~ and is mapped to C-delete (Ctrl + DEL).
+pub const CTRL_DELETE: Key = Key::Ctrl('\x7F');
+// The terminal window getting focus (CSI I, once focus reporting is on) is C-NUL.
+pub const FOCUS_GAINED: Key = Key::Ctrl('\0');
+
+const FOCUS_REPORTING_ON: &str = "\x1B[?1004h";
+const FOCUS_REPORTING_OFF: &str = "\x1B[?1004l";
+
+
+// Terminal input, handed to termion a key at a time. termion keeps whatever it is given
+// beyond the key it parses to itself, where polling for input would not see it.
+struct Input {
+    fd: RawFd,
+    // Bytes read from the terminal that termion has not been given yet
+    unread: Rc<RefCell<VecDeque<u8>>>,
+}
+
+impl Read for Input {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let mut unread = self.unread.borrow_mut();
+        while unread.is_empty() {
+            let mut chunk = [0u8; 1024];
+            let count = unsafe { libc::read(self.fd, chunk.as_mut_ptr() as *mut libc::c_void, chunk.len()) };
+            match count {
+                0 => return Ok(0),
+                n if n > 0 => unread.extend(&chunk[..n as usize]),
+                _ => {
+                    let err = io::Error::last_os_error();
+                    if err.kind() != io::ErrorKind::Interrupted {
+                        return Err(err);
+                    }
+                },
+            }
+        }
+        // termion asks for two bytes to tell Esc from an escape sequence, which it then reads
+        // to the end. Any other key is one byte, and its next byte would be kept back.
+        let wanted = if unread.front() == Some(&0x1B) { 2 } else { 1 };
+        let count = wanted.min(buf.len()).min(unread.len());
+        for (slot, byte) in buf.iter_mut().zip(unread.drain(..count)) {
+            *slot = byte;
+        }
+        Ok(count)
+    }
+}
 
 pub struct Terminal {
     stdout: RawTerminal<std::io::Stdout>,
+    // Where keys are read from: standard input, or the terminal itself when text is piped in
+    fd: RawFd,
+    // Kept open while keys are read from it
+    _tty: Option<File>,
+    // Kept for the lifetime of the terminal so bytes read ahead are not lost between keys
+    input: EventsAndRaw<Input>,
+    unread: Rc<RefCell<VecDeque<u8>>>,
     pub height: usize,
     pub width: usize,
 }
@@ -14,9 +72,21 @@ impl Terminal {
     pub fn new() -> Result<Self, io::Error> {
         let (width, height) = termion::terminal_size()?;
         let stdout = stdout().into_raw_mode()?;
+        let unread = Rc::new(RefCell::new(VecDeque::new()));
+        let tty = match unsafe { libc::isatty(libc::STDIN_FILENO) } {
+            1 => None,
+            _ => Some(File::open("/dev/tty")?),
+        };
+        let fd = tty.as_ref().map_or(libc::STDIN_FILENO, File::as_raw_fd);
+        let input = Input { fd, unread: Rc::clone(&unread) };
+        print!("{}", FOCUS_REPORTING_ON);
 
         Ok(Terminal {
             stdout,
+            fd,
+            _tty: tty,
+            input: input.events_and_raw(),
+            unread,
             height: height as usize,
             width: width as usize,
         })
@@ -26,20 +96,43 @@ impl Terminal {
         print!("{}", termion::clear::All);
     }
 
-    pub fn clear_line(&self) {
-        print!("{}", termion::clear::CurrentLine);
-    }
-
     pub fn flush(&mut self) -> Result<(), io::Error> {
         self.stdout.flush()
     }
 
-    pub fn read_key(&self) -> Result<Key, io::Error> {
+    pub fn read_key(&mut self) -> Result<Key, io::Error> {
         loop {
-            if let Some(key) = io::stdin().lock().keys().next() {
-                return key;
+            match self.input.next() {
+                Some(Ok((Event::Key(key), _))) => return Ok(key),
+                Some(Ok((Event::Unsupported(raw), _))) if raw == b"\x1B[3;5~" => return Ok(CTRL_DELETE),
+                Some(Ok((Event::Unsupported(raw), _))) if raw == b"\x1B[I" => return Ok(FOCUS_GAINED),
+                Some(Ok(_)) => (),
+                Some(Err(err)) => return Err(err),
+                None => return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "input closed")),
+            }
+        }
+    }
+
+    // Waits up to `timeout` for a key, or until there is one without a timeout. Gives up
+    // early, returning None, when one of `others` has something to read.
+    pub fn poll_key(&mut self, timeout: Option<Duration>, others: &[RawFd]) -> Result<Option<Key>, io::Error> {
+        if self.unread.borrow().is_empty() {
+            let mut fds: Vec<libc::pollfd> = [self.fd].iter().chain(others)
+                .map(|fd| libc::pollfd { fd: *fd, events: libc::POLLIN, revents: 0 })
+                .collect();
+            // Rounded up, so a deadline less than a millisecond away is not polled for in a loop
+            let ms = timeout.map_or(-1, |t| (t.as_micros().div_ceil(1000)).min(i32::MAX as u128) as i32);
+            match unsafe { libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, ms) } {
+                0 => return Ok(None),
+                n if n < 0 => {
+                    let err = io::Error::last_os_error();
+                    return if err.kind() == io::ErrorKind::Interrupted { Ok(None) } else { Err(err) };
+                },
+                _ if fds[0].revents == 0 => return Ok(None),
+                _ => (),
             }
         }
+        self.read_key().map(Some)
     }
 
     pub fn cursor_position(&self, x: usize, y: usize) {
@@ -55,4 +148,11 @@ impl Terminal {
     pub fn show_cursor(&self) {
         print!("{}", termion::cursor::Show);
     }
-}
\ No newline at end of file
+}
+
+impl Drop for Terminal {
+    fn drop(&mut self) {
+        print!("{}", FOCUS_REPORTING_OFF);
+        let _ = self.flush();
+    }
+}
@@ -0,0 +1,504 @@
+use std::collections::HashMap;
+use std::{env, fs};
+
+use crate::editor::config::{self, Value};
+
+pub static RESET: &str = "\x1B[0m";
+
+// Parts of the screen a theme can style
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Role {
+    Text,
+    StatusBar,
+    Sidebar,
+    Scrollbar,
+    ScrollbarThumb,
+    LineNumber,
+    Selection,
+    MatchingBracket,
+    Mark,
+    SecondaryCursor,
+    Keyword,
+    String,
+    Comment,
+    Number,
+    Type,
+    Function,
+    Punctuation,
+}
+
+const ROLES: &[(&str, Role)] = &[
+    ("text", Role::Text),
+    ("status_bar", Role::StatusBar),
+    ("sidebar", Role::Sidebar),
+    ("scrollbar", Role::Scrollbar),
+    ("scrollbar_thumb", Role::ScrollbarThumb),
+    ("line_number", Role::LineNumber),
+    ("selection", Role::Selection),
+    ("matching_bracket", Role::MatchingBracket),
+    ("mark", Role::Mark),
+    ("secondary_cursor", Role::SecondaryCursor),
+    ("keyword", Role::Keyword),
+    ("string", Role::String),
+    ("comment", Role::Comment),
+    ("number", Role::Number),
+    ("type", Role::Type),
+    ("function", Role::Function),
+    ("punctuation", Role::Punctuation),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColorSupport {
+    Basic,
+    Indexed,
+    TrueColor,
+}
+
+impl ColorSupport {
+    // Guesses what the terminal can display from $COLORTERM and $TERM.
+    pub fn detect() -> ColorSupport {
+        let colorterm = env::var("COLORTERM").unwrap_or_default();
+        if colorterm == "truecolor" || colorterm == "24bit" {
+            return ColorSupport::TrueColor;
+        }
+        let term = env::var("TERM").unwrap_or_default();
+        if term.contains("truecolor") || term.contains("direct") {
+            ColorSupport::TrueColor
+        } else if term.contains("256") {
+            ColorSupport::Indexed
+        } else {
+            ColorSupport::Basic
+        }
+    }
+
+    // "auto", "16", "256" or "truecolor"
+    pub fn from_name(name: &str) -> Option<ColorSupport> {
+        match name {
+            "auto" => Some(ColorSupport::detect()),
+            "16" => Some(ColorSupport::Basic),
+            "256" => Some(ColorSupport::Indexed),
+            "truecolor" | "24bit" => Some(ColorSupport::TrueColor),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Color {
+    // One of the 16 standard terminal colors
+    Basic(u8),
+    Indexed(u8),
+    Rgb(u8, u8, u8),
+}
+
+const BASIC_NAMES: &[&str] = &["black", "red", "green", "yellow", "blue", "magenta", "cyan", "white"];
+
+// xterm's default values for the 16 standard colors
+const BASIC_RGB: [(u8, u8, u8); 16] = [
+    (0, 0, 0), (205, 0, 0), (0, 205, 0), (205, 205, 0),
+    (0, 0, 238), (205, 0, 205), (0, 205, 205), (229, 229, 229),
+    (127, 127, 127), (255, 0, 0), (0, 255, 0), (255, 255, 0),
+    (92, 92, 255), (255, 0, 255), (0, 255, 255), (255, 255, 255),
+];
+
+const CUBE_LEVELS: [u8; 6] = [0, 95, 135, 175, 215, 255];
+
+impl Color {
+    // "#rrggbb", a palette index, or a name like "red" / "bright-blue"
+    pub fn parse(value: &Value) -> Result<Color, String> {
+        match value {
+            Value::Int(i) if (0..=255).contains(i) => Ok(Color::Indexed(*i as u8)),
+            Value::Str(s) => {
+                if let Some(hex) = s.strip_prefix('#') {
+                    let channel = |i: usize| hex.get(i..i + 2).and_then(|c| u8::from_str_radix(c, 16).ok());
+                    return match (hex.len(), channel(0), channel(2), channel(4)) {
+                        (6, Some(r), Some(g), Some(b)) => Ok(Color::Rgb(r, g, b)),
+                        _ => Err(format!("invalid color `{}`", s)),
+                    };
+                }
+                let (bright, name) = match s.strip_prefix("bright-") {
+                    Some(name) => (8, name),
+                    None => (0, s.as_str()),
+                };
+                match BASIC_NAMES.iter().position(|n| *n == name) {
+                    Some(idx) => Ok(Color::Basic(idx as u8 + bright)),
+                    None => Err(format!("invalid color `{}`", s)),
+                }
+            },
+            other => Err(format!("invalid color {}", other)),
+        }
+    }
+
+    fn rgb(&self) -> (u8, u8, u8) {
+        match *self {
+            Color::Basic(idx) => BASIC_RGB[idx as usize % 16],
+            Color::Indexed(idx) if idx < 16 => BASIC_RGB[idx as usize],
+            Color::Indexed(idx) if idx < 232 => {
+                let idx = idx - 16;
+                let level = |n: u8| CUBE_LEVELS[n as usize];
+                (level(idx / 36), level(idx / 6 % 6), level(idx % 6))
+            },
+            Color::Indexed(idx) => {
+                let gray = 8 + (idx - 232) * 10;
+                (gray, gray, gray)
+            },
+            Color::Rgb(r, g, b) => (r, g, b),
+        }
+    }
+
+    // Converts the color to the closest one the terminal can show.
+    pub fn downsample(&self, support: ColorSupport) -> Color {
+        match (support, *self) {
+            (ColorSupport::TrueColor, color) => color,
+            (_, Color::Basic(idx)) => Color::Basic(idx),
+            (ColorSupport::Indexed, Color::Indexed(idx)) => Color::Indexed(idx),
+            (ColorSupport::Indexed, Color::Rgb(r, g, b)) => Color::Indexed(nearest_indexed(r, g, b)),
+            (ColorSupport::Basic, color) => {
+                let (r, g, b) = color.rgb();
+                Color::Basic(nearest(BASIC_RGB.iter().copied().enumerate(), r, g, b) as u8)
+            },
+        }
+    }
+
+    pub fn escape(&self, background: bool) -> String {
+        match *self {
+            Color::Basic(idx) => {
+                let base = if background { 40 } else { 30 };
+                let base = if idx >= 8 { base + 60 } else { base };
+                format!("\x1B[{}m", base + idx as u32 % 8)
+            },
+            Color::Indexed(idx) => format!("\x1B[{};5;{}m", if background { 48 } else { 38 }, idx),
+            Color::Rgb(r, g, b) => format!("\x1B[{};2;{};{};{}m", if background { 48 } else { 38 }, r, g, b),
+        }
+    }
+}
+
+fn distance(a: (u8, u8, u8), b: (u8, u8, u8)) -> u32 {
+    let d = |x: u8, y: u8| (x as i32 - y as i32).pow(2) as u32;
+    d(a.0, b.0) + d(a.1, b.1) + d(a.2, b.2)
+}
+
+fn nearest(palette: impl Iterator<Item = (usize, (u8, u8, u8))>, r: u8, g: u8, b: u8) -> usize {
+    palette.min_by_key(|(_, rgb)| distance(*rgb, (r, g, b))).map(|(idx, _)| idx).unwrap_or(0)
+}
+
+fn nearest_indexed(r: u8, g: u8, b: u8) -> u8 {
+    // Only the color cube and gray ramp, the first 16 entries vary between terminals
+    let candidates = (16..=255u8).map(|idx| (idx as usize, Color::Indexed(idx).rgb()));
+    nearest(candidates, r, g, b) as u8
+}
+
+// A color with optional hand-picked replacements for less capable terminals
+#[derive(Clone, Copy, Default)]
+struct ColorSpec {
+    color: Option<Color>,
+    indexed: Option<Color>,
+    basic: Option<Color>,
+}
+
+impl ColorSpec {
+    fn resolve(&self, support: ColorSupport) -> Option<Color> {
+        let preferred = match support {
+            ColorSupport::TrueColor => self.color,
+            ColorSupport::Indexed => self.indexed.or(self.color),
+            ColorSupport::Basic => self.basic.or(self.indexed).or(self.color),
+        };
+        preferred.map(|color| color.downsample(support))
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Style {
+    fg: ColorSpec,
+    bg: ColorSpec,
+    bold: bool,
+    italic: bool,
+    underline: bool,
+}
+
+impl Style {
+    fn escape(&self, support: ColorSupport) -> String {
+        let mut escape = String::new();
+        if let Some(fg) = self.fg.resolve(support) {
+            escape.push_str(&fg.escape(false));
+        }
+        if let Some(bg) = self.bg.resolve(support) {
+            escape.push_str(&bg.escape(true));
+        }
+        if self.bold {
+            escape.push_str("\x1B[1m");
+        }
+        if self.italic {
+            escape.push_str("\x1B[3m");
+        }
+        if self.underline {
+            escape.push_str("\x1B[4m");
+        }
+        escape
+    }
+
+    fn set(&mut self, key: &str, value: &Value) -> Result<(), String> {
+        let flag = |value: &Value| match value {
+            Value::Bool(b) => Ok(*b),
+            other => Err(format!("{} must be true or false, found {}", key, other)),
+        };
+        match key {
+            "fg" => self.fg.color = Some(Color::parse(value)?),
+            "fg256" => self.fg.indexed = Some(Color::parse(value)?),
+            "fg16" => self.fg.basic = Some(Color::parse(value)?),
+            "bg" => self.bg.color = Some(Color::parse(value)?),
+            "bg256" => self.bg.indexed = Some(Color::parse(value)?),
+            "bg16" => self.bg.basic = Some(Color::parse(value)?),
+            "bold" => self.bold = flag(value)?,
+            "italic" => self.italic = flag(value)?,
+            "underline" => self.underline = flag(value)?,
+            _ => return Err(format!("unknown style attribute `{}`", key)),
+        }
+        Ok(())
+    }
+}
+
+// Theme files have one section per role, e.g.
+//
+//   base = "dark"
+//
+//   [status_bar]
+//   bg = "#3a3a3a"
+//   bg256 = 237
+//   bg16 = "black"
+//   bold = true
+static DARK: &str = r##"
+[status_bar]
+bg = "#3a3a3a"
+bg256 = 237
+bg16 = "black"
+
+[sidebar]
+bg = "#1c1c1c"
+bg256 = 234
+bg16 = "black"
+
+[scrollbar_thumb]
+fg = "#8a8a8a"
+fg16 = "white"
+
+[line_number]
+fg = "#6c6c6c"
+fg16 = "bright-black"
+
+[mark]
+fg = "#56b6c2"
+fg16 = "cyan"
+bold = true
+
+[secondary_cursor]
+fg = "#1c1c1c"
+bg = "#abb2bf"
+bg256 = 249
+fg16 = "black"
+bg16 = "white"
+
+[selection]
+bg = "#44475a"
+bg256 = 239
+bg16 = "blue"
+
+[matching_bracket]
+bg = "#5c6370"
+bg256 = 241
+bg16 = "bright-black"
+bold = true
+
+[keyword]
+fg = "#c678dd"
+fg16 = "magenta"
+bold = true
+
+[string]
+fg = "#98c379"
+fg16 = "green"
+
+[comment]
+fg = "#7f848e"
+fg16 = "bright-black"
+italic = true
+
+[number]
+fg = "#d19a66"
+fg16 = "yellow"
+
+[type]
+fg = "#e5c07b"
+fg16 = "bright-yellow"
+
+[function]
+fg = "#61afef"
+fg16 = "blue"
+"##;
+
+static LIGHT: &str = r##"
+[text]
+fg = "#383a42"
+bg = "#fafafa"
+fg256 = 237
+bg256 = 231
+fg16 = "black"
+bg16 = "bright-white"
+
+[status_bar]
+fg = "#262626"
+bg = "#d0d0d0"
+fg256 = 235
+bg256 = 252
+fg16 = "black"
+bg16 = "white"
+
+[sidebar]
+fg = "#262626"
+bg = "#eeeeee"
+fg256 = 235
+bg256 = 255
+fg16 = "black"
+bg16 = "bright-white"
+
+[scrollbar_thumb]
+fg = "#9e9e9e"
+fg16 = "bright-black"
+
+[line_number]
+fg = "#9d9d9f"
+fg16 = "bright-black"
+
+[mark]
+fg = "#0184bc"
+fg16 = "blue"
+bold = true
+
+[secondary_cursor]
+fg = "#fafafa"
+bg = "#383a42"
+bg256 = 237
+fg16 = "bright-white"
+bg16 = "black"
+
+[selection]
+bg = "#bfceff"
+bg256 = 153
+bg16 = "cyan"
+
+[matching_bracket]
+bg = "#d0d0d0"
+bg256 = 252
+bg16 = "white"
+bold = true
+
+[keyword]
+fg = "#a626a4"
+fg16 = "magenta"
+bold = true
+
+[string]
+fg = "#50a14f"
+fg16 = "green"
+
+[comment]
+fg = "#a0a1a7"
+fg16 = "bright-black"
+italic = true
+
+[number]
+fg = "#986801"
+fg16 = "yellow"
+
+[type]
+fg = "#c18401"
+fg16 = "yellow"
+
+[function]
+fg = "#4078f2"
+fg16 = "blue"
+"##;
+
+const BUILTIN: &[(&str, &str)] = &[("dark", DARK), ("light", LIGHT)];
+
+pub struct Theme {
+    pub support: ColorSupport,
+    styles: HashMap<Role, Style>,
+    // Cached escape sequences for `support`
+    escapes: HashMap<Role, String>,
+}
+
+impl Theme {
+    // Loads a built-in theme or ~/.config/deft-txt/themes/<name>.toml.
+    pub fn load(name: &str, support: ColorSupport) -> Result<Theme, String> {
+        let mut theme = Theme {
+            support,
+            styles: HashMap::new(),
+            escapes: HashMap::new(),
+        };
+        theme.apply(name, 0)?;
+        theme.set_support(support);
+        Ok(theme)
+    }
+
+    pub fn exists(name: &str) -> bool {
+        BUILTIN.iter().any(|(n, _)| *n == name) || theme_path(name).is_some_and(|path| path.is_file())
+    }
+
+    fn apply(&mut self, name: &str, depth: usize) -> Result<(), String> {
+        if depth > 8 {
+            return Err(format!("theme `{}` inherits from itself", name));
+        }
+        let file = match BUILTIN.iter().find(|(n, _)| *n == name) {
+            Some((n, text)) => config::ConfigFile::parse(n, text),
+            None => {
+                let path = theme_path(name).ok_or_else(|| format!("unknown theme `{}`", name))?;
+                let text = fs::read_to_string(&path).map_err(|_| format!("unknown theme `{}`", name))?;
+                config::ConfigFile::parse(&path.to_string_lossy(), &text)
+            },
+        };
+        if let Some(err) = file.errors.first() {
+            return Err(err.clone());
+        }
+
+        for entry in file.section("") {
+            match (entry.key.as_str(), &entry.value) {
+                ("base", Value::Str(base)) => self.apply(base, depth + 1)?,
+                _ => return Err(format!("{}: unknown setting `{}`", file.location(entry.line), entry.key)),
+            }
+        }
+        for entry in file.entries.iter().filter(|entry| !entry.section.is_empty()) {
+            let role = ROLES.iter().find(|(n, _)| *n == entry.section).map(|(_, role)| *role);
+            let role = role.ok_or_else(|| format!("{}: unknown role `{}`", file.location(entry.line), entry.section))?;
+            self.styles.entry(role).or_default().set(&entry.key, &entry.value)
+                .map_err(|msg| format!("{}: {}", file.location(entry.line), msg))?;
+        }
+        Ok(())
+    }
+
+    pub fn set_support(&mut self, support: ColorSupport) {
+        self.support = support;
+        self.escapes = self.styles.iter().map(|(role, style)| (*role, style.escape(support))).collect();
+    }
+
+    // The escape sequence that switches to a role's style. Roles without a style
+    // fall back to the text style.
+    pub fn style(&self, role: Role) -> &str {
+        self.escapes.get(&role)
+            .or_else(|| self.escapes.get(&Role::Text))
+            .map(|escape| escape.as_str())
+            .unwrap_or("")
+    }
+
+    // Resets attributes and switches to `role`, for use between differently styled spans.
+    pub fn switch(&self, role: Role) -> String {
+        format!("{}{}{}", RESET, self.style(Role::Text), self.style(role))
+    }
+}
+
+fn theme_path(name: &str) -> Option<std::path::PathBuf> {
+    if name.contains('/') {
+        return None;
+    }
+    Some(config::config_dir()?.join("themes").join(format!("{}.toml", name)))
+}
@@ -1,6 +1,8 @@
 use std::{fmt, cmp};
 use std::ops::Range;
 
+use unicode_width::UnicodeWidthChar;
+
 pub struct GapBuffer {
     buffer: Vec<u8>,
     gap_start: usize,
@@ -9,10 +11,8 @@ pub struct GapBuffer {
 
 impl GapBuffer {
     pub fn new(capacity: usize) -> Self {
-        let mut buffer = Vec::with_capacity(capacity);
-        buffer.resize(capacity, 0);
         GapBuffer {
-            buffer,
+            buffer: vec![0; capacity],
             gap_start: 0,
             gap_end: capacity,
         }
@@ -101,31 +101,26 @@ impl GapBuffer {
     }
 
     pub fn remove(&mut self, range: Range<usize>) {
-        // Calculate indices without holding references
-        let pre_len = range.start;
-        let post_start = range.end;
-        let post_len = self.buffer.len() - post_start;
-    
-        // Create new buffer by directly slicing into the old buffer
-        let mut new_buffer = Vec::with_capacity(pre_len + post_len);
-        new_buffer.extend_from_slice(&self.buffer[0..pre_len]);
-        new_buffer.extend_from_slice(&self.buffer[post_start..]);
-    
-        // Replace the old buffer (no outstanding references exist here)
-        self.buffer = new_buffer;
-    
-        // Update gap positions
-        self.gap_start = pre_len;
-        self.gap_end = self.buffer.capacity() - post_len;
+        let end = cmp::min(range.end, self.buffer_length());
+        let start = cmp::min(range.start, end);
+
+        // Park the gap at the start of the range, then widen it over the removed bytes
+        self.move_gap(start);
+        self.gap_end += end - start;
     }
 
-    pub fn remove_char(&mut self, offset: usize) {
-        self.remove(offset..offset + 1);
+    // The byte at `offset`, which need not be part of a UTF-8 character
+    pub fn byte(&self, offset: usize) -> Option<u8> {
+        if offset < self.gap_start {
+            Some(self.buffer[offset])
+        } else {
+            self.buffer.get(offset + self.gap_length()).copied()
+        }
     }
 
-    pub fn replace_char(&mut self, offset: usize, c: char) {
-        self.remove_char(offset);
-        self.insert_char(offset, c);
+    // All the bytes, whatever they are
+    pub fn bytes(&self) -> Vec<u8> {
+        [&self.buffer[0..self.gap_start], &self.buffer[self.gap_end..]].concat()
     }
 
     pub fn from_str(s: &str) -> Self {
@@ -138,11 +133,72 @@ impl GapBuffer {
         self.to_string().chars().count()
     }
 
-    pub fn render(&self, start: usize, end: usize) -> String {
-        let combined = [&self.buffer[0..self.gap_start], &self.buffer[self.gap_end..]].concat();
-        let end = cmp::min(end, combined.len());
-        let start = cmp::min(start, end);
-        String::from_utf8_lossy(&combined[start..end]).to_string()
+    // Byte offset of character `col`, or the buffer length past the end.
+    pub fn byte_offset(&self, col: usize) -> usize {
+        let content = self.to_string();
+        content.char_indices().nth(col).map(|(idx, _)| idx).unwrap_or(content.len())
+    }
+
+    // Removes the characters in start..end.
+    pub fn remove_chars(&mut self, range: Range<usize>) {
+        let start = self.byte_offset(range.start);
+        let end = self.byte_offset(range.end);
+        self.remove(start..end);
+    }
+
+    // Renders the screen columns start..start + width of the row, expanding tabs.
+    pub fn render_cols(&self, start: usize, width: usize, tab_width: usize) -> String {
+        let end = start + width;
+        let mut rendered = String::new();
+        let mut col = 0;
+        for c in self.to_string().chars() {
+            let c_width = char_width(c, col, tab_width);
+            let next = col + c_width;
+            if next > end {
+                break;
+            }
+            if next > start {
+                if c == '\t' || col < start {
+                    // Tabs, and characters cut off by the left edge, become spaces
+                    rendered.extend(std::iter::repeat_n(' ', next - col.max(start)));
+                } else {
+                    rendered.push(c);
+                }
+            }
+            col = next;
+        }
+        rendered
+    }
+
+    // Character index at screen column `col`, or the end of the row if it is shorter.
+    pub fn col_at_visual(&self, col: usize, tab_width: usize) -> usize {
+        let mut visual = 0;
+        for (idx, c) in self.to_string().chars().enumerate() {
+            visual += char_width(c, visual, tab_width);
+            if visual > col {
+                return idx;
+            }
+        }
+        self.str_len()
+    }
+
+    // Number of screen columns taken by the characters in start..end.
+    pub fn visual_width(&self, start: usize, end: usize, tab_width: usize) -> usize {
+        self.to_string()
+            .chars()
+            .skip(start)
+            .take(end.saturating_sub(start))
+            .fold(0, |col, c| col + char_width(c, col, tab_width))
+    }
+}
+
+// Screen columns taken by `c` at screen column `col`. East Asian wide characters take two.
+pub fn char_width(c: char, col: usize, tab_width: usize) -> usize {
+    if c == '\t' {
+        tab_width - col % tab_width
+    } else {
+        // Control characters are shown as they are, one column each
+        c.width().unwrap_or(1)
     }
 }
 
@@ -153,4 +209,51 @@ impl fmt::Display for GapBuffer {
     }
 }
 
-const CHUNK_SIZE: usize = 64;
\ No newline at end of file
+const CHUNK_SIZE: usize = 64;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // "abcXdef" with the gap parked right after the X
+    fn gapped() -> GapBuffer {
+        let mut buffer = GapBuffer::from_str("abcdef");
+        buffer.insert(3, b"X");
+        buffer
+    }
+
+    #[test]
+    fn removes_before_the_gap() {
+        let mut buffer = gapped();
+        buffer.remove(0..2);
+        assert_eq!(buffer.to_string(), "cXdef");
+        assert_eq!(buffer.buffer_length(), 5);
+    }
+
+    #[test]
+    fn removes_across_the_gap() {
+        let mut buffer = gapped();
+        buffer.remove(2..6);
+        assert_eq!(buffer.to_string(), "abf");
+        assert_eq!(buffer.buffer_length(), 3);
+    }
+
+    #[test]
+    fn removes_after_the_gap() {
+        let mut buffer = gapped();
+        buffer.remove(5..7);
+        assert_eq!(buffer.to_string(), "abcXd");
+        buffer.remove(4..99);
+        assert_eq!(buffer.to_string(), "abcX");
+    }
+
+    #[test]
+    fn inserts_after_a_removal() {
+        let mut buffer = gapped();
+        buffer.remove(1..5);
+        buffer.insert(1, b"12");
+        buffer.insert_char(4, '\u{e9}');
+        assert_eq!(buffer.to_string(), "a12e\u{e9}f");
+        buffer.remove(0..buffer.buffer_length());
+        assert_eq!(buffer.to_string(), "");
+    }
+}
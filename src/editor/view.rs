@@ -0,0 +1,331 @@
+use crate::editor::block;
+use crate::editor::document::Document;
+use crate::editor::wrap::{self, WrapOptions};
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Cursor {
+    pub row: usize,
+    // Character index within the row
+    pub col: usize,
+    // Screen column to return to when moving vertically through shorter lines.
+    // Cleared by anything that moves the cursor horizontally.
+    pub goal: Option<usize>,
+}
+
+pub struct ScrollPolicy {
+    // Rows kept visible above and below the cursor
+    pub scrolloff: usize,
+    // Keep the cursor on the middle row wherever possible
+    pub center: bool,
+    // Scroll horizontally for the cursor line alone, so a new line starts unscrolled
+    pub line_only: bool,
+}
+
+// A cursor and scroll position onto a document
+#[derive(Default)]
+pub struct View {
+    pub cursor: Cursor,
+    // First document row on screen
+    pub top: usize,
+    // With soft wrap, the first segment of that row on screen
+    pub top_sub: usize,
+    // First screen column of the text on screen
+    pub left: usize,
+    // Row that `left` was last scrolled for
+    pub left_row: usize,
+    pub jumps: JumpList,
+    // Cursors besides `cursor`, which edits and short motions also apply to
+    pub extra_cursors: Vec<Cursor>,
+    // Row and screen column where a block selection was started
+    pub block_anchor: Option<(usize, usize)>,
+}
+
+impl View {
+    // Places the cursor at a document position, clamped to the document.
+    pub fn set_cursor(&mut self, document: &Document, row: usize, col: usize) {
+        let row = row.min(document.rows().saturating_sub(1));
+        let len = document.row(row).map(|r| r.str_len()).unwrap_or(0);
+        self.cursor = Cursor {
+            row,
+            col: col.min(len),
+            goal: None,
+        };
+    }
+
+    // Moves the cursor `delta` rows, keeping it as close to the goal column as each line allows.
+    pub fn move_vertical(&mut self, document: &Document, delta: isize, tab_width: usize) {
+        let last = document.rows().saturating_sub(1);
+        let goal = self.cursor.goal.unwrap_or_else(|| self.cursor_column(document, tab_width));
+        let row = self.cursor.row.saturating_add_signed(delta).min(last);
+        let col = document.row(row).map(|r| r.col_at_visual(goal, tab_width)).unwrap_or(0);
+        self.cursor = Cursor {
+            row,
+            col,
+            goal: Some(goal),
+        };
+    }
+
+    // The block selection between its anchor and the cursor. The cursor's goal column
+    // counts, so a block can be dragged down past short lines.
+    pub fn block(&self, document: &Document, tab_width: usize) -> Option<block::Rect> {
+        let anchor = self.block_anchor?;
+        let x = self.cursor.goal.unwrap_or_else(|| self.cursor_column(document, tab_width));
+        Some(block::Rect::between(anchor, (self.cursor.row, x)))
+    }
+
+    // Screen column of the cursor within its line, before horizontal scrolling
+    pub fn cursor_column(&self, document: &Document, tab_width: usize) -> usize {
+        document.row(self.cursor.row)
+            .map(|r| r.visual_width(0, self.cursor.col, tab_width))
+            .unwrap_or(0)
+    }
+
+    // Scrolls just enough to show the cursor with the policy's margins.
+    pub fn scroll_to_cursor(&mut self, document: &Document, height: usize, width: usize, tab_width: usize, policy: &ScrollPolicy) {
+        let row = self.cursor.row;
+        self.top_sub = 0;
+        if policy.center {
+            self.top = row.saturating_sub(height / 2);
+        } else {
+            let margin = policy.scrolloff.min(height.saturating_sub(1) / 2);
+            if row < self.top + margin {
+                self.top = row.saturating_sub(margin);
+            }
+            if row + margin >= self.top + height {
+                self.top = row + margin + 1 - height;
+            }
+        }
+        // Never leave empty screen below the end of the document because of the margin or centering
+        self.top = self.top.min(document.rows().saturating_sub(height)).min(row);
+
+        if policy.line_only && self.left_row != row {
+            self.left = 0;
+        }
+        self.left_row = row;
+        let column = self.cursor_column(document, tab_width);
+        if column < self.left {
+            self.left = column;
+        }
+        if column >= self.left + width {
+            self.left = column + 1 - width;
+        }
+    }
+
+    // Screen row position and column of the cursor with soft wrap
+    pub fn cursor_visual(&self, document: &Document, options: &WrapOptions) -> (wrap::VisualPos, usize) {
+        let line = document.row(self.cursor.row).map(|r| r.to_string()).unwrap_or_default();
+        let segments = wrap::segments(&line, options);
+        let sub = wrap::segment_of(&segments, self.cursor.col);
+        let x = wrap::column_in_segment(&line, &segments[sub], self.cursor.col, options.tab_width);
+        ((self.cursor.row, sub), x)
+    }
+
+    // Moves the cursor `delta` screen rows with soft wrap, keeping the goal column.
+    pub fn move_visual(&mut self, document: &Document, delta: isize, options: &WrapOptions) {
+        let (pos, x) = self.cursor_visual(document, options);
+        let goal = self.cursor.goal.unwrap_or(x);
+        let (row, sub) = wrap::step(document, pos, delta, options);
+
+        let line = document.row(row).map(|r| r.to_string()).unwrap_or_default();
+        let segments = wrap::segments(&line, options);
+        let last = sub + 1 == segments.len();
+        let col = wrap::col_at_column(&line, &segments[sub], goal, last, options.tab_width);
+        self.cursor = Cursor {
+            row,
+            col,
+            goal: Some(goal),
+        };
+    }
+
+    // scroll_to_cursor for soft wrap, where scrolling is by screen rows rather than lines.
+    pub fn scroll_wrapped(&mut self, document: &Document, height: usize, options: &WrapOptions, policy: &ScrollPolicy) {
+        self.left = 0;
+        let (cursor, _) = self.cursor_visual(document, options);
+        let mut top = (self.top, self.top_sub);
+        // Editing may have removed segments from under the old position
+        top.1 = top.1.min(wrap::line_segments(document, top.0, options).len() - 1);
+
+        if policy.center {
+            top = wrap::step(document, cursor, -((height / 2) as isize), options);
+        } else {
+            let margin = policy.scrolloff.min(height.saturating_sub(1) / 2);
+            let upper = wrap::step(document, cursor, -(margin as isize), options);
+            if upper < top {
+                top = upper;
+            } else if wrap::distance(document, top, cursor, height, options) + margin >= height {
+                top = wrap::step(document, cursor, -((height - 1 - margin) as isize), options);
+            }
+        }
+        let last_row = document.rows().saturating_sub(1);
+        let end = (last_row, wrap::line_segments(document, last_row, options).len() - 1);
+        top = top.min(wrap::step(document, end, -(height.saturating_sub(1) as isize), options));
+        self.top = top.0;
+        self.top_sub = top.1;
+    }
+}
+
+const JUMP_LIST_SIZE: usize = 100;
+
+// Cursor positions from before large moves, to go back and forth between
+#[derive(Default)]
+pub struct JumpList {
+    positions: Vec<(usize, usize)>,
+    // Entry the next back() returns to, plus one. Equal to the length when not going through the list.
+    index: usize,
+}
+
+impl JumpList {
+    // Remembers a position being jumped away from, forgetting anything that was gone back over.
+    pub fn record(&mut self, position: (usize, usize)) {
+        self.positions.truncate(self.index);
+        // One entry per line is enough
+        self.positions.retain(|(row, _)| *row != position.0);
+        self.positions.push(position);
+        if self.positions.len() > JUMP_LIST_SIZE {
+            self.positions.remove(0);
+        }
+        self.index = self.positions.len();
+    }
+
+    pub fn back(&mut self, current: (usize, usize)) -> Option<(usize, usize)> {
+        if self.index == 0 {
+            return None;
+        }
+        // Keep where we were so forward() can return to it
+        if self.index == self.positions.len() {
+            self.positions.push(current);
+        }
+        self.index -= 1;
+        Some(self.positions[self.index])
+    }
+
+    pub fn forward(&mut self) -> Option<(usize, usize)> {
+        if self.index + 1 >= self.positions.len() {
+            return None;
+        }
+        self.index += 1;
+        Some(self.positions[self.index])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn policy(scrolloff: usize, center: bool) -> ScrollPolicy {
+        ScrollPolicy { scrolloff, center, line_only: false }
+    }
+
+    fn numbered(count: usize) -> Document {
+        let text: Vec<String> = (0..count).map(|n| n.to_string()).collect();
+        Document::from_text("test.txt", &text.join("\n"))
+    }
+
+    fn scrolled(document: &Document, row: usize, policy: &ScrollPolicy) -> usize {
+        let mut view = View::default();
+        view.set_cursor(document, row, 0);
+        view.scroll_to_cursor(document, 10, 80, 4, policy);
+        view.top
+    }
+
+    #[test]
+    fn keeps_the_margin_around_the_cursor() {
+        let document = numbered(100);
+        assert_eq!(scrolled(&document, 5, &policy(3, false)), 0);
+        assert_eq!(scrolled(&document, 50, &policy(3, false)), 44);
+        assert_eq!(scrolled(&document, 98, &policy(3, false)), 90);
+    }
+
+    #[test]
+    fn centers_without_scrolling_past_the_end() {
+        let document = numbered(100);
+        assert_eq!(scrolled(&document, 2, &policy(0, true)), 0);
+        assert_eq!(scrolled(&document, 50, &policy(0, true)), 45);
+        assert_eq!(scrolled(&document, 97, &policy(0, true)), 90);
+        assert_eq!(scrolled(&numbered(4), 3, &policy(0, true)), 0);
+    }
+
+    #[test]
+    fn centers_wrapped_rows_without_scrolling_past_the_end() {
+        let document = Document::from_text("test.txt", &["abcdefgh"; 20].join("\n"));
+        let options = WrapOptions { width: 4, tab_width: 4, words: false, indent: false, marker: String::new() };
+        let mut view = View::default();
+        view.set_cursor(&document, 19, 6);
+        view.scroll_wrapped(&document, 10, &options, &policy(0, true));
+        // The last five lines take the ten screen rows
+        assert_eq!((view.top, view.top_sub), (15, 0));
+        view.set_cursor(&document, 10, 0);
+        view.scroll_wrapped(&document, 10, &options, &policy(0, true));
+        assert_eq!((view.top, view.top_sub), (7, 1));
+    }
+
+    #[test]
+    fn moves_through_short_lines_at_the_goal_column() {
+        let document = Document::from_text("test.txt", "abcdef\nab\n\tabcd");
+        let mut view = View::default();
+        view.set_cursor(&document, 0, 5);
+        view.move_vertical(&document, 1, 4);
+        assert_eq!((view.cursor.row, view.cursor.col), (1, 2));
+        view.move_vertical(&document, 1, 4);
+        assert_eq!((view.cursor.row, view.cursor.col), (2, 2));
+        view.move_vertical(&document, -2, 4);
+        assert_eq!((view.cursor.row, view.cursor.col), (0, 5));
+    }
+
+    #[test]
+    fn jumps_back_and_forward() {
+        let mut jumps = JumpList::default();
+        assert_eq!(jumps.back((0, 0)), None);
+        jumps.record((1, 0));
+        jumps.record((5, 2));
+        jumps.record((9, 0));
+        assert_eq!(jumps.back((12, 3)), Some((9, 0)));
+        assert_eq!(jumps.back((9, 0)), Some((5, 2)));
+        assert_eq!(jumps.forward(), Some((9, 0)));
+        assert_eq!(jumps.forward(), Some((12, 3)));
+        assert_eq!(jumps.forward(), None);
+    }
+
+    #[test]
+    fn recording_forgets_what_was_gone_back_over() {
+        let mut jumps = JumpList::default();
+        jumps.record((1, 0));
+        jumps.record((5, 2));
+        jumps.record((9, 0));
+        assert_eq!(jumps.back((12, 0)), Some((9, 0)));
+        assert_eq!(jumps.back((9, 0)), Some((5, 2)));
+        // Jumping away from here drops (9, 0) and (12, 0)
+        jumps.record((5, 2));
+        assert_eq!(jumps.forward(), None);
+        assert_eq!(jumps.back((30, 0)), Some((5, 2)));
+        assert_eq!(jumps.back((5, 2)), Some((1, 0)));
+        assert_eq!(jumps.back((1, 0)), None);
+    }
+
+    #[test]
+    fn keeps_one_entry_per_line() {
+        let mut jumps = JumpList::default();
+        jumps.record((1, 0));
+        jumps.record((5, 2));
+        jumps.record((1, 4));
+        assert_eq!(jumps.back((9, 0)), Some((1, 4)));
+        assert_eq!(jumps.back((1, 4)), Some((5, 2)));
+        assert_eq!(jumps.back((5, 2)), None);
+    }
+
+    #[test]
+    fn keeps_a_bounded_list() {
+        let mut jumps = JumpList::default();
+        for row in 0..JUMP_LIST_SIZE + 10 {
+            jumps.record((row, 0));
+        }
+        let mut count = 0;
+        let mut current = (1000, 0);
+        while let Some(position) = jumps.back(current) {
+            current = position;
+            count += 1;
+        }
+        assert_eq!(count, JUMP_LIST_SIZE);
+        assert_eq!(current, (10, 0));
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::CString;
+use std::hash::{Hash, Hasher};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::Path;
+use std::time::SystemTime;
+use std::{fs, mem};
+
+// Noticing when another program changes the file being edited.
+
+// What a file held when it was last read or written
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Stamp {
+    modified: Option<SystemTime>,
+    len: u64,
+    hash: u64,
+}
+
+impl Stamp {
+    // For the file as it is now
+    pub fn of(path: &str) -> Option<Stamp> {
+        fs::read(path).ok().map(|content| Stamp::new(path, &content))
+    }
+
+    // For `content` just read from or written to `path`
+    pub fn new(path: &str, content: &[u8]) -> Stamp {
+        let meta = fs::metadata(path).ok();
+        Stamp {
+            modified: meta.as_ref().and_then(|meta| meta.modified().ok()),
+            len: content.len() as u64,
+            hash: hash(content),
+        }
+    }
+}
+
+fn hash(content: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    content.hash(&mut hasher);
+    hasher.finish()
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Disk {
+    Same,
+    Changed,
+    // The file was there and is gone
+    Missing,
+}
+
+// How the file at `path` compares with what it held when `stamp` was taken, or with
+// there being no file when there is no stamp. A file that was only touched is the same.
+pub fn compare(stamp: Option<&Stamp>, path: &str) -> Disk {
+    let Ok(meta) = fs::metadata(path) else {
+        return if stamp.is_some() { Disk::Missing } else { Disk::Same };
+    };
+    let Some(stamp) = stamp else {
+        return Disk::Changed;
+    };
+    if meta.len() == stamp.len && meta.modified().ok() == stamp.modified {
+        return Disk::Same;
+    }
+    match fs::read(path) {
+        Ok(content) if hash(&content) == stamp.hash => Disk::Same,
+        _ => Disk::Changed,
+    }
+}
+
+// Watches a file with inotify. The watch is on its directory, because programs that
+// replace a file instead of writing to it would leave a watch on the file itself
+// looking at the old one.
+pub struct Watcher {
+    fd: RawFd,
+    name: Vec<u8>,
+}
+
+impl Watcher {
+    pub fn new(path: &str) -> Option<Watcher> {
+        let path = Path::new(path);
+        let name = path.file_name()?.as_bytes().to_vec();
+        let dir = match path.parent() {
+            Some(dir) if !dir.as_os_str().is_empty() => dir,
+            _ => Path::new("."),
+        };
+        let dir = CString::new(dir.as_os_str().as_bytes()).ok()?;
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return None;
+        }
+        let watcher = Watcher { fd, name };
+        let mask = libc::IN_CLOSE_WRITE | libc::IN_CREATE | libc::IN_DELETE | libc::IN_MOVED_FROM | libc::IN_MOVED_TO;
+        if unsafe { libc::inotify_add_watch(fd, dir.as_ptr(), mask) } < 0 {
+            return None;
+        }
+        Some(watcher)
+    }
+
+    // For polling together with the terminal
+    pub fn fd(&self) -> RawFd {
+        self.fd
+    }
+
+    // Reads the events waiting and tells whether any of them were about the file.
+    pub fn changed(&mut self) -> bool {
+        let header = mem::size_of::<libc::inotify_event>();
+        let mut buf = [0u8; 4096];
+        let mut changed = false;
+        loop {
+            let count = unsafe { libc::read(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len()) };
+            if count <= 0 {
+                return changed;
+            }
+            let mut offset = 0;
+            while offset + header <= count as usize {
+                let event = unsafe { std::ptr::read_unaligned(buf.as_ptr().add(offset) as *const libc::inotify_event) };
+                let name = &buf[offset + header..offset + header + event.len as usize];
+                // The name is padded with nul bytes
+                let name = &name[..name.iter().position(|b| *b == 0).unwrap_or(name.len())];
+                changed |= name == self.name.as_slice();
+                offset += header + event.len as usize;
+            }
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        unsafe { libc::close(self.fd) };
+    }
+}
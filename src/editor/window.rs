@@ -0,0 +1,388 @@
+use crate::editor::view::View;
+
+// Windows onto documents, splitting the main area side by side or one above the other.
+// The layout is a tree with a window at each leaf. The parts of a split keep their sizes
+// in screen cells, scaled when the area changes, so they can be resized a row at a time.
+
+// Rows or columns a window keeps when another one grows
+const MIN_SIZE: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Split {
+    // One above the other, as :split makes them
+    Rows,
+    // Side by side, as :vsplit makes them
+    Columns,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    // Size in the direction a split divides
+    pub fn length(&self, split: Split) -> usize {
+        match split {
+            Split::Rows => self.height,
+            Split::Columns => self.width,
+        }
+    }
+
+    // `length` cells of this one, `offset` cells from its top or left
+    pub fn part(&self, split: Split, offset: usize, length: usize) -> Rect {
+        match split {
+            Split::Rows => Rect { y: self.y + offset, height: length, ..*self },
+            Split::Columns => Rect { x: self.x + offset, width: length, ..*self },
+        }
+    }
+}
+
+// A window without focus, and the buffer it shows
+pub struct Window {
+    pub id: usize,
+    pub buffer: usize,
+    pub view: View,
+}
+
+enum Node {
+    Window(usize),
+    // The parts of a split from the top or left, with their sizes
+    Split(Split, Vec<(Node, usize)>),
+}
+
+// A line drawn between the parts of a split
+pub struct Separator {
+    pub split: Split,
+    pub rect: Rect,
+}
+
+pub struct Tree {
+    root: Node,
+    next_id: usize,
+    // Where each window and separator went when the area was last divided
+    placed: Vec<(usize, Rect)>,
+    separators: Vec<Separator>,
+}
+
+impl Tree {
+    // One window, numbered 0
+    pub fn new() -> Tree {
+        Tree {
+            root: Node::Window(0),
+            next_id: 1,
+            placed: Vec::new(),
+            separators: Vec::new(),
+        }
+    }
+
+    // Divides `area` among the windows, keeping their sizes in proportion.
+    pub fn place(&mut self, area: Rect) {
+        self.placed.clear();
+        self.separators.clear();
+        place(&mut self.root, area, &mut self.placed, &mut self.separators);
+    }
+
+    // Every window and where it is, from the top left
+    pub fn windows(&self) -> &[(usize, Rect)] {
+        &self.placed
+    }
+
+    pub fn separators(&self) -> &[Separator] {
+        &self.separators
+    }
+
+    pub fn len(&self) -> usize {
+        self.placed.len()
+    }
+
+    pub fn rect(&self, id: usize) -> Rect {
+        self.placed.iter().find(|(window, _)| *window == id).map(|(_, rect)| *rect).unwrap_or_default()
+    }
+
+    // Splits window `id` in two and returns the new window, which takes the top or left
+    // half. None when the window is too small to split.
+    pub fn split(&mut self, id: usize, split: Split) -> Option<usize> {
+        let length = self.rect(id).length(split);
+        if length < 2 * MIN_SIZE + 1 {
+            return None;
+        }
+        let new = self.next_id;
+        self.next_id += 1;
+        split_node(&mut self.root, id, split, new, length);
+        Some(new)
+    }
+
+    // Takes window `id` out, giving its space to the part of the split next to it. Returns
+    // the window nearest to where it was, or None for the last window.
+    pub fn close(&mut self, id: usize) -> Option<usize> {
+        close_node(&mut self.root, id)
+    }
+
+    // Makes window `id` `delta` rows or columns larger, or smaller when it is negative, at
+    // the expense of the part of the nearest split of that kind next to it. Returns false
+    // when there is no such split.
+    pub fn resize(&mut self, id: usize, split: Split, delta: isize) -> bool {
+        resize_node(&mut self.root, id, split, delta) == Some(true)
+    }
+
+    // The window next to `id` in `direction`. Of several, the one nearest to the screen
+    // position `at`, which is the cursor's.
+    pub fn neighbour(&self, id: usize, direction: Direction, at: (usize, usize)) -> Option<usize> {
+        let from = self.rect(id);
+        // Windows are one cell apart, for the separator
+        let beside = |rect: &Rect| match direction {
+            Direction::Left => rect.x + rect.width + 1 == from.x,
+            Direction::Right => from.x + from.width + 1 == rect.x,
+            Direction::Up => rect.y + rect.height + 1 == from.y,
+            Direction::Down => from.y + from.height + 1 == rect.y,
+        };
+        self.placed.iter()
+            .filter(|(_, rect)| beside(rect))
+            .min_by_key(|(_, rect)| match direction {
+                Direction::Left | Direction::Right => gap(rect.y, rect.height, at.1),
+                Direction::Up | Direction::Down => gap(rect.x, rect.width, at.0),
+            })
+            .map(|(window, _)| *window)
+    }
+
+    // The window after `id`, going round from the last one to the first
+    pub fn next(&self, id: usize) -> usize {
+        let idx = self.placed.iter().position(|(window, _)| *window == id).unwrap_or(0);
+        self.placed.get((idx + 1) % self.placed.len().max(1)).map_or(id, |(window, _)| *window)
+    }
+}
+
+// How far `at` is from the cells `start..start + length`
+fn gap(start: usize, length: usize, at: usize) -> usize {
+    if at < start {
+        start - at
+    } else {
+        at.saturating_sub(start + length - 1)
+    }
+}
+
+fn place(node: &mut Node, area: Rect, placed: &mut Vec<(usize, Rect)>, separators: &mut Vec<Separator>) {
+    match node {
+        Node::Window(id) => placed.push((*id, area)),
+        Node::Split(split, parts) => {
+            let split = *split;
+            let count = parts.len();
+            fit(parts, area.length(split).saturating_sub(count - 1));
+            let mut offset = 0;
+            for (idx, (child, size)) in parts.iter_mut().enumerate() {
+                if idx > 0 {
+                    separators.push(Separator { split, rect: area.part(split, offset, 1) });
+                    offset += 1;
+                }
+                place(child, area.part(split, offset, *size), placed, separators);
+                offset += *size;
+            }
+        },
+    }
+}
+
+// Scales the sizes of a split's parts to add up to `total`, each keeping at least a cell.
+// What rounding leaves over goes to the largest part.
+fn fit(parts: &mut [(Node, usize)], total: usize) {
+    let sum: usize = parts.iter().map(|(_, size)| size).sum();
+    if sum == total {
+        return;
+    }
+    for (_, size) in parts.iter_mut() {
+        *size = (*size * total / sum.max(1)).max(1);
+    }
+    let sum: usize = parts.iter().map(|(_, size)| size).sum();
+    if let Some((_, largest)) = parts.iter_mut().max_by_key(|(_, size)| *size) {
+        *largest = (*largest + total).saturating_sub(sum).max(1);
+    }
+}
+
+fn contains(node: &Node, id: usize) -> bool {
+    match node {
+        Node::Window(window) => *window == id,
+        Node::Split(_, parts) => parts.iter().any(|(child, _)| contains(child, id)),
+    }
+}
+
+// The first or last window of a part of the layout
+fn edge_window(node: &Node, last: bool) -> usize {
+    match node {
+        Node::Window(window) => *window,
+        Node::Split(_, parts) => {
+            let part = if last { parts.last() } else { parts.first() };
+            part.map_or(0, |(child, _)| edge_window(child, last))
+        },
+    }
+}
+
+// Puts window `new` before window `id`, which is `length` cells long in the direction of
+// the split. A split of the same kind takes it as another part.
+fn split_node(node: &mut Node, id: usize, split: Split, new: usize, length: usize) -> bool {
+    // The separator takes a cell
+    let first = (length - 1) / 2;
+    let rest = length - 1 - first;
+    match node {
+        Node::Window(window) => {
+            if *window != id {
+                return false;
+            }
+            *node = Node::Split(split, vec![(Node::Window(new), first), (Node::Window(id), rest)]);
+            true
+        },
+        Node::Split(kind, parts) => {
+            let idx = parts.iter().position(|(child, _)| matches!(child, Node::Window(window) if *window == id));
+            match idx {
+                Some(idx) if *kind == split => {
+                    parts[idx].1 = rest;
+                    parts.insert(idx, (Node::Window(new), first));
+                    true
+                },
+                _ => parts.iter_mut().any(|(child, _)| split_node(child, id, split, new, length)),
+            }
+        },
+    }
+}
+
+fn close_node(node: &mut Node, id: usize) -> Option<usize> {
+    let Node::Split(_, parts) = node else {
+        return None;
+    };
+    let Some(idx) = parts.iter().position(|(child, _)| matches!(child, Node::Window(window) if *window == id)) else {
+        return parts.iter_mut().find_map(|(child, _)| close_node(child, id));
+    };
+    let (_, size) = parts.remove(idx);
+    // The part after it grows into its space, or the one before it if it was the last
+    let (neighbour, after) = if idx < parts.len() { (idx, true) } else { (idx - 1, false) };
+    parts[neighbour].1 += size + 1;
+    let focus = edge_window(&parts[neighbour].0, !after);
+    if parts.len() == 1 {
+        *node = parts.remove(0).0;
+    }
+    Some(focus)
+}
+
+// None when window `id` is not in this part of the layout, otherwise whether it was resized.
+// The nearest split is the deepest one, so it is tried first.
+fn resize_node(node: &mut Node, id: usize, split: Split, delta: isize) -> Option<bool> {
+    let Node::Split(kind, parts) = node else {
+        return (contains(node, id)).then_some(false);
+    };
+    let idx = parts.iter().position(|(child, _)| contains(child, id))?;
+    if resize_node(&mut parts[idx].0, id, split, delta) == Some(true) {
+        return Some(true);
+    }
+    if *kind != split {
+        return Some(false);
+    }
+    let other = if idx + 1 < parts.len() { idx + 1 } else { idx - 1 };
+    let total = parts[idx].1 + parts[other].1;
+    if total < 2 * MIN_SIZE {
+        return Some(false);
+    }
+    let size = parts[idx].1.saturating_add_signed(delta).clamp(MIN_SIZE, total - MIN_SIZE);
+    parts[idx].1 = size;
+    parts[other].1 = total - size;
+    Some(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCREEN: Rect = Rect { x: 0, y: 0, width: 80, height: 24 };
+
+    fn sizes(parts: &[(Node, usize)]) -> Vec<usize> {
+        parts.iter().map(|(_, size)| *size).collect()
+    }
+
+    #[test]
+    fn fit_keeps_proportions() {
+        let mut parts = vec![(Node::Window(0), 10), (Node::Window(1), 20)];
+        fit(&mut parts, 60);
+        assert_eq!(sizes(&parts), [20, 40]);
+        // What does not divide evenly goes to the largest part
+        let mut parts = vec![(Node::Window(0), 1), (Node::Window(1), 1), (Node::Window(2), 2)];
+        fit(&mut parts, 10);
+        assert_eq!(sizes(&parts), [2, 2, 6]);
+        // Every part keeps a cell
+        let mut parts = vec![(Node::Window(0), 1), (Node::Window(1), 99)];
+        fit(&mut parts, 10);
+        assert_eq!(sizes(&parts), [1, 9]);
+    }
+
+    #[test]
+    fn splits_and_closes_windows() {
+        let mut tree = Tree::new();
+        tree.place(SCREEN);
+        assert_eq!(tree.split(0, Split::Columns), Some(1));
+        tree.place(SCREEN);
+        assert_eq!(tree.windows(), [
+            (1, Rect { x: 0, y: 0, width: 39, height: 24 }),
+            (0, Rect { x: 40, y: 0, width: 40, height: 24 }),
+        ]);
+        assert_eq!(tree.separators()[0].rect, Rect { x: 39, y: 0, width: 1, height: 24 });
+        assert_eq!(tree.split(0, Split::Rows), Some(2));
+        tree.place(SCREEN);
+        assert_eq!(tree.rect(2), Rect { x: 40, y: 0, width: 40, height: 11 });
+        assert_eq!(tree.rect(0), Rect { x: 40, y: 12, width: 40, height: 12 });
+        // The focus goes to the window that takes the space
+        assert_eq!(tree.close(1), Some(2));
+        tree.place(SCREEN);
+        assert_eq!(tree.len(), 2);
+        assert_eq!(tree.rect(2), Rect { x: 0, y: 0, width: 80, height: 11 });
+        assert_eq!(tree.close(2), Some(0));
+        assert_eq!(tree.close(0), None);
+    }
+
+    #[test]
+    fn small_windows_are_not_split() {
+        let mut tree = Tree::new();
+        tree.place(Rect { width: 6, ..SCREEN });
+        assert_eq!(tree.split(0, Split::Columns), None);
+        assert_eq!(tree.split(0, Split::Rows), Some(1));
+    }
+
+    #[test]
+    fn resizes_against_the_nearest_split() {
+        let mut tree = Tree::new();
+        tree.place(SCREEN);
+        tree.split(0, Split::Rows);
+        tree.place(SCREEN);
+        assert!(tree.resize(1, Split::Rows, 3));
+        tree.place(SCREEN);
+        assert_eq!((tree.rect(1).height, tree.rect(0).height), (14, 9));
+        // The other window keeps a few rows
+        assert!(tree.resize(0, Split::Rows, -20));
+        tree.place(SCREEN);
+        assert_eq!((tree.rect(1).height, tree.rect(0).height), (20, 3));
+        assert!(!tree.resize(0, Split::Columns, 1));
+    }
+
+    #[test]
+    fn finds_neighbours() {
+        let mut tree = Tree::new();
+        tree.place(SCREEN);
+        tree.split(0, Split::Columns);
+        tree.place(SCREEN);
+        tree.split(0, Split::Rows);
+        tree.place(SCREEN);
+        assert_eq!(tree.neighbour(1, Direction::Right, (10, 2)), Some(2));
+        assert_eq!(tree.neighbour(1, Direction::Right, (10, 20)), Some(0));
+        assert_eq!(tree.neighbour(0, Direction::Up, (50, 20)), Some(2));
+        assert_eq!(tree.neighbour(0, Direction::Right, (50, 20)), None);
+        assert_eq!(tree.next(1), 2);
+        assert_eq!(tree.next(0), 1);
+    }
+}
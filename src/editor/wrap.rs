@@ -0,0 +1,324 @@
+use crate::editor::document::Document;
+use crate::editor::util;
+
+// Soft wrapping: lays a document line out over several screen rows.
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct WrapOptions {
+    pub width: usize,
+    pub tab_width: usize,
+    // Break after whitespace instead of at the last column that fits
+    pub words: bool,
+    // Indent continuation rows as far as the line itself is indented
+    pub indent: bool,
+    // Drawn at the start of every continuation row
+    pub marker: String,
+}
+
+// A piece of a line shown on one screen row
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Segment {
+    // Character range of the line on this row
+    pub start: usize,
+    pub end: usize,
+    // Screen columns before the text: continuation indent and marker
+    pub indent: usize,
+}
+
+// Screen width of every character, with tabs expanded from the start of the line.
+fn char_widths(line: &str, tab_width: usize) -> Vec<usize> {
+    let mut col = 0;
+    line.chars()
+        .map(|c| {
+            let width = util::char_width(c, col, tab_width);
+            col += width;
+            width
+        })
+        .collect()
+}
+
+pub fn segments(line: &str, options: &WrapOptions) -> Vec<Segment> {
+    let chars: Vec<char> = line.chars().collect();
+    let widths = char_widths(line, options.tab_width);
+    let marker = options.marker.chars().count();
+
+    let leading: usize = if options.indent {
+        chars.iter().zip(&widths).take_while(|(c, _)| c.is_whitespace()).map(|(_, w)| w).sum()
+    } else {
+        0
+    };
+    // Never let the continuation prefix take more than half the row
+    let continuation = (leading + marker).min(options.width / 2);
+
+    let mut segments = Vec::new();
+    let mut start = 0;
+    loop {
+        let indent = if segments.is_empty() { 0 } else { continuation };
+        let available = options.width.saturating_sub(indent).max(1);
+
+        let mut end = start;
+        let mut used = 0;
+        while end < chars.len() && used + widths[end] <= available {
+            used += widths[end];
+            end += 1;
+        }
+        // Always make progress, even with a character wider than the row
+        if end == start && end < chars.len() {
+            end += 1;
+        }
+        if options.words && end < chars.len() {
+            if let Some(space) = (start + 1..end).rev().find(|&idx| chars[idx - 1].is_whitespace()) {
+                end = space;
+            }
+        }
+
+        segments.push(Segment { start, end, indent });
+        if end >= chars.len() {
+            return segments;
+        }
+        start = end;
+    }
+}
+
+// The text of a segment as it appears on screen, prefix included.
+pub fn render_segment(line: &str, segment: &Segment, options: &WrapOptions) -> String {
+    let widths = char_widths(line, options.tab_width);
+    let mut rendered = String::new();
+    if segment.indent > 0 {
+        let marker: String = options.marker.chars().take(segment.indent).collect();
+        let marker_width = marker.chars().count();
+        rendered.extend(std::iter::repeat_n(' ', segment.indent - marker_width));
+        rendered.push_str(&marker);
+    }
+    for (c, width) in line.chars().zip(&widths).skip(segment.start).take(segment.end - segment.start) {
+        if c == '\t' {
+            rendered.extend(std::iter::repeat_n(' ', *width));
+        } else {
+            rendered.push(c);
+        }
+    }
+    rendered
+}
+
+// Index of the segment showing character `col`. The end of the line belongs to the last one.
+pub fn segment_of(segments: &[Segment], col: usize) -> usize {
+    segments.iter().position(|segment| col < segment.end).unwrap_or(segments.len() - 1)
+}
+
+// Screen column of character `col` on its segment's row
+pub fn column_in_segment(line: &str, segment: &Segment, col: usize, tab_width: usize) -> usize {
+    let widths = char_widths(line, tab_width);
+    segment.indent + widths[segment.start..col.min(widths.len())].iter().sum::<usize>()
+}
+
+// Character on a segment's row closest to screen column `goal`, without leaving the segment.
+pub fn col_at_column(line: &str, segment: &Segment, goal: usize, last: bool, tab_width: usize) -> usize {
+    let widths = char_widths(line, tab_width);
+    let mut x = segment.indent;
+    for (col, width) in widths.iter().enumerate().take(segment.end).skip(segment.start) {
+        x += width;
+        if x > goal {
+            return col;
+        }
+    }
+    // Only the last segment of a line can hold the cursor past its final character
+    if last || segment.end == segment.start {
+        segment.end
+    } else {
+        segment.end - 1
+    }
+}
+
+// A screen row position: document row and segment within it
+pub type VisualPos = (usize, usize);
+
+pub fn line_segments(document: &Document, row: usize, options: &WrapOptions) -> Vec<Segment> {
+    let line = document.row(row).map(|r| r.to_string()).unwrap_or_default();
+    segments(&line, options)
+}
+
+// Moves a screen row position by `delta` rows, stopping at either end of the document.
+pub fn step(document: &Document, pos: VisualPos, delta: isize, options: &WrapOptions) -> VisualPos {
+    let (mut row, mut sub) = pos;
+    let last = document.rows().saturating_sub(1);
+    if delta >= 0 {
+        for _ in 0..delta {
+            if sub + 1 < line_segments(document, row, options).len() {
+                sub += 1;
+            } else if row < last {
+                row += 1;
+                sub = 0;
+            } else {
+                break;
+            }
+        }
+    } else {
+        for _ in 0..delta.unsigned_abs() {
+            if sub > 0 {
+                sub -= 1;
+            } else if row > 0 {
+                row -= 1;
+                sub = line_segments(document, row, options).len() - 1;
+            } else {
+                break;
+            }
+        }
+    }
+    (row, sub)
+}
+
+// Screen rows from `from` to `to`, counting at most `limit`.
+pub fn distance(document: &Document, from: VisualPos, to: VisualPos, limit: usize, options: &WrapOptions) -> usize {
+    let mut pos = from;
+    let mut count = 0;
+    while pos < to && count < limit {
+        let next = step(document, pos, 1, options);
+        if next == pos {
+            break;
+        }
+        pos = next;
+        count += 1;
+    }
+    count
+}
+
+// Sets of options measured for at once, about one per window width
+const MAX_LAYOUTS: usize = 4;
+
+// The screen rows each line takes, kept per set of wrap options so the scrollbar does
+// not wrap the whole document every frame. Rows are measured when first asked about
+// and forgotten when they are edited.
+#[derive(Default)]
+pub struct Heights {
+    layouts: Vec<(WrapOptions, Vec<Option<usize>>)>,
+}
+
+impl Heights {
+    // Forgets the `removed` rows at `row`, which are now `inserted` rows.
+    pub fn replaced(&mut self, row: usize, removed: usize, inserted: usize) {
+        for (_, heights) in &mut self.layouts {
+            let end = (row + removed).min(heights.len());
+            let start = row.min(end);
+            heights.splice(start..end, std::iter::repeat_n(None, inserted));
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.layouts.clear();
+    }
+
+    // Screen rows taken by the whole document, and by the rows above `row`
+    pub fn measure(&mut self, document: &Document, options: &WrapOptions, row: usize) -> (usize, usize) {
+        let index = match self.layouts.iter().position(|(measured, _)| measured == options) {
+            Some(index) => index,
+            None => {
+                if self.layouts.len() == MAX_LAYOUTS {
+                    self.layouts.remove(0);
+                }
+                self.layouts.push((options.clone(), Vec::new()));
+                self.layouts.len() - 1
+            },
+        };
+        let heights = &mut self.layouts[index].1;
+        heights.resize(document.rows(), None);
+        let mut total = 0;
+        let mut above = 0;
+        for (idx, height) in heights.iter_mut().enumerate() {
+            let height = *height.get_or_insert_with(|| line_segments(document, idx, options).len());
+            if idx < row {
+                above += height;
+            }
+            total += height;
+        }
+        (total, above)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(width: usize, words: bool) -> WrapOptions {
+        WrapOptions { width, tab_width: 4, words, indent: false, marker: String::new() }
+    }
+
+    fn ranges(line: &str, options: &WrapOptions) -> Vec<(usize, usize, usize)> {
+        segments(line, options).iter().map(|segment| (segment.start, segment.end, segment.indent)).collect()
+    }
+
+    #[test]
+    fn breaks_at_the_width_or_after_words() {
+        assert_eq!(ranges("abcdefghij", &options(5, false)), [(0, 5, 0), (5, 10, 0)]);
+        assert_eq!(ranges("hello world foo", &options(8, false)), [(0, 8, 0), (8, 15, 0)]);
+        assert_eq!(ranges("hello world foo", &options(8, true)), [(0, 6, 0), (6, 12, 0), (12, 15, 0)]);
+        // A word longer than the row is broken where it has to be
+        assert_eq!(ranges("abcdefghij", &options(4, true)), [(0, 4, 0), (4, 8, 0), (8, 10, 0)]);
+        assert_eq!(ranges("", &options(4, true)), [(0, 0, 0)]);
+    }
+
+    #[test]
+    fn counts_wide_characters_and_tabs() {
+        assert_eq!(ranges("日本語", &options(5, false)), [(0, 2, 0), (2, 3, 0)]);
+        // Each character is wider than the row, which still shows one
+        assert_eq!(ranges("日本", &options(1, false)), [(0, 1, 0), (1, 2, 0)]);
+        assert_eq!(ranges("\tab", &options(5, false)), [(0, 2, 0), (2, 3, 0)]);
+    }
+
+    #[test]
+    fn indents_continuation_rows() {
+        let options = WrapOptions { indent: true, marker: String::from("↪"), ..options(6, false) };
+        let line = "  abcdef";
+        let segments = segments(line, &options);
+        assert_eq!(segments, [Segment { start: 0, end: 6, indent: 0 }, Segment { start: 6, end: 8, indent: 3 }]);
+        assert_eq!(render_segment(line, &segments[0], &options), "  abcd");
+        assert_eq!(render_segment(line, &segments[1], &options), "  ↪ef");
+        assert_eq!(column_in_segment(line, &segments[1], 7, 4), 4);
+        // The prefix never takes more than half the row
+        assert_eq!(ranges("        abcdefgh", &options)[1], (6, 9, 3));
+    }
+
+    #[test]
+    fn finds_characters_on_segments() {
+        let line = "abcdefghij";
+        let segments = segments(line, &options(4, false));
+        assert_eq!(segment_of(&segments, 3), 0);
+        assert_eq!(segment_of(&segments, 4), 1);
+        assert_eq!(segment_of(&segments, 10), 2);
+        assert_eq!(col_at_column(line, &segments[0], 2, false, 4), 2);
+        // Past the end of a segment that is not the last stays on its last character
+        assert_eq!(col_at_column(line, &segments[0], 9, false, 4), 3);
+        assert_eq!(col_at_column(line, &segments[2], 9, true, 4), 10);
+    }
+
+    #[test]
+    fn steps_over_screen_rows() {
+        let document = Document::from_text("test.txt", "abcdefghij\nab\nabcde\n");
+        let options = options(4, false);
+        assert_eq!(step(&document, (0, 0), 3, &options), (1, 0));
+        assert_eq!(step(&document, (1, 0), -2, &options), (0, 1));
+        assert_eq!(step(&document, (2, 0), 5, &options), (2, 1));
+        assert_eq!(step(&document, (0, 1), -3, &options), (0, 0));
+        assert_eq!(distance(&document, (0, 1), (2, 1), 10, &options), 4);
+        assert_eq!(distance(&document, (0, 1), (2, 1), 2, &options), 2);
+    }
+
+    #[test]
+    fn measures_rows_until_they_are_edited() {
+        let narrow = options(4, false);
+        let mut heights = Heights::default();
+        let document = Document::from_text("test.txt", "abcdefghij\nab\nabcde\n");
+        assert_eq!(heights.measure(&document, &narrow, 2), (6, 4));
+        // Rows are kept until told they changed
+        let edited = Document::from_text("test.txt", "abcdefghij\nabcdefghijkl\nabcde\n");
+        assert_eq!(heights.measure(&edited, &narrow, 2), (6, 4));
+        heights.replaced(1, 1, 1);
+        assert_eq!(heights.measure(&edited, &narrow, 2), (8, 6));
+        // One row split in two at the top
+        let split = Document::from_text("test.txt", "ab\ncd\nabcdefghijkl\nabcde\n");
+        heights.replaced(0, 1, 2);
+        assert_eq!(heights.measure(&split, &narrow, 3), (7, 5));
+        // Each width is measured on its own
+        assert_eq!(heights.measure(&split, &options(6, false), 3), (5, 4));
+        assert_eq!(heights.measure(&split, &narrow, 3), (7, 5));
+    }
+}
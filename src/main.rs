@@ -1,9 +1,19 @@
-use std::thread;
-use std::time::Duration;
-
 mod editor;
 
 fn main() {
-    let mut my_editor = editor::editor::Editor::new().unwrap();
+    let args = match editor::cli::parse(std::env::args().skip(1)) {
+        Ok(args) => args,
+        Err(msg) => {
+            eprintln!("deft-txt: {}\n{}", msg, editor::cli::USAGE);
+            std::process::exit(2);
+        },
+    };
+    let mut my_editor = match editor::editor::Editor::new(args) {
+        Ok(editor) => editor,
+        Err(err) => {
+            eprintln!("deft-txt: {}", err);
+            std::process::exit(1);
+        },
+    };
     my_editor.run();
 }